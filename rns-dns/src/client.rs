@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use chrono::Utc;
+use ed25519_dalek::VerifyingKey;
 use reticulum::iface::tcp_client::TcpClient;
 use tokio::sync::{self, Mutex};
 use tokio::time;
@@ -12,12 +14,36 @@ use reticulum::iface::udp::UdpInterface;
 use reticulum::hash::AddressHash;
 use reticulum::transport::{Transport, TransportConfig};
 
+use crate::resolver::{DenialAnswer, EntryAnswer, zone_key};
+use crate::resolver::cache::{VerifierCache, decode_status};
+use crate::resolver::envelope::{AnswerError, PendingQueries};
+use crate::resolver::negative::NegativeCache;
+use crate::resolver::transparency::{HeadCheck, TreeHeadMonitor, parse_consistency};
+use crate::resolver::trust::{TrustPolicy, TrustReason};
+use crate::server::announce::{Capabilities, PROTOCOL_VERSION};
+use crate::server::delegation::TrustAnchor;
 use crate::types;
+use crate::utilites::codec::{Reader, to_hex};
+use crate::utilites::shutdown::{ExitStatus, SHUTDOWN_DEADLINE, Shutdown, poll_until};
 
+/// How often the client checks its verifier cache for stale records.
+const VERIFIER_REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// The most destinations asked about in a single `VERIFIER` query.
+const VERIFIER_QUERY_BATCH: usize = 16;
+/// How often the client checks for outstanding queries while shutting down.
+const SETTLE_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// A client that links to the first dns server it hears of and queries it.
+///
+/// On SIGINT or SIGTERM the client stops sending queries, waits up to
+/// `SHUTDOWN_DEADLINE` for the answers to the queries it already sent, saves the
+/// verifier cache and closes its link.
 pub async fn client(
     node_settings: types::NodeSettings,
     destination_settings: types::DestinationConfig,
-) {
+    verifier_cache: VerifierCache,
+    anchors: Vec<TrustAnchor>,
+) -> ExitStatus {
     log::info!("Reticulum test client");
     let private_id = node_settings.private_identity.extract();
     let transport = Transport::new(TransportConfig::new("client", &private_id, false));
@@ -57,17 +83,61 @@ pub async fn client(
         log::info!("New Node address registered: {}", address_hash);
     }
     let pings = Arc::new(Mutex::new(vec![]));
+    let trust_policy = TrustPolicy::default();
+    // the verifier records known to this client, used to validate signings
+    let verifiers = Arc::new(Mutex::new(verifier_cache));
     let mut announce_recv = transport.recv_announces().await;
     let current_link: Arc<Mutex<Option<Arc<Mutex<Link>>>>> = Arc::new(Mutex::new(None));
+    // the key of the server the current link leads to, it signs verifier status answers
+    let server_key: Arc<Mutex<Option<VerifyingKey>>> = Arc::new(Mutex::new(None));
+    // the tree heads of the transparency log of that server
+    let monitor = Arc::new(Mutex::new(TreeHeadMonitor::default()));
+    // the names the server proved to be missing
+    let negatives = Arc::new(Mutex::new(NegativeCache::default()));
+    // the queries that still wait for their answer
+    let pending = Arc::new(Mutex::new(PendingQueries::default()));
+    let shutdown = Shutdown::default();
+    let request = async |request: String| {
+        if shutdown.is_stopping() {
+            return;
+        }
+        let Some(link) = current_link.lock().await.clone() else {
+            return;
+        };
+        let request = pending.lock().await.prepare(&request, Utc::now());
+        let packet = link.lock().await.data_packet(request.as_bytes());
+        if let Ok(packet) = packet {
+            transport.send_packet(packet).await;
+        }
+    };
+    let query_verifiers = async |destinations: Vec<AddressHash>| {
+        for batch in destinations.chunks(VERIFIER_QUERY_BATCH) {
+            let batch: Vec<String> = batch.iter().map(|d| to_hex(d.as_slice())).collect();
+            request(format!("VERIFIER {}", batch.join(" "))).await;
+        }
+    };
     let mut link_loop = async || {
         while let Ok(announce) = announce_recv.recv().await {
             let destination = announce.destination.lock().await;
             log::trace!("GOT ANNOUNCE: {}", destination.desc.address_hash);
+            // only servers that prove they speak our protocol are linked to, other
+            // nodes announce in the same application space
+            let mut reader = Reader::new(announce.app_data.as_slice());
+            let Ok(capabilities) = Capabilities::decode(&mut reader) else {
+                continue;
+            };
+            if !capabilities.verify(&destination.desc.identity.verifying_key)
+                || !capabilities.supports(PROTOCOL_VERSION)
+            {
+                log::trace!("IGNORED ANNOUNCE: {}", destination.desc.address_hash);
+                continue;
+            }
             let mut current_link = current_link.lock().await;
             if current_link.is_none() {
                 let link = transport.link(destination.desc).await;
                 log::trace!("SET LINK: {}", link.lock().await.id());
                 *current_link = Some(link.clone());
+                *server_key.lock().await = Some(destination.desc.identity.verifying_key);
             }
             drop(current_link);
         }
@@ -80,6 +150,117 @@ pub async fn client(
                     LinkEvent::Data(payload) => {
                         let payload = str::from_utf8(payload.as_slice()).unwrap();
                         log::trace!("{}", payload);
+                        // only answers that are bound to a query of this client are acted upon
+                        let Some(answer) = payload.strip_prefix("ANSWER ") else {
+                            continue;
+                        };
+                        let Some(key) = *server_key.lock().await else {
+                            continue;
+                        };
+                        let payload = match pending.lock().await.accept(answer, &key, Utc::now()) {
+                            Ok(response) => response,
+                            // the same answer arrived over another path
+                            Err(AnswerError::Duplicate) => continue,
+                            Err(e) => {
+                                log::warn!("dropped answer: {e:?}");
+                                continue;
+                            }
+                        };
+                        let payload = payload.as_str();
+                        let answer = payload.strip_prefix("ENTRY ").and_then(EntryAnswer::parse);
+                        if let Some(EntryAnswer { entry, chain, proof }) = answer {
+                            negatives.lock().await.invalidate(entry.name());
+                            if let Some(chain) = &chain
+                                && !anchors.is_empty()
+                            {
+                                match chain.validate(&anchors, &entry, Utc::now()) {
+                                    Ok(()) => log::info!("{} chain of trust valid", entry.name()),
+                                    Err(e) => log::warn!("{} chain of trust {e:?}", entry.name()),
+                                }
+                            }
+                            if let Some(proof) = proof
+                                && let Some(server_key) = *server_key.lock().await
+                            {
+                                let key = zone_key(chain.as_ref(), &server_key);
+                                if !proof.verify(&entry, &key) {
+                                    log::warn!("{} is not in the transparency log", entry.name());
+                                }
+                                // consistency proofs are only asked for the log of the
+                                // primary zone, other zones keep logs of their own
+                                let check = match key == server_key {
+                                    true => monitor.lock().await.observe(&proof.head, &key),
+                                    false => HeadCheck::Known,
+                                };
+                                match check {
+                                    HeadCheck::NeedsProof(first, second) => {
+                                        request(format!("CONSISTENCY {first} {second}")).await
+                                    }
+                                    HeadCheck::Equivocation => log::error!(
+                                        "server signed two trees of size {}",
+                                        proof.head.tree_size
+                                    ),
+                                    HeadCheck::InvalidSignature => {
+                                        log::warn!("tree head is not signed by the server")
+                                    }
+                                    HeadCheck::Known => {}
+                                }
+                            }
+                            let verifiers = verifiers.lock().await;
+                            let verdict = trust_policy.evaluate(&entry, &*verifiers, Utc::now());
+                            drop(verifiers);
+                            log::info!(
+                                "{} {}",
+                                entry.name(),
+                                if verdict.accepted { "trusted" } else { "untrusted" }
+                            );
+                            let mut unknown = Vec::new();
+                            for reason in &verdict.reasons {
+                                log::trace!("  {reason}");
+                                if let TrustReason::UnknownVerifier(destination) = reason {
+                                    unknown.push(*destination);
+                                }
+                            }
+                            // ask the server about verifiers this client does not know
+                            query_verifiers(unknown).await;
+                        }
+                        let denial = payload
+                            .strip_prefix("NXNAME ")
+                            .and_then(DenialAnswer::parse);
+                        if let Some(DenialAnswer { denial, chain }) = denial
+                            && let Some(server_key) = *server_key.lock().await
+                        {
+                            let name = denial.name.clone();
+                            let key = zone_key(chain.as_ref(), &server_key);
+                            if negatives.lock().await.insert(denial, &key, Utc::now()) {
+                                log::info!("{name} does not exist");
+                            } else {
+                                log::warn!("dropped invalid denial of {name}");
+                            }
+                        }
+                        let consistency = payload
+                            .strip_prefix("CONSISTENCY ")
+                            .and_then(parse_consistency);
+                        if let Some((first, second, proof)) = consistency {
+                            let mut monitor = monitor.lock().await;
+                            let check = monitor.check_consistency(first, second, &proof);
+                            if check == HeadCheck::Equivocation {
+                                log::error!("tree of size {second} does not extend size {first}");
+                            }
+                        }
+                        if let Some(payload) = payload.strip_prefix("STATUS ")
+                            && let Some(server_key) = *server_key.lock().await
+                        {
+                            let mut verifiers = verifiers.lock().await;
+                            for status in decode_status(payload) {
+                                let destination = status.destination;
+                                if !verifiers.apply(status, &server_key) {
+                                    log::warn!("dropped unsigned status of verifier {destination}");
+                                }
+                            }
+                            if let Err(e) = verifiers.save() {
+                                log::error!("failed to save the verifier cache: {e}");
+                            }
+                        }
                         // log::trace!(
                         //     "OUT LINK PAYLOAD {} ({}): {}",
                         //     link_event.address_hash,
@@ -157,11 +338,23 @@ pub async fn client(
         }
         log::info!("IN LINK LOOP EXIT");
     };
+    // refresh the verifier records that are too old to be relied upon
+    let refresh_loop = async || loop {
+        time::sleep(VERIFIER_REFRESH_INTERVAL).await;
+        let stale = verifiers.lock().await.stale(Utc::now());
+        if !stale.is_empty() {
+            log::trace!("REFRESH {} VERIFIERS", stale.len());
+            query_verifiers(stale).await;
+        }
+    };
     // send a packet
     let ping_loop = async || {
         let mut counter = 0;
         loop {
-            if let Some(current_link) = current_link.lock().await.as_mut() {
+            // pings are sent as queries so that copies arriving over several paths
+            // are only answered once
+            let linked = current_link.lock().await.is_some();
+            if linked {
                 // if counter == 5 {
                 //     let mut link = current_link.lock().await;
                 //     log::info!("CLOSING LINK");
@@ -174,21 +367,48 @@ pub async fn client(
                 // }
                 log::trace!("SEND PING {counter}");
                 pings.lock().await.push(counter);
-                let link = current_link.lock().await;
-                let packet = link
-                    .data_packet(format!("ping {counter}").as_bytes())
-                    .unwrap();
-                drop(link);
-                transport.send_packet(packet).await;
+                request(format!("PING {counter}")).await;
                 counter += 1;
             }
             time::sleep(time::Duration::from_secs(2)).await;
         }
     };
-    tokio::select! {
-      _ = link_loop() => log::info!("link loop exited"),
-      _ = out_event_loop() => log::info!("out event loop exited"),
-      _ = in_event_loop() => log::info!("in event loop exited"),
-      _ = ping_loop() => log::info!("ping loop exited")
+    // the answers to the queries already sent are still processed while waiting
+    let shutdown_loop = async || {
+        let signal = shutdown.requested().await;
+        log::info!("received {signal:?}, shutting down");
+        let settled = poll_until(SHUTDOWN_DEADLINE, SETTLE_INTERVAL, async || {
+            pending.lock().await.is_settled(Utc::now())
+        })
+        .await;
+        match settled {
+            true => ExitStatus::Clean,
+            false => {
+                log::warn!("abandoned unanswered queries at the shutdown deadline");
+                ExitStatus::DeadlineExceeded
+            }
+        }
+    };
+
+    let status = tokio::select! {
+      _ = link_loop() => { log::info!("link loop exited"); ExitStatus::Unexpected },
+      _ = out_event_loop() => { log::info!("out event loop exited"); ExitStatus::Unexpected },
+      _ = in_event_loop() => { log::info!("in event loop exited"); ExitStatus::Unexpected },
+      _ = ping_loop() => { log::info!("ping loop exited"); ExitStatus::Unexpected },
+      _ = refresh_loop() => { log::info!("refresh loop exited"); ExitStatus::Unexpected },
+      status = shutdown_loop() => status,
+    };
+
+    let flushed = match verifiers.lock().await.save() {
+        Ok(()) => ExitStatus::Clean,
+        Err(e) => {
+            log::error!("failed to save the verifier cache: {e}");
+            ExitStatus::FlushFailed
+        }
+    };
+    if let Some(link) = current_link.lock().await.take() {
+        link.lock().await.close();
     }
+    log::info!("client stopped");
+    flushed.worse(status)
 }
@@ -1,15 +1,23 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use clap::{Arg, ArgAction, ArgGroup};
 use colored;
 
+use crate::server::announce::AnnounceConfig;
+use crate::server::policy::EntryPolicy;
+use crate::server::ratelimit::{OverLimit, RateLimitConfig};
 use crate::types::{Connection, NodeSettings};
 
 mod tui;
 
 mod client;
+mod resolver;
 mod router;
 mod server;
 mod types;
 mod utilites;
+mod verifier;
 
 #[tokio::main]
 async fn main() {
@@ -42,6 +50,13 @@ async fn main() {
                 .conflicts_with("router")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("verifier-cache")
+                .long("verifier-cache")
+                .value_name("FILE")
+                .help("The file the test client keeps its verifier records in")
+                .requires("client"),
+        )
         .arg(
             Arg::new("router")
                 .short('r')
@@ -59,6 +74,14 @@ async fn main() {
                 .requires("cli")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("verifier")
+                .short('e')
+                .long("verifier")
+                .help("Starts a verifier which vouches for dns entries on request")
+                .requires("cli")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("options")
                 .short('o')
@@ -88,6 +111,211 @@ async fn main() {
                 .help("experimental feature")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("database")
+                .long("database")
+                .value_name("FILE")
+                .help("The database file of the dns server or of the verifier commands"),
+        )
+        .arg(
+            Arg::new("verifier-add")
+                .long("verifier-add")
+                .value_names(["NAME", "DESTINATION", "TRUST_LEVEL", "PUBLIC_KEY"])
+                .num_args(4)
+                .help("Adds a verifier to the database")
+                .requires("database")
+                .conflicts_with("cli"),
+        )
+        .arg(
+            Arg::new("verifier-update")
+                .long("verifier-update")
+                .value_names(["NAME", "DESTINATION", "TRUST_LEVEL", "PUBLIC_KEY"])
+                .num_args(4)
+                .help("Replaces a verifier in the database")
+                .requires("database")
+                .conflicts_with("cli"),
+        )
+        .arg(
+            Arg::new("verifier-remove")
+                .long("verifier-remove")
+                .value_name("DESTINATION")
+                .help("Removes a verifier from the database")
+                .requires("database")
+                .conflicts_with("cli"),
+        )
+        .arg(
+            Arg::new("verifier-revoke")
+                .long("verifier-revoke")
+                .value_name("DESTINATION")
+                .help("Revokes a verifier and drops all of its signings")
+                .requires("database")
+                .conflicts_with("cli"),
+        )
+        .arg(
+            Arg::new("verifier-list")
+                .long("verifier-list")
+                .help("Lists the verifiers of the database")
+                .requires("database")
+                .conflicts_with("cli")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("delegate")
+                .long("delegate")
+                .value_names(["SUFFIX", "DESTINATION", "PUBLIC_KEY"])
+                .num_args(3)
+                .help("Delegates a suffix to a child server and writes its trust chain")
+                .requires("chain-out")
+                .conflicts_with("cli"),
+        )
+        .arg(
+            Arg::new("parent-chain")
+                .long("parent-chain")
+                .value_name("FILE")
+                .help("The trust chain of this server, omit it to delegate as a root")
+                .requires("delegate"),
+        )
+        .arg(
+            Arg::new("chain-out")
+                .long("chain-out")
+                .value_name("FILE")
+                .help("The file the trust chain of the child server is written to")
+                .requires("delegate"),
+        )
+        .arg(
+            Arg::new("chain")
+                .long("chain")
+                .value_name("FILE")
+                .help("The trust chain the dns server attaches to its answers")
+                .requires("dns"),
+        )
+        .arg(
+            Arg::new("trust-anchor")
+                .long("trust-anchor")
+                .value_name("PUBLIC_KEY")
+                .help("A root key the test client or the resolver validates trust chains against")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("acl")
+                .long("acl")
+                .value_name("FILE")
+                .help("The rules which identities may register names under which suffixes")
+                .requires("dns"),
+        )
+        .arg(
+            Arg::new("max-names")
+                .long("max-names")
+                .value_name("COUNT")
+                .help("The most names a single owner key may hold in the primary zone")
+                .requires("dns")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("min-update-minutes")
+                .long("min-update-minutes")
+                .value_name("MINUTES")
+                .help("The shortest time between two updates of a name in the primary zone")
+                .requires("dns")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("max-destinations")
+                .long("max-destinations")
+                .value_name("COUNT")
+                .help("The most destinations an entry of the primary zone may point to")
+                .requires("dns")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("zones")
+                .long("zones")
+                .value_name("FILE")
+                .help("The zones hosted next to the primary one, each with its own identity")
+                .requires("dns"),
+        )
+        .arg(
+            Arg::new("secondary")
+                .long("secondary")
+                .value_names(["DESTINATION", "PUBLIC_KEY", "ZONE"])
+                .num_args(2..=3)
+                .help("Transfers a zone from a primary server, the primary zone without ZONE")
+                .requires("dns"),
+        )
+        .arg(
+            Arg::new("notify")
+                .long("notify")
+                .value_name("DESTINATION")
+                .help("A secondary server that may transfer the zones and is told when one changed")
+                .requires("dns")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("peer")
+                .long("peer")
+                .value_name("DESTINATION")
+                .help("A server that accepts registrations as well, entries are replicated to it")
+                .requires("dns")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("resolver")
+                .long("resolver")
+                .help("Forwards lookups of names the server is not authoritative for")
+                .requires("dns")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("upstream")
+                .long("upstream")
+                .value_name("DESTINATION")
+                .help("A server the resolver forwards lookups to")
+                .requires("resolver")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("announce-interval")
+                .long("announce-interval")
+                .value_name("SECONDS")
+                .help("The longest interval between two announces of the server")
+                .requires("dns")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("rate-limit-drop")
+                .long("rate-limit-drop")
+                .help("Drops queries over the rate limit instead of answering them")
+                .requires("dns")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("check-db")
+                .long("check-db")
+                .value_name("FILE")
+                .help("Checks the integrity of a database file and prints a JSON report")
+                .conflicts_with_all(["cli", "audit-log"]),
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .value_name("FILE")
+                .help("Prints the entries of an audit log, filtered by --name and --key")
+                .conflicts_with("cli"),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .value_name("DOMAIN")
+                .help("Only show audit entries for this domain name")
+                .requires("audit-log"),
+        )
+        .arg(
+            Arg::new("key")
+                .long("key")
+                .value_name("HEX")
+                .help("Only show audit entries made by this requester, hex or unidentified")
+                .requires("audit-log"),
+        )
         .group(
             ArgGroup::new("router or dns")
                 .args(["router", "dns"])
@@ -101,6 +329,79 @@ async fn main() {
     // if args.get_flag("experimental2") {
     //     client::client().await;
     // }
+    if let Some(path) = args.get_one::<String>("check-db") {
+        let raw = match server::server::DnsDatabaseRaw::load(Path::new(path)) {
+            Ok(raw) => raw,
+            Err(e) => {
+                log::error!("failed to load database: {e}");
+                std::process::exit(2);
+            }
+        };
+        let report = server::integrity::check_database(&raw, chrono::Utc::now());
+        println!("{}", report.to_json());
+        std::process::exit(if report.is_ok() { 0 } else { 1 });
+    }
+
+    if let Some(values) = args.get_many::<String>("delegate") {
+        let values: Vec<String> = values.cloned().collect();
+        let result = server::admin::delegate(
+            &dns_identity().extract(),
+            &values,
+            args.get_one::<String>("parent-chain").map(Path::new),
+            Path::new(args.get_one::<String>("chain-out").unwrap()),
+        );
+        if let Err(e) = result {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = args.get_one::<String>("database")
+        && !args.get_flag("cli")
+    {
+        let path = Path::new(path);
+        let private_id = dns_identity().extract();
+        let verifier_args = |id: &str| -> Option<Vec<String>> {
+            args.get_many::<String>(id).map(|vals| vals.cloned().collect())
+        };
+
+        let result = if let Some(values) = verifier_args("verifier-add") {
+            server::admin::parse_verifier(&values)
+                .and_then(|v| server::admin::add_verifier(path, &private_id, v))
+        } else if let Some(values) = verifier_args("verifier-update") {
+            server::admin::parse_verifier(&values)
+                .and_then(|v| server::admin::update_verifier(path, &private_id, v))
+        } else if let Some(destination) = args.get_one::<String>("verifier-remove") {
+            server::admin::remove_verifier(path, &private_id, destination)
+        } else if let Some(destination) = args.get_one::<String>("verifier-revoke") {
+            server::admin::revoke_verifier(path, &private_id, destination)
+        } else if args.get_flag("verifier-list") {
+            server::admin::list_verifiers(path, &private_id);
+            Ok(())
+        } else {
+            Err("no verifier command given".to_owned())
+        };
+
+        if let Err(e) = result {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = args.get_one::<String>("audit-log") {
+        let query = server::audit::AuditQuery {
+            name: args.get_one::<String>("name").cloned(),
+            requester: args.get_one::<String>("key").cloned(),
+        };
+        if let Err(e) = server::audit::query_audit_log(Path::new(path), &query) {
+            log::error!("failed to read audit log: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if args.get_flag("cli") {
         // let options: Vec<_> = args
         //     .get_many::<String>("options")
@@ -121,7 +422,18 @@ async fn main() {
             );
             let destination_config =
                 types::DestinationConfig::new("node.config".to_owned(), "infra".to_owned());
-            client::client(node_settings, destination_config).await;
+            let verifier_cache = match args.get_one::<String>("verifier-cache") {
+                Some(path) => resolver::cache::VerifierCache::open(Path::new(path))
+                    .unwrap_or_else(|e| {
+                        log::error!("failed to open the verifier cache: {e}");
+                        std::process::exit(1);
+                    }),
+                None => resolver::cache::VerifierCache::in_memory(),
+            };
+            let anchors = trust_anchors(&args);
+            let status =
+                client::client(node_settings, destination_config, verifier_cache, anchors).await;
+            std::process::exit(status.code());
         }
 
         if args.get_flag("router") {
@@ -138,7 +450,24 @@ async fn main() {
             );
             let destination_config =
                 types::DestinationConfig::new("node.config".to_owned(), "infra".to_owned());
-            router::start_router(node_settings, destination_config).await;
+            let status = router::start_router(node_settings, destination_config).await;
+            std::process::exit(status.code());
+        }
+
+        if args.get_flag("verifier") {
+            let tcp = Connection::new_tcp("127.0.0.1".to_string(), 53317);
+            let node_settings = NodeSettings::new(
+                vec![tcp],
+                types::PrivateIdentity::FromString("verifier-test".to_owned()),
+            );
+            let destination_config =
+                types::DestinationConfig::new("verifier".to_owned(), "infra".to_owned());
+            verifier::start_verifier(
+                node_settings,
+                destination_config,
+                std::sync::Arc::new(verifier::ApproveAll),
+            )
+            .await;
         }
 
         if args.get_flag("dns") {
@@ -151,14 +480,120 @@ async fn main() {
             let tcp = Connection::new_tcp("0.0.0.0".to_string(), 53317);
             let node_settings = NodeSettings::new(
                 vec![udp, tcp],
-                types::PrivateIdentity::FromString("router-test".to_owned()),
+                dns_identity(),
             );
             let destination_config =
                 types::DestinationConfig::new("node.config".to_owned(), "infra".to_owned());
-            server::node::start_server(node_settings, destination_config).await;
+            let mut server_config = types::ServerConfig::new(
+                args.get_one::<String>("database").map(PathBuf::from),
+            )
+            .with_chain(args.get_one::<String>("chain").map(PathBuf::from))
+            .with_acl(args.get_one::<String>("acl").map(PathBuf::from))
+            .with_zones(args.get_one::<String>("zones").map(PathBuf::from))
+            .with_policy(entry_policy(&args))
+            .with_rate_limit(RateLimitConfig {
+                over_limit: match args.get_flag("rate-limit-drop") {
+                    true => OverLimit::Drop,
+                    false => OverLimit::Reply,
+                },
+                ..Default::default()
+            });
+            if let Some(values) = args.get_many::<String>("secondary") {
+                let values: Vec<String> = values.cloned().collect();
+                let secondary = server::admin::parse_secondary(&values).unwrap_or_else(|e| {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                });
+                server_config = server_config.with_secondary(Some(secondary));
+            }
+            let secondaries = args
+                .get_many::<String>("notify")
+                .into_iter()
+                .flatten()
+                .map(|destination| parse_destination(destination))
+                .collect();
+            server_config = server_config.with_secondaries(secondaries);
+            let peers = args
+                .get_many::<String>("peer")
+                .into_iter()
+                .flatten()
+                .map(|destination| parse_destination(destination))
+                .collect();
+            server_config = server_config.with_peers(peers);
+            if args.get_flag("resolver") {
+                let upstreams = args
+                    .get_many::<String>("upstream")
+                    .into_iter()
+                    .flatten()
+                    .map(|destination| parse_destination(destination))
+                    .collect();
+                server_config = server_config.with_resolver(Some(types::ResolverConfig {
+                    upstreams,
+                    anchors: trust_anchors(&args),
+                }));
+            }
+            if let Some(seconds) = args.get_one::<u64>("announce-interval") {
+                let max_interval = Duration::from_secs(*seconds);
+                server_config = server_config.with_announce(AnnounceConfig {
+                    min_interval: AnnounceConfig::default().min_interval.min(max_interval),
+                    max_interval,
+                    ..Default::default()
+                });
+            }
+            let status =
+                server::node::start_server(node_settings, destination_config, server_config).await;
+            std::process::exit(status.code());
         }
     } else {
         log::info!("You have selected visual mode");
         tui::tui().await.unwrap();
     }
 }
+
+/// Parses a destination given on the command line, exits should it be invalid.
+fn parse_destination(destination: &str) -> reticulum::hash::AddressHash {
+    utilites::codec::parse_address(destination).unwrap_or_else(|| {
+        log::error!("invalid destination {destination}");
+        std::process::exit(1);
+    })
+}
+
+/// The policy of the primary zone, the zones file sets the policy of the others.
+/// Options that are not given keep their default.
+fn entry_policy(args: &clap::ArgMatches) -> EntryPolicy {
+    let mut policy = EntryPolicy::default();
+    if let Some(count) = args.get_one::<u64>("max-names") {
+        policy.max_names_per_key = *count as usize;
+    }
+    if let Some(minutes) = args.get_one::<u32>("min-update-minutes") {
+        policy.min_update_interval = chrono::TimeDelta::minutes(*minutes as i64);
+    }
+    if let Some(count) = args.get_one::<u64>("max-destinations") {
+        policy.max_destinations = *count as usize;
+    }
+    policy
+}
+
+/// The root keys given with `--trust-anchor`, each trusted for every name.
+fn trust_anchors(args: &clap::ArgMatches) -> Vec<server::delegation::TrustAnchor> {
+    args.get_many::<String>("trust-anchor")
+        .into_iter()
+        .flatten()
+        .map(|key| {
+            let key = server::admin::parse_key(key).unwrap_or_else(|e| {
+                log::error!("{e}");
+                std::process::exit(1);
+            });
+            server::delegation::TrustAnchor {
+                suffix: String::new(),
+                key,
+            }
+        })
+        .collect()
+}
+
+/// The identity of the dns server. The verifier commands act as this identity
+/// since only the server operator may change the verifier registry.
+fn dns_identity() -> types::PrivateIdentity {
+    types::PrivateIdentity::FromString("router-test".to_owned())
+}
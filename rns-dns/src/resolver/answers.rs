@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// The longest an answer of an upstream server is cached, however long its
+/// records stay valid.
+pub const MAX_ANSWER_TTL: TimeDelta = TimeDelta::minutes(10);
+
+/// The most answers the cache holds at once.
+const MAX_ANSWERS: usize = 1024;
+
+struct CachedAnswer {
+    response: String,
+    expires: DateTime<Utc>,
+}
+
+/// This is the cache of a resolver for the answers of its upstream servers.
+///
+/// # Fields
+/// `answers` - The validated responses by the request they answer.
+///
+/// # Reasoning
+///
+/// The response is kept exactly as the upstream server sent it, with the chain
+/// and the proof, so that a client can validate a cached answer just like a
+/// fresh one. Only the envelope is signed anew for every query.
+#[derive(Default)]
+pub struct AnswerCache {
+    answers: HashMap<String, CachedAnswer>,
+}
+
+impl AnswerCache {
+    /// Returns the cached response to `request` unless it expired.
+    pub fn get(&self, request: &str, now: DateTime<Utc>) -> Option<&str> {
+        self.answers
+            .get(request)
+            .filter(|cached| cached.expires > now)
+            .map(|cached| cached.response.as_str())
+    }
+
+    /// Caches a response until `expires`, at most for `MAX_ANSWER_TTL`.
+    pub fn insert(
+        &mut self,
+        request: String,
+        response: String,
+        expires: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) {
+        let expires = expires.min(now + MAX_ANSWER_TTL);
+        if expires <= now {
+            return;
+        }
+
+        self.answers.retain(|_, cached| cached.expires > now);
+        if self.answers.len() >= MAX_ANSWERS
+            && let Some(oldest) = self
+                .answers
+                .iter()
+                .min_by_key(|(_, cached)| cached.expires)
+                .map(|(request, _)| request.clone())
+        {
+            self.answers.remove(&oldest);
+        }
+        self.answers
+            .insert(request, CachedAnswer { response, expires });
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, TimeDelta, Utc};
+use ed25519_dalek::VerifyingKey;
+use reticulum::hash::AddressHash;
+
+use crate::resolver::trust::VerifierLookup;
+use crate::server::server::{SignedVerifierStatus, Verifier, VerifierStatus};
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+/// How long a cached verifier record is used before it is refreshed.
+pub const VERIFIER_MAX_AGE: TimeDelta = TimeDelta::hours(24);
+
+/// The first bytes of every verifier cache file.
+const CACHE_MAGIC: &[u8; 8] = b"RNSDNSVC";
+/// The version of the verifier cache file format.
+const CACHE_VERSION: u8 = 1;
+
+/// Decodes the payloads of a `STATUS` answer, one per queried destination.
+pub fn decode_status(payload: &str) -> Vec<SignedVerifierStatus> {
+    payload
+        .split_whitespace()
+        .filter_map(|encoded| URL_SAFE_NO_PAD.decode(encoded).ok())
+        .filter_map(|bytes| SignedVerifierStatus::decode(&mut Reader::new(&bytes)).ok())
+        .collect()
+}
+
+#[derive(Clone)]
+struct CachedVerifier {
+    verifier: Verifier,
+    /// The timestamp of the server answer the record was taken from.
+    fetched_at: DateTime<Utc>,
+}
+
+/// This is the verifier cache of a client.
+///
+/// # Fields
+/// `records` - The cached verifier records by destination.
+/// `path` - The file the cache is persisted to. `None` keeps it in memory only.
+/// `max_age` - How old a record may get before it counts as stale.
+///
+/// # Reasoning
+///
+/// Signings only carry the destination of their verifier, the record itself has
+/// to be looked up with a `VERIFIER` query. Verifier records rarely change so the
+/// client keeps them across restarts and only asks again once a record is stale.
+///
+/// Stale records are still used to validate signings until they are refreshed,
+/// a server that can not be reached should not make every answer untrusted. A
+/// revoked verifier is dropped as soon as the server says so.
+pub struct VerifierCache {
+    records: HashMap<AddressHash, CachedVerifier>,
+    path: Option<PathBuf>,
+    max_age: TimeDelta,
+}
+
+impl VerifierCache {
+    /// Creates an empty cache which is never written to disk.
+    pub fn in_memory() -> Self {
+        Self {
+            records: HashMap::new(),
+            path: None,
+            max_age: VERIFIER_MAX_AGE,
+        }
+    }
+
+    /// Opens the cache at `path`. A missing file results in an empty cache.
+    ///
+    /// # Errors
+    ///
+    /// Fails should the file exist but be unreadable or malformed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut cache = Self::in_memory();
+        cache.path = Some(path.to_path_buf());
+        if path.exists() {
+            cache.records = Self::decode(&fs::read(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+        }
+        Ok(cache)
+    }
+
+    pub fn with_max_age(mut self, max_age: TimeDelta) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn get(&self, destination: &AddressHash) -> Option<&Verifier> {
+        self.records.get(destination).map(|c| &c.verifier)
+    }
+
+    /// Returns the destinations whose records are older than the maximum age.
+    pub fn stale(&self, now: DateTime<Utc>) -> Vec<AddressHash> {
+        self.records
+            .iter()
+            .filter(|(_, c)| now - c.fetched_at > self.max_age)
+            .map(|(destination, _)| *destination)
+            .collect()
+    }
+
+    /// Applies a verifier status answer of the server.
+    ///
+    /// # Behaviour
+    ///
+    /// Active verifiers are inserted or replaced, which also picks up a changed
+    /// trust level or key. Revoked and unknown verifiers are dropped so that
+    /// their signings stop counting. Answers older than the cached record are
+    /// ignored so that a delayed answer can not replace a newer record.
+    ///
+    /// Returns `false` and leaves the cache untouched should the answer not be
+    /// signed by `server_key`.
+    pub fn apply(&mut self, status: SignedVerifierStatus, server_key: &VerifyingKey) -> bool {
+        if !status.verify(server_key) {
+            return false;
+        }
+        let outdated = self
+            .records
+            .get(&status.destination)
+            .is_some_and(|c| c.fetched_at > status.timestamp);
+        if outdated {
+            return true;
+        }
+
+        match status.status {
+            VerifierStatus::Active(verifier) => {
+                self.records.insert(
+                    status.destination,
+                    CachedVerifier {
+                        verifier,
+                        fetched_at: status.timestamp,
+                    },
+                );
+            }
+            VerifierStatus::Revoked(_) | VerifierStatus::Unknown => {
+                self.records.remove(&status.destination);
+            }
+        }
+        true
+    }
+
+    /// Writes the cache to its file, should it have one.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.encode())?;
+        fs::rename(tmp, path)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_fixed(CACHE_MAGIC).put_u8(CACHE_VERSION);
+        writer.put_u32(self.records.len() as u32);
+        for cached in self.records.values() {
+            cached.verifier.encode_into(&mut writer);
+            writer.put_time(&cached.fetched_at);
+        }
+        writer.finish()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<HashMap<AddressHash, CachedVerifier>, CodecError> {
+        let mut reader = Reader::new(bytes);
+        if reader.get_fixed(CACHE_MAGIC.len())? != CACHE_MAGIC
+            || reader.get_u8()? != CACHE_VERSION
+        {
+            return Err(CodecError::InvalidData);
+        }
+        let mut records = HashMap::new();
+        for _ in 0..reader.get_u32()? {
+            let verifier = Verifier::decode(&mut reader)?;
+            let fetched_at = reader.get_time()?;
+            records.insert(
+                *verifier.destination(),
+                CachedVerifier {
+                    verifier,
+                    fetched_at,
+                },
+            );
+        }
+        Ok(records)
+    }
+}
+
+impl VerifierLookup for VerifierCache {
+    fn lookup_verifier(&self, destination: &AddressHash) -> Option<&Verifier> {
+        self.get(destination)
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, TimeDelta, Utc};
+use ed25519_dalek::VerifyingKey;
+use rand_core::{OsRng, RngCore};
+
+use crate::server::envelope::{NONCE_LENGTH, Query, QueryFlags, SignedAnswer, request_digest};
+use crate::utilites::codec::Reader;
+use crate::utilites::dedup::DuplicateFilter;
+
+/// How long the client waits for the answer to a query.
+pub const QUERY_TIMEOUT: TimeDelta = TimeDelta::seconds(30);
+
+/// The most queries that may be outstanding at once. The oldest one is given up
+/// on should another be sent.
+const MAX_OUTSTANDING: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnswerError {
+    /// The answer could not be decoded.
+    Malformed,
+    /// The answer is not signed by the server.
+    InvalidSignature,
+    /// There is no outstanding query with the id of the answer.
+    Unsolicited,
+    /// The query was already answered, the answer arrived over another path.
+    Duplicate,
+    /// The nonce or the request of the answer do not match the query.
+    Mismatch,
+    /// The server answered outside of the time the query was outstanding.
+    Stale,
+}
+
+struct Outstanding {
+    nonce: [u8; NONCE_LENGTH],
+    request: [u8; 32],
+    sent_at: DateTime<Utc>,
+}
+
+/// This keeps track of the queries a client is waiting on.
+///
+/// # Fields
+/// `outstanding` - The queries that were sent but not yet answered, by id.
+/// `next_id` - The id the next query is sent with.
+/// `answered` - The queries answered recently, by id and nonce.
+///
+/// # Reasoning
+///
+/// Every answer has to be bound to a query that is still outstanding, an answer
+/// is taken off the list once it arrived so that a replay of it is rejected.
+/// With redundant paths the same answer arrives more than once, the copies are
+/// told apart from replays so that they can be dropped quietly.
+#[derive(Default)]
+pub struct PendingQueries {
+    outstanding: HashMap<u16, Outstanding>,
+    next_id: u16,
+    answered: DuplicateFilter<(u16, [u8; NONCE_LENGTH])>,
+}
+
+impl PendingQueries {
+    /// Registers a new query and returns the request wrapped in its envelope.
+    pub fn prepare(&mut self, request: &str, now: DateTime<Utc>) -> String {
+        self.prepare_with(request, QueryFlags::default(), now).1
+    }
+
+    /// Registers a new query with the given flags and returns its id and the
+    /// request wrapped in its envelope.
+    pub fn prepare_with(
+        &mut self,
+        request: &str,
+        flags: QueryFlags,
+        now: DateTime<Utc>,
+    ) -> (u16, String) {
+        self.outstanding.retain(|_, query| now - query.sent_at <= QUERY_TIMEOUT);
+        if self.outstanding.len() >= MAX_OUTSTANDING
+            && let Some(oldest) = self
+                .outstanding
+                .iter()
+                .min_by_key(|(_, query)| query.sent_at)
+                .map(|(id, _)| *id)
+        {
+            self.outstanding.remove(&oldest);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        self.outstanding.insert(
+            id,
+            Outstanding {
+                nonce,
+                request: request_digest(request),
+                sent_at: now,
+            },
+        );
+        let query = Query {
+            id,
+            nonce,
+            flags,
+            request,
+        };
+        (id, query.format())
+    }
+
+    /// Whether every query was answered or timed out.
+    pub fn is_settled(&self, now: DateTime<Utc>) -> bool {
+        self.outstanding
+            .values()
+            .all(|query| now - query.sent_at > QUERY_TIMEOUT)
+    }
+
+    /// Verifies everything after the `ANSWER` command and returns the response
+    /// that is wrapped in it.
+    ///
+    /// # Errors
+    ///
+    /// Fails should the answer not be signed by `server_key` or not match an
+    /// outstanding query. The query stays outstanding unless the answer was
+    /// valid, a forged answer must not stop the real one from being accepted.
+    pub fn accept(
+        &mut self,
+        payload: &str,
+        server_key: &VerifyingKey,
+        now: DateTime<Utc>,
+    ) -> Result<String, AnswerError> {
+        self.accept_with_id(payload, server_key, now)
+            .map(|(_, response)| response)
+    }
+
+    /// Like `accept`, but also returns the id of the query that was answered.
+    pub fn accept_with_id(
+        &mut self,
+        payload: &str,
+        server_key: &VerifyingKey,
+        now: DateTime<Utc>,
+    ) -> Result<(u16, String), AnswerError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(payload.trim())
+            .map_err(|_| AnswerError::Malformed)?;
+        let answer =
+            SignedAnswer::decode(&mut Reader::new(&bytes)).map_err(|_| AnswerError::Malformed)?;
+        if !answer.verify(server_key) {
+            return Err(AnswerError::InvalidSignature);
+        }
+        // only answers that matched a query are remembered, otherwise anyone
+        // could fill the filter with made up ids
+        let key = (answer.id, answer.nonce);
+        let Some(query) = self.outstanding.get(&answer.id) else {
+            return match self.answered.contains(&key, Instant::now()) {
+                true => Err(AnswerError::Duplicate),
+                false => Err(AnswerError::Unsolicited),
+            };
+        };
+        if query.nonce != answer.nonce || query.request != answer.request {
+            return Err(AnswerError::Mismatch);
+        }
+        if answer.timestamp < query.sent_at - QUERY_TIMEOUT
+            || answer.timestamp > query.sent_at + QUERY_TIMEOUT
+            || now - query.sent_at > QUERY_TIMEOUT
+        {
+            return Err(AnswerError::Stale);
+        }
+        self.outstanding.remove(&answer.id);
+        self.answered.first_seen(key, Instant::now());
+        Ok((answer.id, answer.response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reticulum::hash::AddressHash;
+    use reticulum::identity::PrivateIdentity;
+
+    use super::*;
+
+    fn server() -> (PrivateIdentity, VerifyingKey) {
+        let server = PrivateIdentity::new_from_name("server");
+        let key = server.as_identity().verifying_key;
+        (server, key)
+    }
+
+    /// The answer of `server` to the query as it was sent, without `ANSWER`.
+    fn answer(server: &PrivateIdentity, sent: &str) -> String {
+        let query = Query::parse(sent).unwrap();
+        let destination = AddressHash::new([1u8; 16]);
+        let answer = SignedAnswer::sign(&query, "PONG".to_owned(), destination, server);
+        URL_SAFE_NO_PAD.encode(answer.encode())
+    }
+
+    #[test]
+    fn answer_to_the_query_is_accepted() {
+        let ((server, key), mut pending) = (server(), PendingQueries::default());
+        let (id, sent) = pending.prepare_with("PING", QueryFlags::default(), Utc::now());
+
+        let accepted = pending.accept_with_id(&answer(&server, &sent), &key, Utc::now());
+        assert_eq!(accepted, Ok((id, "PONG".to_owned())));
+        assert!(pending.is_settled(Utc::now()));
+    }
+
+    #[test]
+    fn malformed_answer_is_rejected() {
+        let ((_, key), mut pending) = (server(), PendingQueries::default());
+        pending.prepare("PING", Utc::now());
+
+        let accepted = pending.accept("not an answer", &key, Utc::now());
+        assert_eq!(accepted, Err(AnswerError::Malformed));
+    }
+
+    #[test]
+    fn answer_of_another_server_is_rejected() {
+        let ((_, key), mut pending) = (server(), PendingQueries::default());
+        let sent = pending.prepare("PING", Utc::now());
+        let forger = PrivateIdentity::new_from_name("forger");
+
+        let accepted = pending.accept(&answer(&forger, &sent), &key, Utc::now());
+        assert_eq!(accepted, Err(AnswerError::InvalidSignature));
+        assert!(!pending.is_settled(Utc::now()));
+    }
+
+    #[test]
+    fn replayed_answer_is_a_duplicate() {
+        let ((server, key), mut pending) = (server(), PendingQueries::default());
+        let answer = answer(&server, &pending.prepare("PING", Utc::now()));
+
+        assert!(pending.accept(&answer, &key, Utc::now()).is_ok());
+        let replayed = pending.accept(&answer, &key, Utc::now());
+        assert_eq!(replayed, Err(AnswerError::Duplicate));
+    }
+
+    #[test]
+    fn unsolicited_answer_is_not_remembered() {
+        let ((server, key), mut pending) = (server(), PendingQueries::default());
+        let sent = PendingQueries::default().prepare("PING", Utc::now());
+        let answer = answer(&server, &sent.replace("QUERY 0 ", "QUERY 7 "));
+
+        for _ in 0..2 {
+            let accepted = pending.accept(&answer, &key, Utc::now());
+            assert_eq!(accepted, Err(AnswerError::Unsolicited));
+        }
+    }
+
+    #[test]
+    fn answer_bound_to_another_nonce_is_a_mismatch() {
+        let ((server, key), mut pending) = (server(), PendingQueries::default());
+        let sent = pending.prepare("PING", Utc::now());
+        // the same id, but the nonce of a query sent by somebody else
+        let other = PendingQueries::default().prepare("PING", Utc::now());
+
+        let accepted = pending.accept(&answer(&server, &other), &key, Utc::now());
+        assert_eq!(accepted, Err(AnswerError::Mismatch));
+        let accepted = pending.accept(&answer(&server, &sent), &key, Utc::now());
+        assert_eq!(accepted, Ok("PONG".to_owned()));
+    }
+
+    #[test]
+    fn answer_to_another_request_is_a_mismatch() {
+        let ((server, key), mut pending) = (server(), PendingQueries::default());
+        let sent = pending.prepare("LOOKUP a.node", Utc::now());
+        let swapped = sent.replace("LOOKUP a.node", "LOOKUP b.node");
+
+        let accepted = pending.accept(&answer(&server, &swapped), &key, Utc::now());
+        assert_eq!(accepted, Err(AnswerError::Mismatch));
+    }
+
+    #[test]
+    fn answer_after_the_timeout_is_stale() {
+        let ((server, key), mut pending) = (server(), PendingQueries::default());
+        let sent = pending.prepare("PING", Utc::now());
+
+        let later = Utc::now() + QUERY_TIMEOUT + TimeDelta::seconds(1);
+        let accepted = pending.accept(&answer(&server, &sent), &key, later);
+        assert_eq!(accepted, Err(AnswerError::Stale));
+    }
+}
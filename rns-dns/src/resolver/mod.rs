@@ -0,0 +1,94 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use ed25519_dalek::VerifyingKey;
+
+use crate::server::delegation::TrustChain;
+use crate::server::denial::DenialOfExistence;
+use crate::server::server::DnsEntry;
+use crate::server::transparency::InclusionProof;
+use crate::utilites::codec::Reader;
+use crate::verifier::decode_entry;
+
+pub mod answers;
+pub mod cache;
+pub mod envelope;
+pub mod negative;
+pub mod transparency;
+pub mod trust;
+
+/// An `ENTRY` answer of the dns server.
+///
+/// # Fields
+/// `entry` - The entry that was asked for.
+/// `chain` - The delegations from a root down to the server, should it have any.
+/// `proof` - The proof that the entry is part of the transparency log.
+pub struct EntryAnswer {
+    pub entry: DnsEntry,
+    pub chain: Option<TrustChain>,
+    pub proof: Option<InclusionProof>,
+}
+
+impl EntryAnswer {
+    /// Parses everything after the `ENTRY` command. Sections that can not be
+    /// decoded are treated as missing.
+    pub fn parse(payload: &str) -> Option<Self> {
+        let mut parts = payload.split_whitespace();
+        let entry = decode_entry(parts.next()?)?;
+        let mut answer = Self {
+            entry,
+            chain: None,
+            proof: None,
+        };
+
+        while let (Some(section), Some(encoded)) = (parts.next(), parts.next()) {
+            let Ok(bytes) = URL_SAFE_NO_PAD.decode(encoded) else {
+                continue;
+            };
+            let mut reader = Reader::new(&bytes);
+            match section {
+                "CHAIN" => answer.chain = TrustChain::decode(&mut reader).ok(),
+                "PROOF" => answer.proof = InclusionProof::decode(&mut reader).ok(),
+                _ => {}
+            }
+        }
+        Some(answer)
+    }
+}
+
+/// A `NXNAME` answer of the dns server.
+///
+/// `denial` - The proof that the name is missing.
+/// `chain` - The delegations from a root down to the zone, should it have any.
+pub struct DenialAnswer {
+    pub denial: DenialOfExistence,
+    pub chain: Option<TrustChain>,
+}
+
+impl DenialAnswer {
+    /// Parses everything after the `NXNAME` command. A chain that can not be
+    /// decoded is treated as missing.
+    pub fn parse(payload: &str) -> Option<Self> {
+        let mut parts = payload.split_whitespace();
+        let bytes = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+        let denial = DenialOfExistence::decode(&mut Reader::new(&bytes)).ok()?;
+        let chain = match (parts.next(), parts.next()) {
+            (Some("CHAIN"), Some(encoded)) => URL_SAFE_NO_PAD
+                .decode(encoded)
+                .ok()
+                .and_then(|bytes| TrustChain::decode(&mut Reader::new(&bytes)).ok()),
+            _ => None,
+        };
+        Some(Self { denial, chain })
+    }
+}
+
+/// Returns the key the records of an answer are signed with.
+///
+/// A server hosting several zones signs the records of each zone with the key of
+/// that zone, which is the last key of the chain. Without a chain the records are
+/// signed with the key of the server itself.
+pub fn zone_key(chain: Option<&TrustChain>, server_key: &VerifyingKey) -> VerifyingKey {
+    chain
+        .and_then(|chain| chain.delegations().last())
+        .map_or(*server_key, |leaf| *leaf.key())
+}
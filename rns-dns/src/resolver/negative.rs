@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use ed25519_dalek::VerifyingKey;
+
+use crate::server::denial::DenialOfExistence;
+
+/// How long a denial of existence is relied upon after the server made it.
+pub const NEGATIVE_TTL: TimeDelta = TimeDelta::minutes(5);
+
+/// The most denials the cache holds at once.
+const MAX_DENIALS: usize = 256;
+
+#[derive(Clone)]
+struct CachedDenial {
+    successor: Option<String>,
+    expires: DateTime<Utc>,
+}
+
+/// This is the cache of negative answers of a client.
+///
+/// # Fields
+/// `gaps` - The gaps between two existing names that are known to be empty,
+/// keyed by the name before the gap. `None` is the start of the index.
+/// `ttl` - How long a denial is used after the server made it.
+///
+/// # Reasoning
+///
+/// A denial proves that the whole gap around the queried name is empty, so the
+/// cache keeps the gap rather than the name and answers any name within it.
+///
+/// The time is bounded since a name may be registered right after the denial
+/// was made. It is counted from the timestamp of the server rather than from
+/// the moment the answer arrived, so that a replayed denial expires no later
+/// than the original one did.
+pub struct NegativeCache {
+    gaps: BTreeMap<Option<String>, CachedDenial>,
+    ttl: TimeDelta,
+}
+
+impl Default for NegativeCache {
+    fn default() -> Self {
+        Self {
+            gaps: BTreeMap::new(),
+            ttl: NEGATIVE_TTL,
+        }
+    }
+}
+
+impl NegativeCache {
+    pub fn with_ttl(mut self, ttl: TimeDelta) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Caches a denial of the server.
+    ///
+    /// Returns `false` and leaves the cache untouched should the denial not be
+    /// signed by `server_key` or already be older than the ttl.
+    pub fn insert(
+        &mut self,
+        denial: DenialOfExistence,
+        server_key: &VerifyingKey,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let expires = denial.timestamp + self.ttl;
+        if !denial.verify(server_key) || expires <= now {
+            return false;
+        }
+
+        self.gaps.retain(|_, cached| cached.expires > now);
+        if self.gaps.len() >= MAX_DENIALS
+            && let Some(oldest) = self
+                .gaps
+                .iter()
+                .min_by_key(|(_, cached)| cached.expires)
+                .map(|(predecessor, _)| predecessor.clone())
+        {
+            self.gaps.remove(&oldest);
+        }
+        self.gaps.insert(
+            denial.predecessor,
+            CachedDenial {
+                successor: denial.successor,
+                expires,
+            },
+        );
+        true
+    }
+
+    /// Returns whether a cached denial proves that `name` does not exist.
+    pub fn is_denied(&self, name: &str, now: DateTime<Utc>) -> bool {
+        self.gap_of(name).is_some_and(|(_, cached)| cached.expires > now)
+    }
+
+    /// Drops the gap that contains `name`, e.g. because an entry for it was
+    /// received after all.
+    pub fn invalidate(&mut self, name: &str) {
+        if let Some((predecessor, _)) = self.gap_of(name) {
+            let predecessor = predecessor.clone();
+            self.gaps.remove(&predecessor);
+        }
+    }
+
+    fn gap_of(&self, name: &str) -> Option<(&Option<String>, &CachedDenial)> {
+        self.gaps
+            .range(..Some(name.to_owned()))
+            .next_back()
+            .filter(|(_, cached)| cached.successor.as_deref().is_none_or(|s| name < s))
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::VerifyingKey;
+
+use crate::server::transparency::{Hash, SignedTreeHead, decode_hashes, verify_consistency};
+use crate::utilites::codec::Reader;
+
+/// The most tree heads the monitor remembers.
+const MAX_HEADS: usize = 16;
+
+/// Parses everything after the `CONSISTENCY` command into both tree sizes and
+/// the proof.
+pub fn parse_consistency(payload: &str) -> Option<(u64, u64, Vec<Hash>)> {
+    let mut parts = payload.split_whitespace();
+    let first = parts.next()?.parse().ok()?;
+    let second = parts.next()?.parse().ok()?;
+    let bytes = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+    let proof = decode_hashes(&mut Reader::new(&bytes)).ok()?;
+    Some((first, second, proof))
+}
+
+/// What the monitor concluded from a tree head.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadCheck {
+    /// The head is already known or is the first head seen.
+    Known,
+    /// The head has to be proven consistent with a known head first. The client
+    /// should send `CONSISTENCY <first> <second>`.
+    NeedsProof(u64, u64),
+    /// The head is not signed by the server.
+    InvalidSignature,
+    /// The server signed two different trees of the same size.
+    Equivocation,
+}
+
+/// This keeps track of the tree heads of a server that a client has seen.
+///
+/// # Fields
+/// `heads` - The heads that were proven consistent with each other, by size.
+/// `pending` - The heads that still wait for a consistency proof, by size.
+///
+/// # Reasoning
+///
+/// A single head proves nothing, a malicious server can sign any tree it likes.
+/// Only by demanding proofs that every new head extends the heads seen before
+/// can a client tell that it is shown the same history as everyone else. The
+/// first head is trusted on first use.
+#[derive(Default)]
+pub struct TreeHeadMonitor {
+    heads: BTreeMap<u64, Hash>,
+    pending: HashMap<u64, Hash>,
+}
+
+impl TreeHeadMonitor {
+    /// Checks a tree head that came with an answer.
+    pub fn observe(&mut self, head: &SignedTreeHead, server_key: &VerifyingKey) -> HeadCheck {
+        if !head.verify(server_key) {
+            return HeadCheck::InvalidSignature;
+        }
+        let size = head.tree_size;
+        if let Some(root) = self.heads.get(&size).or(self.pending.get(&size)) {
+            return match *root == head.root {
+                true => HeadCheck::Known,
+                false => HeadCheck::Equivocation,
+            };
+        }
+        if self.heads.is_empty() {
+            self.remember(size, head.root);
+            return HeadCheck::Known;
+        }
+
+        // heads whose proofs never arrived are forgotten rather than kept forever
+        if self.pending.len() >= MAX_HEADS {
+            self.pending.clear();
+        }
+        self.pending.insert(size, head.root);
+        // prove against the closest known head, preferring an older one
+        match self.heads.range(..size).next_back() {
+            Some((first, _)) => HeadCheck::NeedsProof(*first, size),
+            None => {
+                let (second, _) = self.heads.range(size..).next().expect("heads is not empty");
+                HeadCheck::NeedsProof(size, *second)
+            }
+        }
+    }
+
+    /// Checks the answer to a `CONSISTENCY` query and moves the pending head to
+    /// the proven ones should it hold.
+    ///
+    /// Returns `HeadCheck::Equivocation` should the proof not hold, which means
+    /// the server rewrote its history.
+    pub fn check_consistency(&mut self, first: u64, second: u64, proof: &[Hash]) -> HeadCheck {
+        let root = |size| self.heads.get(&size).or(self.pending.get(&size)).copied();
+        let (Some(first_root), Some(second_root)) = (root(first), root(second)) else {
+            return HeadCheck::Known;
+        };
+        if !verify_consistency((first, &first_root), (second, &second_root), proof) {
+            return HeadCheck::Equivocation;
+        }
+        for size in [first, second] {
+            if let Some(root) = self.pending.remove(&size) {
+                self.remember(size, root);
+            }
+        }
+        HeadCheck::Known
+    }
+
+    fn remember(&mut self, size: u64, root: Hash) {
+        self.heads.insert(size, root);
+        while self.heads.len() > MAX_HEADS {
+            self.heads.pop_first();
+        }
+    }
+}
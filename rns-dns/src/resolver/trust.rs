@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use reticulum::hash::AddressHash;
+
+use crate::server::server::{DnsEntry, Verifier, VerifierRegistry};
+
+/// Anything that can provide the `Verifier` record for a verifier destination.
+///
+/// On the client this is the cache of verifier records, on the server it is the
+/// `VerifierRegistry`.
+pub trait VerifierLookup {
+    fn lookup_verifier(&self, destination: &AddressHash) -> Option<&Verifier>;
+}
+
+impl VerifierLookup for VerifierRegistry {
+    fn lookup_verifier(&self, destination: &AddressHash) -> Option<&Verifier> {
+        self.get_verifier(destination)
+    }
+}
+
+impl VerifierLookup for HashMap<AddressHash, Verifier> {
+    fn lookup_verifier(&self, destination: &AddressHash) -> Option<&Verifier> {
+        self.get(destination)
+    }
+}
+
+/// This is the policy a client applies to decide whether an answer is trusted.
+///
+/// # Fields
+/// `require_server_signature` - Whether a signing of trust level `0`, which is
+/// reserved for the dns server, is required.
+/// `min_verifiers` - The minimum amount of verifiers, not counting the server,
+/// that have to vouch for the entry.
+/// `max_trust_level` - The highest trust level that still counts towards
+/// `min_verifiers`. Remember that a lower level means more trust.
+/// `ignored_verifiers` - Verifiers whose signings are never counted.
+/// `accept_expired` - Whether entries past their expiry are accepted.
+///
+/// # Reasoning
+///
+/// The server only collects the signings, what they are worth is up to each
+/// client. A policy such as "the server signed it and at least two verifiers with
+/// a trust level of at most 3 vouched" is expressed as:
+///
+/// ```ignore
+/// TrustPolicy {
+///     require_server_signature: true,
+///     min_verifiers: 2,
+///     max_trust_level: 3,
+///     ..Default::default()
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TrustPolicy {
+    pub require_server_signature: bool,
+    pub min_verifiers: usize,
+    pub max_trust_level: u32,
+    pub ignored_verifiers: HashSet<AddressHash>,
+    pub accept_expired: bool,
+}
+
+impl Default for TrustPolicy {
+    fn default() -> Self {
+        Self {
+            require_server_signature: true,
+            min_verifiers: 0,
+            max_trust_level: u32::MAX,
+            ignored_verifiers: HashSet::new(),
+            accept_expired: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustReason {
+    /// The owner signature of the entry does not validate.
+    InvalidOwnerSignature,
+    /// The entry is past its expiry.
+    Expired,
+    /// The entry was signed by the server.
+    ServerSigned,
+    /// The entry was not signed by the server.
+    MissingServerSignature,
+    /// The verifier vouched for the entry and was counted.
+    CountedVerifier(AddressHash),
+    /// The verifier is ignored by the policy.
+    IgnoredVerifier(AddressHash),
+    /// The verifier is less trusted than the policy requires.
+    InsufficientTrustLevel(AddressHash),
+    /// The verifier is not known, its record has to be looked up first.
+    UnknownVerifier(AddressHash),
+    /// The signing of the verifier does not validate.
+    InvalidVerifierSignature(AddressHash),
+    /// Fewer verifiers vouched than the policy requires.
+    NotEnoughVerifiers { have: usize, need: usize },
+}
+
+impl fmt::Display for TrustReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidOwnerSignature => write!(f, "invalid owner signature"),
+            Self::Expired => write!(f, "entry expired"),
+            Self::ServerSigned => write!(f, "signed by the server"),
+            Self::MissingServerSignature => write!(f, "not signed by the server"),
+            Self::CountedVerifier(d) => write!(f, "vouched for by {d}"),
+            Self::IgnoredVerifier(d) => write!(f, "ignored verifier {d}"),
+            Self::InsufficientTrustLevel(d) => write!(f, "verifier {d} is not trusted enough"),
+            Self::UnknownVerifier(d) => write!(f, "unknown verifier {d}"),
+            Self::InvalidVerifierSignature(d) => write!(f, "invalid signature of verifier {d}"),
+            Self::NotEnoughVerifiers { have, need } => {
+                write!(f, "{have} of {need} required verifiers vouched")
+            }
+        }
+    }
+}
+
+/// The outcome of evaluating an entry against a `TrustPolicy`.
+///
+/// `reasons` contains both the reasons that led to a rejection and the ones that
+/// supported the decision, so that a user can see why an answer was accepted.
+#[derive(Debug, Clone)]
+pub struct TrustVerdict {
+    pub accepted: bool,
+    pub reasons: Vec<TrustReason>,
+}
+
+impl TrustPolicy {
+    /// Returns the `level` field of a query for this policy.
+    ///
+    /// Only the verifications within these trust levels can change the decision,
+    /// so there is no point in asking the server for any others.
+    pub fn query_level(&self) -> (u8, u8) {
+        let min = if self.require_server_signature { 0 } else { 1 };
+        let max = self.max_trust_level.min(u8::MAX as u32) as u8;
+        (min, max.max(min))
+    }
+
+    /// Evaluates the entry and its verifications.
+    ///
+    /// # Behaviour
+    ///
+    /// Signings of verifiers that are not in `verifiers` can not be validated and
+    /// are reported as `TrustReason::UnknownVerifier`, they never count.
+    pub fn evaluate(
+        &self,
+        entry: &DnsEntry,
+        verifiers: &impl VerifierLookup,
+        now: DateTime<Utc>,
+    ) -> TrustVerdict {
+        let mut reasons = Vec::new();
+        let mut rejected = false;
+
+        if !entry.verify_signature() {
+            reasons.push(TrustReason::InvalidOwnerSignature);
+            rejected = true;
+        }
+        if entry.expiry() < now && !self.accept_expired {
+            reasons.push(TrustReason::Expired);
+            rejected = true;
+        }
+
+        let mut server_signed = false;
+        let mut counted = 0;
+        for signing in entry.verifications() {
+            let destination = *signing.destination();
+            if self.ignored_verifiers.contains(&destination) {
+                reasons.push(TrustReason::IgnoredVerifier(destination));
+                continue;
+            }
+            let Some(verifier) = verifiers.lookup_verifier(&destination) else {
+                reasons.push(TrustReason::UnknownVerifier(destination));
+                continue;
+            };
+            if !signing.verify(entry, verifier) {
+                reasons.push(TrustReason::InvalidVerifierSignature(destination));
+                continue;
+            }
+
+            if verifier.trust_level() == 0 {
+                server_signed = true;
+            } else if verifier.trust_level() <= self.max_trust_level {
+                counted += 1;
+                reasons.push(TrustReason::CountedVerifier(destination));
+            } else {
+                reasons.push(TrustReason::InsufficientTrustLevel(destination));
+            }
+        }
+
+        if server_signed {
+            reasons.push(TrustReason::ServerSigned);
+        } else if self.require_server_signature {
+            reasons.push(TrustReason::MissingServerSignature);
+            rejected = true;
+        }
+        if counted < self.min_verifiers {
+            reasons.push(TrustReason::NotEnoughVerifiers {
+                have: counted,
+                need: self.min_verifiers,
+            });
+            rejected = true;
+        }
+
+        TrustVerdict {
+            accepted: !rejected,
+            reasons,
+        }
+    }
+}
@@ -1,19 +1,28 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use reticulum::iface::tcp_server::TcpServer;
+use tokio::sync::Mutex;
 use tokio::time;
 
-use reticulum::destination::link::LinkEvent;
+use reticulum::destination::link::{Link, LinkEvent};
 use reticulum::iface::udp::UdpInterface;
 use reticulum::{destination::DestinationName, hash::AddressHash};
 //use reticulum::iface::tcp_server::TcpServer;
 use reticulum::transport::{Transport, TransportConfig};
 
+use crate::server::announce::{AnnounceConfig, AnnounceSchedule};
 use crate::types::{self, Connection};
+use crate::utilites::shutdown::{ExitStatus, SHUTDOWN_DEADLINE, Shutdown};
 
 /// The router that handles routing between nodes on the local network. May be connected to other nodes.
+///
+/// On SIGINT or SIGTERM the router refuses new links and requests, answers the
+/// requests in flight within `SHUTDOWN_DEADLINE` and closes its links.
 pub async fn start_router(
     node_settings: types::NodeSettings,
     destination_settings: types::DestinationConfig,
-) {
+) -> ExitStatus {
     log::info!("Starting Reticlum Router");
 
     let private_id = node_settings.private_identity.extract();
@@ -112,12 +121,21 @@ pub async fn start_router(
         destination_settings.application_space
     );
 
-    let announce_loop = async || loop {
-        log::trace!("SEND ANNOUNCE {}", destination_hash);
-        transport.send_announce(&destination, None).await;
-        time::sleep(time::Duration::from_secs(2)).await;
+    let shutdown = Shutdown::default();
+    let announce_loop = async || {
+        let mut schedule = AnnounceSchedule::new(AnnounceConfig::default());
+        loop {
+            if !shutdown.is_stopping() {
+                log::trace!("SEND ANNOUNCE {}", destination_hash);
+                transport.send_announce(&destination, None).await;
+            }
+            time::sleep(schedule.next_delay()).await;
+        }
     };
 
+    // the links opened by other nodes, closed on shutdown
+    let in_links: Mutex<HashMap<AddressHash, Arc<Mutex<Link>>>> = Mutex::new(HashMap::new());
+
     let in_event_loop = async || {
         let mut in_link_events = transport.in_link_events();
         while let Ok(link_event) = in_link_events.recv().await {
@@ -130,6 +148,9 @@ pub async fn start_router(
                         link_event.id,
                         payload
                     );
+                    let Some(_in_flight) = shutdown.begin() else {
+                        continue;
+                    };
 
                     // link
                     let link_id = link_event.id;
@@ -149,9 +170,18 @@ pub async fn start_router(
                         "IN LINK ACTIVATED {} ({})",
                         link_event.address_hash,
                         link_event.id
-                    )
+                    );
+                    let Some(link) = transport.find_in_link(&link_event.id).await else {
+                        continue;
+                    };
+                    if shutdown.is_stopping() {
+                        link.lock().await.close();
+                        continue;
+                    }
+                    in_links.lock().await.insert(link_event.id, link);
                 }
                 LinkEvent::Closed => {
+                    in_links.lock().await.remove(&link_event.id);
                     log::trace!(
                         "IN LINK CLOSED {} ({})",
                         link_event.address_hash,
@@ -162,8 +192,25 @@ pub async fn start_router(
         }
         log::info!("IN LINK LOOP EXIT")
     };
-    tokio::select! {
-      _ = announce_loop() => log::info!("announce loop exited"),
-      _ = in_event_loop() => log::info!("in event loop exited"),
+    let shutdown_loop = async || {
+        let signal = shutdown.requested().await;
+        log::info!("received {signal:?}, shutting down");
+        match shutdown.drain(SHUTDOWN_DEADLINE).await {
+            true => ExitStatus::Clean,
+            false => ExitStatus::DeadlineExceeded,
+        }
+    };
+
+    let status = tokio::select! {
+      _ = announce_loop() => { log::info!("announce loop exited"); ExitStatus::Unexpected },
+      _ = in_event_loop() => { log::info!("in event loop exited"); ExitStatus::Unexpected },
+      status = shutdown_loop() => status,
+    };
+
+    let links: Vec<_> = in_links.lock().await.drain().map(|(_, link)| link).collect();
+    for link in links {
+        link.lock().await.close();
     }
+    log::info!("router stopped");
+    status
 }
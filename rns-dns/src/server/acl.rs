@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use reticulum::hash::AddressHash;
+
+use crate::server::delegation::is_within;
+use crate::utilites::codec::{parse_address, to_hex};
+
+/// Who may change names under a suffix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Every identity may register and change names.
+    Open,
+    /// Only the listed identities may register and change names.
+    Restricted(HashSet<AddressHash>),
+    /// Nobody may change names, they can only be looked up.
+    ReadOnly,
+}
+
+/// A single rule of the access list.
+///
+/// # Fields
+/// `suffix` - The suffix the rule applies to.
+/// `strict` - Whether the rule was written as `*.suffix` and therefore only
+/// applies to names below the suffix but not to the suffix itself.
+/// `access` - Who may change names under the suffix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclRule {
+    pub suffix: String,
+    pub strict: bool,
+    pub access: Access,
+}
+
+impl AclRule {
+    pub fn matches(&self, name: &str) -> bool {
+        is_within(name, &self.suffix) && !(self.strict && name == self.suffix)
+    }
+
+    /// Whether the rule lets `identity` change names, `None` for a requester
+    /// that did not identify itself, which only open rules let through.
+    pub fn allows(&self, identity: Option<&AddressHash>) -> bool {
+        match &self.access {
+            Access::Open => true,
+            Access::Restricted(identities) => identity.is_some_and(|i| identities.contains(i)),
+            Access::ReadOnly => false,
+        }
+    }
+
+    /// Parses a line such as `*.lab.node restricted <hex>,<hex>`,
+    /// `archive.node read-only` or `*.public.node open`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut parts = line.split_whitespace();
+        let pattern = parts.next().ok_or("missing suffix")?;
+        let (suffix, strict) = match pattern.strip_prefix("*.") {
+            Some(suffix) => (suffix, true),
+            None if pattern == "*" => ("", false),
+            None => (pattern, false),
+        };
+        let access = match parts.next() {
+            Some("open") => Access::Open,
+            Some("read-only") => Access::ReadOnly,
+            Some("restricted") => {
+                let identities = parts
+                    .next()
+                    .ok_or("restricted rule without identities")?
+                    .split(',')
+                    .map(|hex| parse_address(hex).ok_or(format!("invalid identity {hex}")))
+                    .collect::<Result<HashSet<_>, _>>()?;
+                Access::Restricted(identities)
+            }
+            Some(other) => return Err(format!("unknown access {other}")),
+            None => return Err("missing access".to_owned()),
+        };
+        if parts.next().is_some() {
+            return Err("trailing input".to_owned());
+        }
+        Ok(Self {
+            suffix: suffix.to_owned(),
+            strict,
+            access,
+        })
+    }
+}
+
+impl fmt::Display for AclRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.strict, self.suffix.is_empty()) {
+            (_, true) => write!(f, "*")?,
+            (true, false) => write!(f, "*.{}", self.suffix)?,
+            (false, false) => write!(f, "{}", self.suffix)?,
+        }
+        match &self.access {
+            Access::Open => write!(f, " open"),
+            Access::ReadOnly => write!(f, " read-only"),
+            Access::Restricted(identities) => {
+                let identities: Vec<String> =
+                    identities.iter().map(|i| to_hex(i.as_slice())).collect();
+                write!(f, " restricted {}", identities.join(","))
+            }
+        }
+    }
+}
+
+/// The rules that decide which identities may change names under which
+/// suffixes.
+///
+/// # Reasoning
+///
+/// The most specific rule wins, i.e. the one with the longest matching suffix,
+/// so that a read-only zone can still contain an open sub zone. Names that no
+/// rule matches are open, which is how the server behaved before there were
+/// rules at all.
+///
+/// # Security
+///
+/// The rules only apply to mutations requested over the network. The operator
+/// can always change every name.
+#[derive(Clone, Debug, Default)]
+pub struct AccessList {
+    rules: Vec<AclRule>,
+}
+
+impl AccessList {
+    pub fn new(rules: Vec<AclRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Reads the rules from a file with one rule per line. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let rules = fs::read_to_string(path)?
+            .lines()
+            .enumerate()
+            .map(|(n, line)| (n, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(n, line)| {
+                AclRule::parse(line).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", n + 1))
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Returns the most specific rule for the name, should any match.
+    pub fn rule_for(&self, name: &str) -> Option<&AclRule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(name))
+            .max_by_key(|rule| (rule.suffix.len(), rule.strict))
+    }
+
+    /// Checks whether `identity` may change `name`, see `AclRule::allows`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the rule that denies the change.
+    pub fn check(&self, identity: Option<&AddressHash>, name: &str) -> Result<(), &AclRule> {
+        match self.rule_for(name) {
+            Some(rule) if !rule.allows(identity) => Err(rule),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(byte: u8) -> AddressHash {
+        AddressHash::new([byte; 16])
+    }
+
+    fn access_list() -> AccessList {
+        let restricted = format!("*.lab.node restricted {}", to_hex(identity(1).as_slice()));
+        let rules = [restricted.as_str(), "node read-only", "open.lab.node open"]
+            .iter()
+            .map(|line| AclRule::parse(line).unwrap())
+            .collect();
+        AccessList::new(rules)
+    }
+
+    #[test]
+    fn most_specific_rule_decides() {
+        let acl = access_list();
+        assert!(acl.check(None, "open.lab.node").is_ok());
+        assert!(acl.check(Some(&identity(2)), "a.open.lab.node").is_ok());
+        assert!(acl.check(Some(&identity(1)), "a.lab.node").is_ok());
+        assert!(acl.check(Some(&identity(1)), "a.node").is_err());
+    }
+
+    #[test]
+    fn restricted_rule_lets_only_its_identities_through() {
+        let acl = access_list();
+        assert!(acl.check(Some(&identity(1)), "a.lab.node").is_ok());
+        assert!(acl.check(Some(&identity(2)), "a.lab.node").is_err());
+    }
+
+    #[test]
+    fn unidentified_requester_only_passes_open_rules() {
+        let acl = access_list();
+        assert!(acl.check(None, "a.lab.node").is_err());
+        assert!(acl.check(None, "a.node").is_err());
+        assert!(acl.check(None, "a.open.lab.node").is_ok());
+        assert!(acl.check(None, "unlisted.other").is_ok());
+    }
+
+    #[test]
+    fn strict_rule_does_not_cover_its_suffix() {
+        let acl = access_list();
+        // lab.node itself falls back to the read-only rule of node
+        assert!(acl.check(Some(&identity(1)), "lab.node").is_err());
+        assert_eq!(acl.rule_for("lab.node").unwrap().suffix, "node");
+    }
+
+    #[test]
+    fn rules_survive_printing() {
+        for rule in access_list().rules {
+            assert_eq!(AclRule::parse(&rule.to_string()).unwrap(), rule);
+        }
+    }
+}
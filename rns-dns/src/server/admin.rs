@@ -0,0 +1,192 @@
+//! Offline administration of a database file by the server operator.
+//!
+//! These functions act with the identity of the dns server since the verifier
+//! registry may only be changed by the operator. They are meant to be run while
+//! the server itself is stopped.
+
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::TimeDelta;
+use ed25519_dalek::VerifyingKey;
+use reticulum::identity::PrivateIdentity;
+
+use crate::server::delegation::{Delegation, TrustChain, is_within};
+use crate::server::node::open_database;
+use crate::server::server::{DnsDatabase, Requester, Verifier};
+use crate::types::{SecondaryConfig, ServerConfig};
+use crate::utilites::codec::{parse_address, to_hex};
+
+fn open(path: &Path, private_id: &PrivateIdentity) -> (DnsDatabase, Requester) {
+    let config = ServerConfig::new(Some(path.to_path_buf())).primary_zone();
+    let database = open_database(&config, private_id);
+    let requester = Requester::new(*database.operator(), None);
+    (database, requester)
+}
+
+fn commit(database: &DnsDatabase, path: &Path) -> Result<(), String> {
+    database
+        .promote_staging()
+        .and_then(|_| database.save(path).map_err(|e| e.to_string()))
+}
+
+/// How long a delegation made by `delegate` stays valid.
+const DELEGATION_VALIDITY: TimeDelta = TimeDelta::days(365);
+
+/// Parses a base64 encoded verifying key as printed by the verifier and server.
+pub fn parse_key(key: &str) -> Result<VerifyingKey, String> {
+    URL_SAFE_NO_PAD
+        .decode(key)
+        .ok()
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .and_then(|k| VerifyingKey::from_bytes(&k).ok())
+        .ok_or("invalid public key".into())
+}
+
+/// Parses the command line representation of the primary server of a
+/// secondary.
+///
+/// `args` - destination of the primary as hex, the base64 encoded key of the
+/// zone and optionally the suffix of the zone, the primary zone without it.
+pub fn parse_secondary(args: &[String]) -> Result<SecondaryConfig, String> {
+    let (destination, key, zone) = match args {
+        [destination, key] => (destination, key, ""),
+        [destination, key, zone] => (destination, key, zone.as_str()),
+        _ => return Err("expected DESTINATION PUBLIC_KEY [ZONE]".into()),
+    };
+    Ok(SecondaryConfig {
+        primary: parse_address(destination).ok_or("invalid destination")?,
+        zone_key: parse_key(key)?,
+        zone: zone.to_owned(),
+    })
+}
+
+/// Parses the command line representation of a verifier.
+///
+/// `args` - name, destination as hex, trust level and the base64 encoded
+/// verifying key.
+pub fn parse_verifier(args: &[String]) -> Result<Verifier, String> {
+    let [name, destination, trust_level, public_key] = args else {
+        return Err("expected NAME DESTINATION TRUST_LEVEL PUBLIC_KEY".into());
+    };
+    let destination = parse_address(destination).ok_or("invalid destination")?;
+    let trust_level = trust_level
+        .parse::<u32>()
+        .map_err(|_| "invalid trust level")?;
+    let public_key = parse_key(public_key)?;
+
+    Ok(Verifier::new(
+        name.clone(),
+        destination,
+        trust_level,
+        public_key,
+    ))
+}
+
+pub fn add_verifier(
+    path: &Path,
+    private_id: &PrivateIdentity,
+    verifier: Verifier,
+) -> Result<(), String> {
+    let (database, requester) = open(path, private_id);
+    database
+        .add_verifier(&requester, verifier)
+        .map_err(|e| format!("{e:?}"))?;
+    commit(&database, path)
+}
+
+pub fn update_verifier(
+    path: &Path,
+    private_id: &PrivateIdentity,
+    verifier: Verifier,
+) -> Result<(), String> {
+    let (database, requester) = open(path, private_id);
+    database
+        .update_verifier(&requester, verifier)
+        .map_err(|e| format!("{e:?}"))?;
+    commit(&database, path)
+}
+
+pub fn remove_verifier(
+    path: &Path,
+    private_id: &PrivateIdentity,
+    destination: &str,
+) -> Result<(), String> {
+    let destination = parse_address(destination).ok_or("invalid destination")?;
+    let (database, requester) = open(path, private_id);
+    database
+        .remove_verifier(&requester, &destination)
+        .map_err(|e| format!("{e:?}"))?;
+    commit(&database, path)
+}
+
+pub fn revoke_verifier(
+    path: &Path,
+    private_id: &PrivateIdentity,
+    destination: &str,
+) -> Result<(), String> {
+    let destination = parse_address(destination).ok_or("invalid destination")?;
+    let (database, requester) = open(path, private_id);
+    database
+        .revoke_verifier(&requester, &destination)
+        .map_err(|e| format!("{e:?}"))?;
+    commit(&database, path)
+}
+
+/// Prints every verifier, most trusted first, as tab separated lines.
+pub fn list_verifiers(path: &Path, private_id: &PrivateIdentity) {
+    let (database, _) = open(path, private_id);
+    let active = database.active();
+    for verifier in active.verifier_registry().get_entries_by_trust_level(0..=u32::MAX) {
+        println!(
+            "{}\t{}\t{}\t{}",
+            verifier.trust_level(),
+            to_hex(verifier.destination().as_slice()),
+            verifier.name(),
+            URL_SAFE_NO_PAD.encode(verifier.public_key().as_bytes()),
+        );
+    }
+}
+
+/// Delegates `suffix` to a child server and writes the chain the child has to be
+/// started with.
+///
+/// `args` - suffix, destination of the child as hex and its base64 encoded
+/// verifying key.
+/// `parent_chain` - The chain of this server. Without one this server acts as a
+/// root and its key has to be configured as a trust anchor by the resolvers.
+pub fn delegate(
+    private_id: &PrivateIdentity,
+    args: &[String],
+    parent_chain: Option<&Path>,
+    out: &Path,
+) -> Result<(), String> {
+    let [suffix, server, key] = args else {
+        return Err("expected SUFFIX DESTINATION PUBLIC_KEY".into());
+    };
+    let server = parse_address(server).ok_or("invalid destination")?;
+    let key = parse_key(key)?;
+
+    let parent = match parent_chain {
+        Some(path) => TrustChain::load(path).map_err(|e| e.to_string())?,
+        None => TrustChain::default(),
+    };
+    if let Some(leaf) = parent.delegations().last()
+        && !is_within(suffix, leaf.suffix())
+    {
+        return Err(format!("{suffix} is not within {}", leaf.suffix()));
+    }
+
+    let delegation = Delegation::sign(suffix.clone(), server, key, DELEGATION_VALIDITY, private_id);
+    parent
+        .extend(delegation)
+        .save(out)
+        .map_err(|e| e.to_string())?;
+    if parent.is_empty() {
+        log::info!(
+            "delegated as root, the trust anchor is {}",
+            URL_SAFE_NO_PAD.encode(private_id.as_identity().verifying_key.as_bytes())
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use rand::Rng;
+use reticulum::identity::PrivateIdentity;
+
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+/// The version of the query protocol this server speaks.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The version of the capability format.
+const CAPABILITY_VERSION: u8 = 1;
+/// The most bytes the capabilities may take up in an announce.
+const MAX_CAPABILITY_SIZE: usize = 256;
+
+/// How often a node announces its destination.
+///
+/// # Fields
+/// `min_interval` - The interval right after startup or a change.
+/// `max_interval` - The interval the node backs off to.
+/// `jitter` - The fraction by which every interval is randomly varied.
+///
+/// # Reasoning
+///
+/// A node that just came up wants to be found quickly, but announces travel
+/// over every interface of the network and cost bandwidth on slow links. The
+/// interval therefore doubles after every announce until `max_interval` is
+/// reached. The jitter keeps nodes that started together from announcing in
+/// lock step.
+#[derive(Clone, Copy, Debug)]
+pub struct AnnounceConfig {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub jitter: f64,
+}
+
+impl Default for AnnounceConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_secs(2),
+            max_interval: Duration::from_secs(120),
+            jitter: 0.1,
+        }
+    }
+}
+
+/// The delays between the announces of a node, see `AnnounceConfig`.
+pub struct AnnounceSchedule {
+    config: AnnounceConfig,
+    current: Duration,
+}
+
+impl AnnounceSchedule {
+    pub fn new(config: AnnounceConfig) -> Self {
+        Self {
+            config,
+            current: config.min_interval,
+        }
+    }
+
+    /// Returns how long to wait before the next announce and backs off.
+    pub fn next_delay(&mut self) -> Duration {
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        let factor = match jitter > 0.0 {
+            true => rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter),
+            false => 1.0,
+        };
+        let delay = self.current.mul_f64(factor);
+        self.current = (self.current * 2).min(self.config.max_interval);
+        delay
+    }
+
+    /// Starts over at the minimum interval, e.g. because the announced data
+    /// changed.
+    pub fn reset(&mut self) {
+        self.current = self.config.min_interval;
+    }
+}
+
+/// What a dns server offers, sent as the app data of its announces.
+///
+/// # Fields
+/// `versions` - The protocol versions the server speaks.
+/// `zones` - The suffixes the server is authoritative for. Empty should the
+/// server not restrict itself to any suffix.
+/// `recursion` - Whether the server resolves names it is not authoritative for.
+/// `timestamp` - The time at which the capabilities were signed.
+/// `signature` - The signature of the server over all of the above.
+///
+/// # Reasoning
+///
+/// Clients pick a server straight from the announces they hear instead of
+/// linking to every destination first. The capabilities are signed on their own
+/// so that they stay verifiable when they are passed on outside of an announce.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub versions: Vec<u16>,
+    pub zones: Vec<String>,
+    pub recursion: bool,
+    pub timestamp: DateTime<Utc>,
+    pub signature: Signature,
+}
+
+impl Capabilities {
+    /// Creates the capabilities and signs them with the identity of the server.
+    ///
+    /// Zones that would push the encoding past the size limit of an announce are
+    /// left out.
+    pub fn sign(zones: &[String], recursion: bool, private_id: &PrivateIdentity) -> Self {
+        let mut capabilities = Self {
+            versions: vec![PROTOCOL_VERSION],
+            zones: Vec::new(),
+            recursion,
+            timestamp: Utc::now(),
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        for zone in zones {
+            capabilities.zones.push(zone.clone());
+            if capabilities.encode().len() > MAX_CAPABILITY_SIZE {
+                capabilities.zones.pop();
+                log::warn!("zone {zone} does not fit into the announce");
+            }
+        }
+        capabilities.signature = private_id.sign(&capabilities.signable_bytes());
+        capabilities
+    }
+
+    pub fn supports(&self, version: u16) -> bool {
+        self.versions.contains(&version)
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer.put_u8(CAPABILITY_VERSION);
+        writer.put_u8(self.versions.len() as u8);
+        for version in &self.versions {
+            writer.put_u16(*version);
+        }
+        writer.put_u8(self.zones.len() as u8);
+        for zone in &self.zones {
+            writer.put_str(zone);
+        }
+        writer.put_bool(self.recursion).put_time(&self.timestamp);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    /// Checks the signature against the key of the server.
+    pub fn verify(&self, server_key: &VerifyingKey) -> bool {
+        server_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        if reader.get_u8()? != CAPABILITY_VERSION {
+            return Err(CodecError::InvalidData);
+        }
+        let versions = (0..reader.get_u8()?)
+            .map(|_| reader.get_u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        let zones = (0..reader.get_u8()?)
+            .map(|_| reader.get_str())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            versions,
+            zones,
+            recursion: reader.get_bool()?,
+            timestamp: reader.get_time()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
@@ -0,0 +1,509 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+use sha2::{Digest, Sha256};
+
+use crate::server::server::{DnsEntry, Requester};
+use crate::utilites::codec::{Reader, Writer, to_hex};
+use crate::utilites::error::CodecError;
+
+/// The first token of the header line of every audit log file.
+const AUDIT_HEADER: &str = "rns-dns-audit";
+/// How a requester that did not identify itself is shown.
+const UNIDENTIFIED: &str = "unidentified";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Add,
+    Override,
+    Remove,
+    Renew,
+    VerificationAdded,
+    VerificationRemoved,
+    /// The verifications of the entry changed because a verifier was updated,
+    /// removed or revoked.
+    VerifierChanged,
+    /// The entry was changed or removed by a transfer from the primary server.
+    Transferred,
+    /// The entry was replaced by a version a peer replicated.
+    Replicated,
+}
+
+impl AuditAction {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Add => 0,
+            Self::Override => 1,
+            Self::Remove => 2,
+            Self::Renew => 3,
+            Self::VerificationAdded => 4,
+            Self::VerificationRemoved => 5,
+            Self::VerifierChanged => 6,
+            Self::Transferred => 7,
+            Self::Replicated => 8,
+        }
+    }
+
+    fn from_u8(value: u8) -> Result<Self, CodecError> {
+        match value {
+            0 => Ok(Self::Add),
+            1 => Ok(Self::Override),
+            2 => Ok(Self::Remove),
+            3 => Ok(Self::Renew),
+            4 => Ok(Self::VerificationAdded),
+            5 => Ok(Self::VerificationRemoved),
+            6 => Ok(Self::VerifierChanged),
+            7 => Ok(Self::Transferred),
+            8 => Ok(Self::Replicated),
+            _ => Err(CodecError::InvalidData),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "ADD",
+            Self::Override => "OVERRIDE",
+            Self::Remove => "REMOVE",
+            Self::Renew => "RENEW",
+            Self::VerificationAdded => "VERIFICATION_ADDED",
+            Self::VerificationRemoved => "VERIFICATION_REMOVED",
+            Self::VerifierChanged => "VERIFIER_CHANGED",
+            Self::Transferred => "TRANSFERRED",
+            Self::Replicated => "REPLICATED",
+        }
+    }
+}
+
+/// A single record of a mutation of the `DnsDatabase`.
+///
+/// # Fields
+/// `sequence` - The position of the entry in the log, starting at `0`.
+/// `timestamp` - The time at which the mutation was made.
+/// `action` - What kind of mutation was made.
+/// `name` - The domain name that was affected.
+/// `requester` - The identity on whose behalf the mutation was made, `None`
+/// should the requester not have identified itself.
+/// `request_signature` - The signature of the request, if there was one.
+/// `before` - The digest of the entry before the mutation.
+/// `after` - The digest of the entry after the mutation.
+/// `previous` - The digest of the previous audit entry.
+/// `server_signature` - The signature of the server over all of the above.
+///
+/// # Reasoning
+///
+/// Only digests of the entries are stored. The log is meant to answer who changed
+/// what and when, the entries themselves live in the database.
+///
+/// # Security
+///
+/// Each entry contains the digest of the entry before it, so removing or
+/// reordering entries breaks the chain even though each entry is still validly
+/// signed by the server.
+#[derive(Clone)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    pub name: String,
+    pub requester: Option<AddressHash>,
+    pub request_signature: Option<Signature>,
+    pub before: Option<[u8; 32]>,
+    pub after: Option<[u8; 32]>,
+    pub previous: [u8; 32],
+    pub server_signature: Signature,
+}
+
+impl AuditEntry {
+    /// Encodes every field that is covered by the server signature.
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer
+            .put_u64(self.sequence)
+            .put_time(&self.timestamp)
+            .put_u8(self.action.to_u8())
+            .put_str(&self.name)
+            .put_bool(self.requester.is_some());
+        if let Some(requester) = &self.requester {
+            writer.put_address(requester);
+        }
+        writer.put_bool(self.request_signature.is_some());
+        if let Some(signature) = &self.request_signature {
+            writer.put_signature(signature);
+        }
+        for digest in [&self.before, &self.after] {
+            writer.put_bool(digest.is_some());
+            if let Some(digest) = digest {
+                writer.put_fixed(digest);
+            }
+        }
+        writer.put_fixed(&self.previous);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.server_signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let sequence = reader.get_u64()?;
+        let timestamp = reader.get_time()?;
+        let action = AuditAction::from_u8(reader.get_u8()?)?;
+        let name = reader.get_str()?;
+        let requester = match reader.get_bool()? {
+            true => Some(reader.get_address()?),
+            false => None,
+        };
+        let request_signature = match reader.get_bool()? {
+            true => Some(reader.get_signature()?),
+            false => None,
+        };
+        let before = match reader.get_bool()? {
+            true => Some(reader.get_array()?),
+            false => None,
+        };
+        let after = match reader.get_bool()? {
+            true => Some(reader.get_array()?),
+            false => None,
+        };
+        let previous = reader.get_array()?;
+        let server_signature = reader.get_signature()?;
+
+        Ok(Self {
+            sequence,
+            timestamp,
+            action,
+            name,
+            requester,
+            request_signature,
+            before,
+            after,
+            previous,
+            server_signature,
+        })
+    }
+
+    /// Returns the digest which the next entry will chain to.
+    pub fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+
+    /// The hex encoded requester, or `unidentified`.
+    pub fn requester_hex(&self) -> String {
+        self.requester
+            .map_or(UNIDENTIFIED.to_owned(), |requester| to_hex(requester.as_slice()))
+    }
+
+    /// Checks the server signature of this entry.
+    pub fn verify(&self, key: &VerifyingKey) -> bool {
+        key.verify_strict(&self.signable_bytes(), &self.server_signature)
+            .is_ok()
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digest = |d: &Option<[u8; 32]>| d.map(|d| to_hex(&d)).unwrap_or("-".into());
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.sequence,
+            self.timestamp.to_rfc3339(),
+            self.action.as_str(),
+            self.name,
+            self.requester_hex(),
+            digest(&self.before),
+            digest(&self.after),
+        )
+    }
+}
+
+/// A filter over the audit log. Fields that are `None` match everything.
+#[derive(Default)]
+pub struct AuditQuery {
+    pub name: Option<String>,
+    /// The hex encoded address hash of the requester, or `unidentified`.
+    pub requester: Option<String>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let name = self.name.as_ref().is_none_or(|n| *n == entry.name);
+        let requester = self
+            .requester
+            .as_ref()
+            .is_none_or(|r| r.eq_ignore_ascii_case(&entry.requester_hex()));
+        name && requester
+    }
+}
+
+/// This is the append only audit log of the `DnsDatabase`.
+///
+/// # Fields
+/// `signer` - The identity of the server which signs every entry.
+/// `file` - The file the entries are appended to. `None` if the log is ephemeral.
+/// `sequence` - The sequence number of the next entry.
+/// `head` - The digest of the last entry.
+///
+/// # Reasoning
+///
+/// The file starts with a header line containing the verifying key of the server
+/// followed by one base64 encoded entry per line. This keeps the file readable by
+/// line based tools while still being verifiable on its own.
+pub struct AuditLog {
+    signer: PrivateIdentity,
+    file: Option<File>,
+    sequence: u64,
+    head: [u8; 32],
+}
+
+impl AuditLog {
+    /// Creates a log which signs entries but does not store them anywhere.
+    pub fn ephemeral(signer: PrivateIdentity) -> Self {
+        Self {
+            signer,
+            file: None,
+            sequence: 0,
+            head: [0u8; 32],
+        }
+    }
+
+    /// Opens the log at `path`, continuing the chain of an existing log.
+    ///
+    /// # Errors
+    ///
+    /// Fails should the file be unreadable or belong to a different server key.
+    pub fn open(path: &Path, signer: PrivateIdentity) -> io::Result<Self> {
+        let key = signer.as_identity().verifying_key;
+        let mut log = Self::ephemeral(signer);
+
+        if path.exists() {
+            let (file_key, entries) = read_audit_log(path)?;
+            if file_key != key {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "audit log was signed by a different server key",
+                ));
+            }
+            if let Some(last) = entries.last() {
+                log.sequence = last.sequence + 1;
+                log.head = last.digest();
+            }
+            log.file = Some(OpenOptions::new().append(true).open(path)?);
+        } else {
+            let mut file = OpenOptions::new().create_new(true).append(true).open(path)?;
+            writeln!(file, "{AUDIT_HEADER} {}", URL_SAFE_NO_PAD.encode(key.as_bytes()))?;
+            log.file = Some(file);
+        }
+
+        Ok(log)
+    }
+
+    /// Signs and appends a new entry to the log.
+    pub fn append(
+        &mut self,
+        action: AuditAction,
+        name: &str,
+        requester: &Requester,
+        before: Option<&DnsEntry>,
+        after: Option<&DnsEntry>,
+    ) -> io::Result<AuditEntry> {
+        let mut entry = AuditEntry {
+            sequence: self.sequence,
+            timestamp: Utc::now(),
+            action,
+            name: name.to_owned(),
+            requester: requester.identity,
+            request_signature: requester.signature,
+            before: before.map(DnsEntry::digest),
+            after: after.map(DnsEntry::digest),
+            previous: self.head,
+            server_signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        entry.server_signature = self.signer.sign(&entry.signable_bytes());
+
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", URL_SAFE_NO_PAD.encode(entry.encode()))?;
+            file.flush()?;
+        }
+
+        self.sequence += 1;
+        self.head = entry.digest();
+        Ok(entry)
+    }
+}
+
+/// Reads an audit log file and returns the server key and all entries.
+pub fn read_audit_log(path: &Path) -> io::Result<(VerifyingKey, Vec<AuditEntry>)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+    let mut lines = BufReader::new(File::open(path)?).lines();
+
+    let header = lines.next().ok_or_else(|| invalid("missing header"))??;
+    let key = header
+        .strip_prefix(AUDIT_HEADER)
+        .map(str::trim)
+        .and_then(|k| URL_SAFE_NO_PAD.decode(k).ok())
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .and_then(|k| VerifyingKey::from_bytes(&k).ok())
+        .ok_or_else(|| invalid("malformed header"))?;
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(line?.trim())
+            .map_err(|_| invalid("malformed entry"))?;
+        let entry = AuditEntry::decode(&mut Reader::new(&bytes))
+            .map_err(|_| invalid("malformed entry"))?;
+        entries.push(entry);
+    }
+
+    Ok((key, entries))
+}
+
+/// Checks the signatures and the chain of the given entries.
+///
+/// # Errors
+///
+/// Returns the sequence number of the first entry which is not valid.
+pub fn verify_audit_chain(key: &VerifyingKey, entries: &[AuditEntry]) -> Result<(), u64> {
+    let mut previous = [0u8; 32];
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.sequence != i as u64 || entry.previous != previous || !entry.verify(key) {
+            return Err(entry.sequence);
+        }
+        previous = entry.digest();
+    }
+    Ok(())
+}
+
+/// Prints every entry of the log at `path` that matches the query.
+///
+/// The chain is verified first, a broken chain is reported but the entries are
+/// still printed so that the damage can be inspected.
+pub fn query_audit_log(path: &Path, query: &AuditQuery) -> io::Result<()> {
+    let (key, entries) = read_audit_log(path)?;
+
+    match verify_audit_chain(&key, &entries) {
+        Ok(()) => log::info!("audit log verified, {} entries", entries.len()),
+        Err(sequence) => log::error!("audit log chain is broken at entry {sequence}"),
+    }
+
+    for entry in entries.iter().filter(|e| query.matches(e)) {
+        println!("{entry}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_with_entries(count: usize) -> (VerifyingKey, Vec<AuditEntry>) {
+        let signer = PrivateIdentity::new_from_name("server");
+        let key = signer.as_identity().verifying_key;
+        let mut log = AuditLog::ephemeral(signer);
+        let requester = Requester::new(AddressHash::new([7u8; 16]), None);
+        let entries = (0..count)
+            .map(|i| {
+                let name = format!("{i}.node");
+                log.append(AuditAction::Add, &name, &requester, None, None)
+                    .unwrap()
+            })
+            .collect();
+        (key, entries)
+    }
+
+    fn round_trip(entry: &AuditEntry) -> AuditEntry {
+        AuditEntry::decode(&mut Reader::new(&entry.encode())).unwrap()
+    }
+
+    #[test]
+    fn appended_entries_form_a_valid_chain() {
+        let (key, entries) = log_with_entries(3);
+        assert_eq!(verify_audit_chain(&key, &entries), Ok(()));
+        assert_eq!(entries[1].previous, entries[0].digest());
+        assert_eq!(entries[2].previous, entries[1].digest());
+    }
+
+    #[test]
+    fn removed_entry_breaks_the_chain() {
+        let (key, mut entries) = log_with_entries(3);
+        entries.remove(1);
+        assert_eq!(verify_audit_chain(&key, &entries), Err(2));
+    }
+
+    #[test]
+    fn reordered_entries_break_the_chain() {
+        let (key, mut entries) = log_with_entries(3);
+        entries.swap(1, 2);
+        assert_eq!(verify_audit_chain(&key, &entries), Err(2));
+    }
+
+    #[test]
+    fn tampered_entry_breaks_the_chain() {
+        let (key, mut entries) = log_with_entries(3);
+        entries[1].name = "other.node".to_owned();
+        assert_eq!(verify_audit_chain(&key, &entries), Err(1));
+    }
+
+    #[test]
+    fn chain_of_another_server_is_rejected() {
+        let (_, entries) = log_with_entries(1);
+        let other = PrivateIdentity::new_from_name("other").as_identity().verifying_key;
+        assert_eq!(verify_audit_chain(&other, &entries), Err(0));
+    }
+
+    #[test]
+    fn entries_survive_encoding() {
+        let (key, entries) = log_with_entries(1);
+        let decoded = round_trip(&entries[0]);
+        assert_eq!(decoded.requester, entries[0].requester);
+        assert_eq!(decoded.digest(), entries[0].digest());
+        assert!(decoded.verify(&key));
+    }
+
+    #[test]
+    fn unidentified_requester_survives_encoding() {
+        let signer = PrivateIdentity::new_from_name("server");
+        let key = signer.as_identity().verifying_key;
+        let mut log = AuditLog::ephemeral(signer);
+        let requester = Requester::on_link(AddressHash::new([1u8; 16]), None);
+        let entry = log
+            .append(AuditAction::Remove, "a.node", &requester, None, None)
+            .unwrap();
+
+        let decoded = round_trip(&entry);
+        assert!(decoded.requester.is_none());
+        assert!(decoded.verify(&key));
+        assert_eq!(decoded.requester_hex(), UNIDENTIFIED);
+    }
+
+    #[test]
+    fn query_matches_unidentified_requesters() {
+        let signer = PrivateIdentity::new_from_name("server");
+        let mut log = AuditLog::ephemeral(signer);
+        let requester = Requester::on_link(AddressHash::new([1u8; 16]), None);
+        let entry = log
+            .append(AuditAction::Remove, "a.node", &requester, None, None)
+            .unwrap();
+
+        let query = AuditQuery {
+            name: None,
+            requester: Some(UNIDENTIFIED.to_owned()),
+        };
+        assert!(query.matches(&entry));
+    }
+}
@@ -0,0 +1,453 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+
+use crate::server::server::DnsEntry;
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+/// The longest chain a resolver follows from a root anchor down to a server.
+pub const MAX_CHAIN_DEPTH: usize = 8;
+
+/// The first bytes of every trust chain file.
+const CHAIN_MAGIC: &[u8; 8] = b"RNSDNSTC";
+/// The version of the trust chain file format.
+const CHAIN_VERSION: u8 = 1;
+
+/// Returns whether `name` lies within `suffix`. The empty suffix is the root and
+/// contains every name.
+pub fn is_within(name: &str, suffix: &str) -> bool {
+    suffix.is_empty()
+        || name == suffix
+        || name
+            .strip_suffix(suffix)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// A signed statement of a parent that a child server is authoritative for a
+/// suffix.
+///
+/// # Fields
+/// `suffix` - The suffix that is delegated, e.g. `node` or `weather.node`.
+/// `server` - The destination of the child server.
+/// `key` - The verifying key of the child server. It signs the entries of the
+/// suffix and any further delegations below it.
+/// `issued_at` - The time at which the delegation was made.
+/// `expiry` - The time at which the delegation ceases to be valid.
+/// `signature` - The signature of the parent over all of the above.
+///
+/// # Reasoning
+///
+/// This mirrors the DS records of DNSSEC. The parent does not sign the entries of
+/// the child, it only vouches for the key that does. A server therefore only has
+/// to carry the delegations from a root down to itself and not any records of
+/// the servers above it.
+#[derive(Clone)]
+pub struct Delegation {
+    suffix: String,
+    server: AddressHash,
+    key: VerifyingKey,
+    issued_at: DateTime<Utc>,
+    expiry: DateTime<Utc>,
+    signature: Signature,
+}
+
+impl Delegation {
+    /// Creates a delegation and signs it with the identity of the parent.
+    pub fn sign(
+        suffix: String,
+        server: AddressHash,
+        key: VerifyingKey,
+        validity: TimeDelta,
+        parent: &PrivateIdentity,
+    ) -> Self {
+        let issued_at = Utc::now();
+        let mut delegation = Self {
+            suffix,
+            server,
+            key,
+            issued_at,
+            expiry: issued_at + validity,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        delegation.signature = parent.sign(&delegation.signable_bytes());
+        delegation
+    }
+
+    pub fn suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    pub fn server(&self) -> &AddressHash {
+        &self.server
+    }
+
+    pub fn key(&self) -> &VerifyingKey {
+        &self.key
+    }
+
+    pub fn expiry(&self) -> DateTime<Utc> {
+        self.expiry
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer
+            .put_str(&self.suffix)
+            .put_address(&self.server)
+            .put_fixed(self.key.as_bytes())
+            .put_time(&self.issued_at)
+            .put_time(&self.expiry);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    /// Checks the signature against the key of the parent.
+    pub fn verify(&self, parent_key: &VerifyingKey) -> bool {
+        parent_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    pub fn encode_into(&self, writer: &mut Writer) {
+        self.encode_unsigned(writer);
+        writer.put_signature(&self.signature);
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            suffix: reader.get_str()?,
+            server: reader.get_address()?,
+            key: VerifyingKey::from_bytes(&reader.get_array()?)
+                .map_err(|_| CodecError::InvalidData)?,
+            issued_at: reader.get_time()?,
+            expiry: reader.get_time()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+/// A key that a resolver trusts without any delegation, usually the key of a
+/// root server. `suffix` limits the names the anchor may vouch for, the empty
+/// suffix trusts the anchor for every name.
+#[derive(Clone)]
+pub struct TrustAnchor {
+    pub suffix: String,
+    pub key: VerifyingKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    /// The chain is longer than `MAX_CHAIN_DEPTH`.
+    TooLong,
+    /// No anchor signed the first delegation of the chain.
+    NoAnchor,
+    /// The delegation at the given depth is not signed by the key above it.
+    InvalidSignature(usize),
+    /// The delegation at the given depth has expired.
+    Expired(usize),
+    /// The delegation at the given depth is not within the suffix above it.
+    OutsideParent(usize),
+    /// The name of the entry is not within the last delegated suffix.
+    NameOutsideChain,
+    /// The entry was not signed by the server at the end of the chain.
+    MissingServerSigning,
+}
+
+/// The delegations from a root down to a server, root first.
+#[derive(Clone, Default)]
+pub struct TrustChain {
+    delegations: Vec<Delegation>,
+}
+
+impl TrustChain {
+    pub fn new(delegations: Vec<Delegation>) -> Self {
+        Self { delegations }
+    }
+
+    pub fn delegations(&self) -> &[Delegation] {
+        &self.delegations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.delegations.is_empty()
+    }
+
+    /// Returns a new chain that ends in `delegation`.
+    pub fn extend(&self, delegation: Delegation) -> Self {
+        let mut delegations = self.delegations.clone();
+        delegations.push(delegation);
+        Self { delegations }
+    }
+
+    /// Validates the chain from one of the anchors down to the entry.
+    ///
+    /// # Behaviour
+    ///
+    /// The chain has to lead to the name of the entry, see `validate_zone`. The
+    /// entry also has to carry a valid signing of the last server, which is how
+    /// the server signs every entry it accepts.
+    ///
+    /// No network access is needed, everything is carried by the answer.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first link of the chain that does not hold.
+    pub fn validate(
+        &self,
+        anchors: &[TrustAnchor],
+        entry: &DnsEntry,
+        now: DateTime<Utc>,
+    ) -> Result<(), ChainError> {
+        let leaf = self.validate_zone(anchors, entry.name(), now)?;
+        let signed = entry
+            .verifications()
+            .iter()
+            .any(|s| *s.destination() == leaf.server && s.verify_with_key(entry, &leaf.key));
+        if !signed {
+            return Err(ChainError::MissingServerSigning);
+        }
+        Ok(())
+    }
+
+    /// Validates the chain from one of the anchors down to the zone of `name` and
+    /// returns the delegation of that zone, whose key signs its records and
+    /// denials.
+    ///
+    /// # Behaviour
+    ///
+    /// The first delegation must be signed by an anchor whose suffix contains
+    /// it, each following delegation by the key of the one before it and within
+    /// its suffix. The name has to lie within the last suffix.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first link of the chain that does not hold.
+    pub fn validate_zone(
+        &self,
+        anchors: &[TrustAnchor],
+        name: &str,
+        now: DateTime<Utc>,
+    ) -> Result<&Delegation, ChainError> {
+        if self.delegations.len() > MAX_CHAIN_DEPTH {
+            return Err(ChainError::TooLong);
+        }
+        let first = self.delegations.first().ok_or(ChainError::NoAnchor)?;
+        if !anchors
+            .iter()
+            .any(|a| is_within(&first.suffix, &a.suffix) && first.verify(&a.key))
+        {
+            return Err(ChainError::NoAnchor);
+        }
+
+        for (depth, delegation) in self.delegations.iter().enumerate() {
+            if delegation.expiry < now {
+                return Err(ChainError::Expired(depth));
+            }
+            if depth == 0 {
+                continue;
+            }
+            let parent = &self.delegations[depth - 1];
+            if !is_within(&delegation.suffix, &parent.suffix) {
+                return Err(ChainError::OutsideParent(depth));
+            }
+            if !delegation.verify(&parent.key) {
+                return Err(ChainError::InvalidSignature(depth));
+            }
+        }
+
+        let leaf = self.delegations.last().ok_or(ChainError::NoAnchor)?;
+        if !is_within(name, &leaf.suffix) {
+            return Err(ChainError::NameOutsideChain);
+        }
+        Ok(leaf)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u8(self.delegations.len() as u8);
+        for delegation in &self.delegations {
+            delegation.encode_into(&mut writer);
+        }
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let count = reader.get_u8()? as usize;
+        if count > MAX_CHAIN_DEPTH {
+            return Err(CodecError::InvalidData);
+        }
+        let delegations = (0..count)
+            .map(|_| Delegation::decode(reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { delegations })
+    }
+
+    /// Reads a trust chain file from disk.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "malformed trust chain");
+        let bytes = fs::read(path)?;
+        let mut reader = Reader::new(&bytes);
+        if reader.get_fixed(CHAIN_MAGIC.len()).map_err(invalid)? != CHAIN_MAGIC
+            || reader.get_u8().map_err(invalid)? != CHAIN_VERSION
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a trust chain"));
+        }
+        Self::decode(&mut reader).map_err(invalid)
+    }
+
+    /// Writes the chain to disk.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = Writer::new();
+        writer
+            .put_fixed(CHAIN_MAGIC)
+            .put_u8(CHAIN_VERSION)
+            .put_fixed(&self.encode());
+        fs::write(path, writer.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::server::VerifierSigning;
+
+    struct Server {
+        identity: PrivateIdentity,
+        destination: AddressHash,
+    }
+
+    fn server(name: &str, byte: u8) -> Server {
+        Server {
+            identity: PrivateIdentity::new_from_name(name),
+            destination: AddressHash::new([byte; 16]),
+        }
+    }
+
+    fn delegate(suffix: &str, child: &Server, parent: &PrivateIdentity) -> Delegation {
+        let key = child.identity.as_identity().verifying_key;
+        let validity = TimeDelta::days(1);
+        Delegation::sign(suffix.to_owned(), child.destination, key, validity, parent)
+    }
+
+    fn anchor(root: &PrivateIdentity) -> Vec<TrustAnchor> {
+        let key = root.as_identity().verifying_key;
+        vec![TrustAnchor {
+            suffix: String::new(),
+            key,
+        }]
+    }
+
+    /// An entry under `weather.node` that carries the signing of `leaf`.
+    fn entry(leaf: &Server) -> DnsEntry {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let destinations = vec![AddressHash::new([9u8; 16])];
+        let entry = DnsEntry::new_signed("x.weather.node".to_owned(), destinations, 1, &owner);
+        let signing = VerifierSigning::sign(&entry, leaf.destination, &leaf.identity);
+
+        // the encoded entry ends in the count of its verifications
+        let mut encoded = entry.encode();
+        encoded.truncate(encoded.len() - 4);
+        let mut writer = Writer::new();
+        writer.put_fixed(&encoded).put_u32(1);
+        signing.encode_into(&mut writer);
+        DnsEntry::decode(&mut Reader::new(&writer.finish())).unwrap()
+    }
+
+    #[test]
+    fn names_within_a_suffix() {
+        assert!(is_within("a.b", "a.b"));
+        assert!(is_within("x.a.b", "a.b"));
+        assert!(is_within("xa.b", ""));
+        assert!(!is_within("xa.b", "a.b"));
+        assert!(!is_within("a.b", "x.a.b"));
+    }
+
+    #[test]
+    fn valid_chain_leads_to_the_entry() {
+        let root = PrivateIdentity::new_from_name("root");
+        let (node, weather) = (server("node", 1), server("weather", 2));
+        let chain = TrustChain::new(vec![
+            delegate("node", &node, &root),
+            delegate("weather.node", &weather, &node.identity),
+        ]);
+
+        let leaf = chain
+            .validate_zone(&anchor(&root), "x.weather.node", Utc::now())
+            .unwrap();
+        assert_eq!(leaf.server(), &weather.destination);
+        assert_eq!(
+            chain.validate(&anchor(&root), &entry(&weather), Utc::now()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn entry_without_the_signing_of_the_leaf_is_rejected() {
+        let root = PrivateIdentity::new_from_name("root");
+        let (node, weather) = (server("node", 1), server("weather", 2));
+        let chain = TrustChain::new(vec![
+            delegate("node", &node, &root),
+            delegate("weather.node", &weather, &node.identity),
+        ]);
+
+        assert_eq!(
+            chain.validate(&anchor(&root), &entry(&node), Utc::now()),
+            Err(ChainError::MissingServerSigning)
+        );
+    }
+
+    #[test]
+    fn delegation_not_signed_by_its_parent_breaks_the_chain() {
+        let root = PrivateIdentity::new_from_name("root");
+        let (node, weather) = (server("node", 1), server("weather", 2));
+        let chain = TrustChain::new(vec![
+            delegate("node", &node, &root),
+            delegate("weather.node", &weather, &root),
+        ]);
+
+        assert_eq!(
+            chain.validate(&anchor(&root), &entry(&weather), Utc::now()),
+            Err(ChainError::InvalidSignature(1))
+        );
+    }
+
+    #[test]
+    fn delegation_outside_its_parent_is_rejected() {
+        let root = PrivateIdentity::new_from_name("root");
+        let (parent, child) = (server("parent", 1), server("child", 2));
+        let chain = TrustChain::new(vec![
+            delegate("a.b", &parent, &root),
+            delegate("xa.b", &child, &parent.identity),
+        ]);
+
+        assert_eq!(
+            chain
+                .validate_zone(&anchor(&root), "y.xa.b", Utc::now())
+                .err(),
+            Some(ChainError::OutsideParent(1))
+        );
+    }
+
+    #[test]
+    fn chain_from_an_untrusted_root_is_rejected() {
+        let (root, other) = (PrivateIdentity::new_from_name("root"), server("other", 3));
+        let node = server("node", 1);
+        let chain = TrustChain::new(vec![delegate("node", &node, &root)]);
+
+        assert_eq!(
+            chain
+                .validate_zone(&anchor(&other.identity), "x.node", Utc::now())
+                .err(),
+            Some(ChainError::NoAnchor)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+fn put_name(writer: &mut Writer, name: &Option<String>) {
+    writer.put_bool(name.is_some());
+    if let Some(name) = name {
+        writer.put_str(name);
+    }
+}
+
+fn get_name(reader: &mut Reader) -> Result<Option<String>, CodecError> {
+    match reader.get_bool()? {
+        true => Ok(Some(reader.get_str()?)),
+        false => Ok(None),
+    }
+}
+
+/// A signed statement of the server that a name does not exist.
+///
+/// # Fields
+/// `name` - The name that was asked for.
+/// `predecessor` - The name that comes directly before it in sorted order,
+/// `None` should there be no name before it.
+/// `successor` - The name that comes directly after it in sorted order, `None`
+/// should there be no name after it.
+/// `timestamp` - The time at which the statement was made.
+/// `server` - The destination of the server that made it.
+/// `signature` - The signature of the server over all of the above.
+///
+/// # Reasoning
+///
+/// This follows NSEC of DNSSEC. Signing only "no such name" would allow an
+/// attacker to replay it for any name, the neighbours bind the statement to the
+/// gap between two existing names. Every name within that gap is missing as well
+/// so a resolver can answer further queries for it from its cache.
+///
+/// # Security
+///
+/// The neighbours reveal names that exist, which makes walking the whole zone
+/// possible. Names in this dns are public anyway so this is accepted.
+#[derive(Clone)]
+pub struct DenialOfExistence {
+    pub name: String,
+    pub predecessor: Option<String>,
+    pub successor: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
+
+impl DenialOfExistence {
+    /// Creates the statement and signs it with the identity of the server.
+    pub fn sign(
+        name: String,
+        predecessor: Option<String>,
+        successor: Option<String>,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut denial = Self {
+            name,
+            predecessor,
+            successor,
+            timestamp: Utc::now(),
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        denial.signature = private_id.sign(&denial.signable_bytes());
+        denial
+    }
+
+    /// Returns whether `name` lies strictly between the neighbours.
+    pub fn covers(&self, name: &str) -> bool {
+        self.predecessor.as_deref().is_none_or(|p| p < name)
+            && self.successor.as_deref().is_none_or(|s| name < s)
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer.put_str(&self.name);
+        put_name(writer, &self.predecessor);
+        put_name(writer, &self.successor);
+        writer.put_time(&self.timestamp).put_address(&self.server);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    /// Checks the signature against the key of the server and that the queried
+    /// name actually lies between the neighbours.
+    pub fn verify(&self, server_key: &VerifyingKey) -> bool {
+        self.covers(&self.name)
+            && server_key
+                .verify_strict(&self.signable_bytes(), &self.signature)
+                .is_ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            name: reader.get_str()?,
+            predecessor: get_name(reader)?,
+            successor: get_name(reader)?,
+            timestamp: reader.get_time()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::server::{DnsEntry, DnsEntryStore};
+
+    fn denial(name: &str, predecessor: Option<&str>, successor: Option<&str>) -> DenialOfExistence {
+        DenialOfExistence::sign(
+            name.to_owned(),
+            predecessor.map(str::to_owned),
+            successor.map(str::to_owned),
+            AddressHash::new([1u8; 16]),
+            &PrivateIdentity::new_from_name("server"),
+        )
+    }
+
+    fn server_key() -> VerifyingKey {
+        PrivateIdentity::new_from_name("server").as_identity().verifying_key
+    }
+
+    #[test]
+    fn gap_between_neighbours_is_covered() {
+        let denial = denial("b.node", Some("a.node"), Some("c.node"));
+        assert!(denial.covers("b.node"));
+        assert!(denial.covers("bz.node"));
+        assert!(!denial.covers("a.node"));
+        assert!(!denial.covers("c.node"));
+        assert!(!denial.covers("d.node"));
+    }
+
+    #[test]
+    fn open_ends_cover_the_start_and_end() {
+        assert!(denial("a.node", None, Some("b.node")).covers("0.node"));
+        assert!(denial("z.node", Some("y.node"), None).covers("zz.node"));
+        assert!(denial("a.node", None, None).covers("any.node"));
+    }
+
+    #[test]
+    fn denial_is_verified_against_the_server() {
+        let denial = denial("b.node", Some("a.node"), Some("c.node"));
+        assert!(denial.verify(&server_key()));
+        let other = PrivateIdentity::new_from_name("other");
+        assert!(!denial.verify(&other.as_identity().verifying_key));
+    }
+
+    #[test]
+    fn denial_replayed_for_another_name_fails() {
+        let mut denial = denial("b.node", Some("a.node"), Some("c.node"));
+        denial.name = "bb.node".to_owned();
+        assert!(!denial.verify(&server_key()));
+        denial.name = "d.node".to_owned();
+        assert!(!denial.verify(&server_key()));
+    }
+
+    #[test]
+    fn denial_of_an_existing_name_fails() {
+        let denial = denial("a.node", Some("a.node"), Some("c.node"));
+        assert!(!denial.verify(&server_key()));
+    }
+
+    #[test]
+    fn denial_survives_encoding() {
+        let denial = denial("b.node", None, Some("c.node"));
+        let decoded = DenialOfExistence::decode(&mut Reader::new(&denial.encode())).unwrap();
+        assert_eq!(decoded.predecessor, None);
+        assert_eq!(decoded.successor.as_deref(), Some("c.node"));
+        assert!(decoded.verify(&server_key()));
+    }
+
+    #[test]
+    fn neighbours_bound_a_missing_name() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let mut store = DnsEntryStore::new_empty();
+        for name in ["a.node", "c.node", "e.node"] {
+            let destinations = vec![AddressHash::new([2u8; 16])];
+            store.override_entry(DnsEntry::new_signed(name.to_owned(), destinations, 1, &owner));
+        }
+        let neighbours = |name| {
+            let (predecessor, successor) = store.neighbours(name);
+            (predecessor.cloned(), successor.cloned())
+        };
+        let name = |name: &str| Some(name.to_owned());
+        assert_eq!(neighbours("b.node"), (name("a.node"), name("c.node")));
+        assert_eq!(neighbours("0.node"), (None, name("a.node")));
+        assert_eq!(neighbours("f.node"), (name("e.node"), None));
+        assert_eq!(neighbours("c.node"), (name("a.node"), name("e.node")));
+    }
+}
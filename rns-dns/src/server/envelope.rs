@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+use sha2::{Digest, Sha256};
+
+use crate::utilites::codec::{Reader, Writer, from_hex, to_hex};
+use crate::utilites::error::CodecError;
+
+/// The length of the nonce a client picks for every query.
+pub const NONCE_LENGTH: usize = 16;
+
+/// The most resolvers a query may be forwarded by before it is given up on.
+pub const MAX_HOPS: u8 = 4;
+
+/// The flags of a query, sent as `+<flag>,<flag>` in front of the request.
+///
+/// # Fields
+/// `hops` - How many resolvers already forwarded the query, `hops=<n>`.
+///
+/// # Reasoning
+///
+/// Two resolvers that forward to each other would pass a query back and forth
+/// forever. Every resolver counts up the hops when it forwards a query and
+/// refuses to forward it once there were `MAX_HOPS`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryFlags {
+    pub hops: u8,
+}
+
+impl QueryFlags {
+    /// Parses the flags without the leading `+`. Unknown flags are ignored so
+    /// that new ones can be added without breaking older servers.
+    pub fn parse(flags: &str) -> Option<Self> {
+        let mut parsed = Self::default();
+        for flag in flags.split(',') {
+            if let Some(hops) = flag.strip_prefix("hops=") {
+                parsed.hops = hops.parse().ok()?;
+            }
+        }
+        Some(parsed)
+    }
+
+    /// The flags as they are sent, `None` should all of them be unset.
+    pub fn format(&self) -> Option<String> {
+        (*self != Self::default()).then(|| format!("+hops={}", self.hops))
+    }
+}
+
+/// A request that is wrapped in `QUERY <id> <nonce> [+<flags>] <request>`.
+pub struct Query<'a> {
+    pub id: u16,
+    pub nonce: [u8; NONCE_LENGTH],
+    pub flags: QueryFlags,
+    pub request: &'a str,
+}
+
+impl<'a> Query<'a> {
+    /// Parses a wrapped request. Returns `None` should the request not be
+    /// wrapped or the header be malformed.
+    pub fn parse(payload: &'a str) -> Option<Self> {
+        let rest = payload.trim_ascii().strip_prefix("QUERY ")?;
+        let (id, rest) = rest.split_once(' ')?;
+        let (nonce, request) = rest.split_once(' ')?;
+        // a command never starts with `+`, so the flags can not be mistaken for it
+        let (flags, request) = match request.strip_prefix('+') {
+            Some(flagged) => {
+                let (flags, request) = flagged.split_once(' ')?;
+                (QueryFlags::parse(flags)?, request)
+            }
+            None => (QueryFlags::default(), request),
+        };
+        Some(Self {
+            id: id.parse().ok()?,
+            nonce: from_hex(nonce)?.try_into().ok()?,
+            flags,
+            request: request.trim_ascii(),
+        })
+    }
+
+    pub fn format(&self) -> String {
+        let nonce = to_hex(&self.nonce);
+        match self.flags.format() {
+            Some(flags) => format!("QUERY {} {nonce} {flags} {}", self.id, self.request),
+            None => format!("QUERY {} {nonce} {}", self.id, self.request),
+        }
+    }
+}
+
+/// Returns the digest of a request as it is bound into the answer.
+pub fn request_digest(request: &str) -> [u8; 32] {
+    Sha256::digest(request.trim_ascii().as_bytes()).into()
+}
+
+/// The answer of the server to a `QUERY`, signed as a whole.
+///
+/// # Fields
+/// `id` - The id the client chose for the query.
+/// `nonce` - The nonce the client chose for the query.
+/// `request` - The digest of the request that was answered.
+/// `timestamp` - The time at which the server answered.
+/// `response` - The answer itself, exactly as it would be sent unwrapped.
+/// `server` - The destination of the server.
+/// `signature` - The signature of the server over all of the above.
+///
+/// # Reasoning
+///
+/// Signed records alone do not stop an attacker from answering a query with a
+/// different, equally well signed answer, e.g. an old entry or the entry of a
+/// different name. Binding the answer to the id, the nonce and the request of
+/// the query makes it useless for any other query.
+///
+/// # Security
+///
+/// The nonce has to be unpredictable, otherwise an answer can be obtained ahead
+/// of the query it is later replayed for.
+#[derive(Clone)]
+pub struct SignedAnswer {
+    pub id: u16,
+    pub nonce: [u8; NONCE_LENGTH],
+    pub request: [u8; 32],
+    pub timestamp: DateTime<Utc>,
+    pub response: String,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
+
+impl SignedAnswer {
+    /// Wraps the response and signs it with the identity of the server.
+    pub fn sign(
+        query: &Query,
+        response: String,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut answer = Self {
+            id: query.id,
+            nonce: query.nonce,
+            request: request_digest(query.request),
+            timestamp: Utc::now(),
+            response,
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        answer.signature = private_id.sign(&answer.signable_bytes());
+        answer
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer
+            .put_u16(self.id)
+            .put_fixed(&self.nonce)
+            .put_fixed(&self.request)
+            .put_time(&self.timestamp)
+            .put_str(&self.response)
+            .put_address(&self.server);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    /// Checks the signature against the key of the server.
+    pub fn verify(&self, server_key: &VerifyingKey) -> bool {
+        server_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            id: reader.get_u16()?,
+            nonce: reader.get_array()?,
+            request: reader.get_array()?,
+            timestamp: reader.get_time()?,
+            response: reader.get_str()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: &str = "000102030405060708090a0b0c0d0e0f";
+
+    fn signed(payload: &str) -> (SignedAnswer, VerifyingKey) {
+        let server = PrivateIdentity::new_from_name("server");
+        let query = Query::parse(payload).unwrap();
+        let destination = AddressHash::new([1u8; 16]);
+        let answer = SignedAnswer::sign(&query, "PONG".to_owned(), destination, &server);
+        (answer, server.as_identity().verifying_key)
+    }
+
+    #[test]
+    fn query_is_parsed_with_and_without_flags() {
+        let payload = format!("QUERY 7 {NONCE} LOOKUP a.node");
+        let query = Query::parse(&payload).unwrap();
+        assert_eq!((query.id, query.request), (7, "LOOKUP a.node"));
+        assert_eq!(query.flags, QueryFlags::default());
+
+        let payload = format!("QUERY 7 {NONCE} +hops=2 LOOKUP a.node");
+        let query = Query::parse(&payload).unwrap();
+        assert_eq!(query.flags.hops, 2);
+        assert_eq!(query.format(), payload);
+    }
+
+    #[test]
+    fn malformed_query_is_not_parsed() {
+        assert!(Query::parse("LOOKUP a.node").is_none());
+        assert!(Query::parse("QUERY 7 0001 LOOKUP a.node").is_none());
+        assert!(Query::parse(&format!("QUERY x {NONCE} LOOKUP a.node")).is_none());
+    }
+
+    #[test]
+    fn answer_survives_encoding() {
+        let (answer, key) = signed(&format!("QUERY 7 {NONCE} PING"));
+        let decoded = SignedAnswer::decode(&mut Reader::new(&answer.encode())).unwrap();
+        assert!(decoded.verify(&key));
+        assert_eq!((decoded.id, decoded.response.as_str()), (7, "PONG"));
+    }
+
+    #[test]
+    fn answer_is_bound_to_id_nonce_and_request() {
+        let (answer, key) = signed(&format!("QUERY 7 {NONCE} PING"));
+        assert!(answer.verify(&key));
+
+        let mut other = answer.clone();
+        other.id = 8;
+        assert!(!other.verify(&key));
+
+        let mut other = answer.clone();
+        other.nonce[0] ^= 1;
+        assert!(!other.verify(&key));
+
+        let mut other = answer;
+        other.request = request_digest("LOOKUP a.node");
+        assert!(!other.verify(&key));
+    }
+}
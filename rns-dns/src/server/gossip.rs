@@ -0,0 +1,477 @@
+//! Replication of the zones of servers that all accept registrations, by
+//! anti-entropy gossip between configured peers.
+//!
+//! Every peer regularly sends each of the others the hashes of its names in
+//! `BUCKETS` buckets, `GOSSIP <digest>`. The other peer answers with the
+//! versions of its names in the buckets that differ, `GOSSIP <summary>`, or with
+//! `INSYNC <zone>` should none differ. From the summary the first peer works out
+//! which names the other one is missing or holds an older version of and sends
+//! them, `GOSSIP <records>`. The names it is missing or holds an older version
+//! of itself it asks for with `WANT <zone> <name>...`, which is answered with
+//! `GOSSIP <records>` as well.
+//!
+//! Every message is signed by the peer that sent it and only accepted from the
+//! configured peers. The entries and removals inside are signed by their owners,
+//! so a peer can hold them back but it can not forge them.
+//!
+//! Both peers keep the version that `wins`, which only depends on the versions
+//! themselves. Whichever order the messages arrive in, the peers end up holding
+//! the same version of every name.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, TimeDelta, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+use sha2::{Digest, Sha256};
+
+use crate::server::server::DnsEntry;
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+/// The number of buckets the names of a zone are hashed into.
+pub const BUCKETS: usize = 16;
+
+/// The length of the hash of a bucket in a digest.
+const BUCKET_HASH_LENGTH: usize = 8;
+
+/// The most names sent in a single message with records.
+pub const RECORDS_PAGE_SIZE: usize = 8;
+
+/// The most names asked for in a single `WANT` request.
+pub const WANT_BATCH: usize = 16;
+
+/// How far the timestamp of a message may lie from the time it is received.
+pub const MAX_GOSSIP_AGE: TimeDelta = TimeDelta::minutes(5);
+
+/// The bucket a name is hashed into.
+pub fn bucket_of(name: &str) -> u8 {
+    Sha256::digest(name.as_bytes())[0] % BUCKETS as u8
+}
+
+/// The version of a name, which decides which one is kept should two peers hold
+/// different ones.
+///
+/// # Fields
+/// `serial` - The serial the owner gave the entry.
+/// `signature` - The signature of the owner over the entry, or over its removal
+/// should it be removed, see `registration::removal_bytes`.
+/// `expiry` - The timestamp at which the entry ceases to be valid.
+/// `removed` - Whether the entry of this version was removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub serial: u64,
+    pub signature: Signature,
+    pub expiry: DateTime<Utc>,
+    pub removed: bool,
+}
+
+impl Version {
+    pub fn of(entry: &DnsEntry) -> Self {
+        Self {
+            serial: entry.serial(),
+            signature: *entry.signature(),
+            expiry: entry.expiry(),
+            removed: false,
+        }
+    }
+
+    /// The version of the removal of `entry`, signed by its owner with
+    /// `signature`.
+    pub fn removal(entry: &DnsEntry, signature: Signature) -> Self {
+        Self {
+            signature,
+            removed: true,
+            ..Self::of(entry)
+        }
+    }
+
+    /// Whether this version is kept over `other`.
+    ///
+    /// # Reasoning
+    ///
+    /// The highest serial wins since it is the latest change the owner made, and
+    /// the removal of an entry wins over the entry itself. Two entries with the
+    /// same serial but different signatures were made independently, e.g. by an
+    /// owner that sent two different updates to two peers at once, then the
+    /// lexically smaller signature wins. An entry that was renewed keeps its
+    /// signature, so the later expiry wins last. Should all of these be equal the
+    /// versions are the same.
+    pub fn wins(&self, other: &Version) -> bool {
+        let order = self
+            .serial
+            .cmp(&other.serial)
+            .then(self.removed.cmp(&other.removed))
+            .then(other.signature.to_bytes().cmp(&self.signature.to_bytes()))
+            .then(self.expiry.cmp(&other.expiry));
+        order == Ordering::Greater
+    }
+
+    fn encode_into(&self, writer: &mut Writer) {
+        writer
+            .put_u64(self.serial)
+            .put_signature(&self.signature)
+            .put_time(&self.expiry)
+            .put_bool(self.removed);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            serial: reader.get_u64()?,
+            signature: reader.get_signature()?,
+            expiry: reader.get_time()?,
+            removed: reader.get_bool()?,
+        })
+    }
+}
+
+/// The hash of every bucket over the names in it and their versions. The
+/// versions have to be sorted by name.
+pub fn digest(versions: &[(String, Version)]) -> [[u8; BUCKET_HASH_LENGTH]; BUCKETS] {
+    let mut hashers: Vec<Sha256> = (0..BUCKETS).map(|_| Sha256::new()).collect();
+    for (name, version) in versions {
+        let mut writer = Writer::new();
+        writer.put_str(name);
+        version.encode_into(&mut writer);
+        hashers[bucket_of(name) as usize].update(writer.finish());
+    }
+    let mut buckets = [[0u8; BUCKET_HASH_LENGTH]; BUCKETS];
+    for (bucket, hasher) in buckets.iter_mut().zip(hashers) {
+        bucket.copy_from_slice(&hasher.finalize()[..BUCKET_HASH_LENGTH]);
+    }
+    buckets
+}
+
+/// The buckets in which two digests differ.
+pub fn differing(
+    ours: &[[u8; BUCKET_HASH_LENGTH]; BUCKETS],
+    theirs: &[[u8; BUCKET_HASH_LENGTH]; BUCKETS],
+) -> Vec<u8> {
+    (0..BUCKETS)
+        .filter(|bucket| ours[*bucket] != theirs[*bucket])
+        .map(|bucket| bucket as u8)
+        .collect()
+}
+
+/// The versions of the names that lie in one of `buckets`.
+pub fn in_buckets(versions: &[(String, Version)], buckets: &[u8]) -> Vec<(String, Version)> {
+    versions
+        .iter()
+        .filter(|(name, _)| buckets.contains(&bucket_of(name)))
+        .cloned()
+        .collect()
+}
+
+/// Compares the versions a peer holds in some buckets with those held locally
+/// in the same buckets and returns the names to send to the peer and the names
+/// to ask it for.
+pub fn reconcile(
+    ours: &[(String, Version)],
+    theirs: &[(String, Version)],
+) -> (Vec<String>, Vec<String>) {
+    let newer = |a: &[(String, Version)], b: &[(String, Version)]| -> Vec<String> {
+        let b: HashMap<&str, &Version> = b
+            .iter()
+            .map(|(name, version)| (name.as_str(), version))
+            .collect();
+        a.iter()
+            .filter(|(name, version)| b.get(name.as_str()).is_none_or(|other| version.wins(other)))
+            .map(|(name, _)| name.clone())
+            .collect()
+    };
+    (newer(ours, theirs), newer(theirs, ours))
+}
+
+/// What a gossip message carries.
+#[derive(Clone)]
+pub enum Payload {
+    /// The hash of every bucket, see `digest`.
+    Digest([[u8; BUCKET_HASH_LENGTH]; BUCKETS]),
+    /// The versions of the names in the buckets that differ.
+    Summary {
+        buckets: Vec<u8>,
+        versions: Vec<(String, Version)>,
+    },
+    /// The entries and removals the other peer is missing.
+    Records {
+        entries: Vec<DnsEntry>,
+        removals: Vec<(String, Version)>,
+    },
+}
+
+impl Payload {
+    fn encode_into(&self, writer: &mut Writer) {
+        let encode_versions = |writer: &mut Writer, versions: &[(String, Version)]| {
+            writer.put_u32(versions.len() as u32);
+            for (name, version) in versions {
+                writer.put_str(name);
+                version.encode_into(writer);
+            }
+        };
+        match self {
+            Self::Digest(buckets) => {
+                writer.put_u8(0);
+                for bucket in buckets {
+                    writer.put_fixed(bucket);
+                }
+            }
+            Self::Summary { buckets, versions } => {
+                writer.put_u8(1).put_bytes(buckets);
+                encode_versions(writer, versions);
+            }
+            Self::Records { entries, removals } => {
+                writer.put_u8(2).put_u32(entries.len() as u32);
+                for entry in entries {
+                    writer.put_bytes(&entry.encode());
+                }
+                encode_versions(writer, removals);
+            }
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let decode_versions = |reader: &mut Reader| {
+            (0..reader.get_u32()?)
+                .map(|_| Ok((reader.get_str()?, Version::decode(reader)?)))
+                .collect::<Result<Vec<_>, CodecError>>()
+        };
+        match reader.get_u8()? {
+            0 => {
+                let mut buckets = [[0u8; BUCKET_HASH_LENGTH]; BUCKETS];
+                for bucket in buckets.iter_mut() {
+                    *bucket = reader.get_array()?;
+                }
+                Ok(Self::Digest(buckets))
+            }
+            1 => Ok(Self::Summary {
+                buckets: reader.get_bytes()?.to_vec(),
+                versions: decode_versions(reader)?,
+            }),
+            2 => {
+                let entries = (0..reader.get_u32()?)
+                    .map(|_| DnsEntry::decode(&mut Reader::new(reader.get_bytes()?)))
+                    .collect::<Result<Vec<_>, CodecError>>()?;
+                Ok(Self::Records {
+                    entries,
+                    removals: decode_versions(reader)?,
+                })
+            }
+            _ => Err(CodecError::InvalidData),
+        }
+    }
+}
+
+/// A gossip message, signed by the peer that sent it.
+///
+/// # Fields
+/// `zone` - The suffix of the zone, empty for the primary zone.
+/// `payload` - What the message carries.
+/// `timestamp` - The time at which the message was signed.
+/// `server` - The destination of the peer.
+/// `signature` - The signature of the peer over all of the above.
+///
+/// # Reasoning
+///
+/// Messages are signed by the identity the peer announces rather than by the
+/// zone, the same as answers, so that a peer is known by a single key whichever
+/// zones it hosts.
+#[derive(Clone)]
+pub struct Gossip {
+    pub zone: String,
+    pub payload: Payload,
+    pub timestamp: DateTime<Utc>,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
+
+impl Gossip {
+    pub fn sign(
+        zone: String,
+        payload: Payload,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut gossip = Self {
+            zone,
+            payload,
+            timestamp: Utc::now(),
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        gossip.signature = private_id.sign(&gossip.signable_bytes());
+        gossip
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer.put_str(&self.zone);
+        self.payload.encode_into(writer);
+        writer.put_time(&self.timestamp).put_address(&self.server);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    /// Checks that the message is signed by the peer and was signed recently, so
+    /// that an old message can not be replayed much later.
+    pub fn check(&self, peer_key: &VerifyingKey, now: DateTime<Utc>) -> bool {
+        now - self.timestamp <= MAX_GOSSIP_AGE
+            && self.timestamp - now <= MAX_GOSSIP_AGE
+            && peer_key
+                .verify_strict(&self.signable_bytes(), &self.signature)
+                .is_ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            zone: reader.get_str()?,
+            payload: Payload::decode(reader)?,
+            timestamp: reader.get_time()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+
+    /// The message as it is sent, `GOSSIP <message>`.
+    pub fn to_message(&self) -> String {
+        format!("GOSSIP {}", URL_SAFE_NO_PAD.encode(self.encode()))
+    }
+}
+
+/// Decodes the payload of a `GOSSIP` message.
+pub fn decode_gossip(payload: &str) -> Option<Gossip> {
+    let bytes = URL_SAFE_NO_PAD.decode(payload.trim()).ok()?;
+    Gossip::decode(&mut Reader::new(&bytes)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(serial: u64, signature: u8, expiry: i64, removed: bool) -> Version {
+        Version {
+            serial,
+            signature: Signature::from_bytes(&[signature; 64]),
+            expiry: DateTime::from_timestamp(expiry, 0).unwrap(),
+            removed,
+        }
+    }
+
+    fn versions(entries: &[(&str, Version)]) -> Vec<(String, Version)> {
+        entries
+            .iter()
+            .map(|(name, version)| (name.to_string(), *version))
+            .collect()
+    }
+
+    #[test]
+    fn higher_serial_wins() {
+        let newer = version(2, 9, 0, false);
+        let older = version(1, 1, 100, true);
+        assert!(newer.wins(&older));
+        assert!(!older.wins(&newer));
+    }
+
+    #[test]
+    fn removal_wins_over_entry_of_same_serial() {
+        let removal = version(1, 9, 0, true);
+        let entry = version(1, 1, 100, false);
+        assert!(removal.wins(&entry));
+        assert!(!entry.wins(&removal));
+    }
+
+    #[test]
+    fn smaller_signature_wins() {
+        let smaller = version(1, 1, 0, false);
+        let larger = version(1, 2, 100, false);
+        assert!(smaller.wins(&larger));
+        assert!(!larger.wins(&smaller));
+    }
+
+    #[test]
+    fn later_expiry_wins() {
+        let renewed = version(1, 1, 100, false);
+        let entry = version(1, 1, 0, false);
+        assert!(renewed.wins(&entry));
+        assert!(!entry.wins(&renewed));
+    }
+
+    #[test]
+    fn same_version_does_not_win() {
+        let version = version(1, 1, 0, false);
+        assert!(!version.wins(&version));
+    }
+
+    #[test]
+    fn reconcile_sends_newer_and_asks_for_missing() {
+        let ours = versions(&[
+            ("a.node", version(2, 1, 0, false)),
+            ("b.node", version(1, 1, 0, false)),
+            ("c.node", version(1, 1, 0, false)),
+        ]);
+        let theirs = versions(&[
+            ("a.node", version(1, 1, 0, false)),
+            ("b.node", version(1, 1, 0, true)),
+            ("c.node", version(1, 1, 0, false)),
+            ("d.node", version(1, 1, 0, false)),
+        ]);
+        let (send, want) = reconcile(&ours, &theirs);
+        assert_eq!(send, ["a.node"]);
+        assert_eq!(want, ["b.node", "d.node"]);
+    }
+
+    #[test]
+    fn digest_differs_only_in_changed_bucket() {
+        let ours = versions(&[("a.node", version(1, 1, 0, false))]);
+        let theirs = versions(&[("a.node", version(2, 1, 0, false))]);
+        assert!(differing(&digest(&ours), &digest(&ours)).is_empty());
+        assert_eq!(differing(&digest(&ours), &digest(&theirs)), [bucket_of("a.node")]);
+    }
+
+    #[test]
+    fn message_survives_encoding() {
+        let peer = PrivateIdentity::new_from_name("peer");
+        let server = AddressHash::new([1u8; 16]);
+        let payload = Payload::Summary {
+            buckets: vec![bucket_of("a.node")],
+            versions: versions(&[("a.node", version(1, 1, 0, true))]),
+        };
+        let message = Gossip::sign("node".to_owned(), payload, server, &peer);
+        let decoded = decode_gossip(message.to_message().strip_prefix("GOSSIP ").unwrap()).unwrap();
+        assert!(decoded.check(&peer.as_identity().verifying_key, Utc::now()));
+        let Payload::Summary { versions, .. } = decoded.payload else {
+            panic!("decoded another payload");
+        };
+        assert_eq!(versions[0].1, version(1, 1, 0, true));
+    }
+
+    #[test]
+    fn message_of_another_peer_is_rejected() {
+        let peer = PrivateIdentity::new_from_name("peer");
+        let other = PrivateIdentity::new_from_name("other");
+        let server = AddressHash::new([1u8; 16]);
+        let message = Gossip::sign("node".to_owned(), Payload::Digest(digest(&[])), server, &peer);
+        assert!(!message.check(&other.as_identity().verifying_key, Utc::now()));
+    }
+
+    #[test]
+    fn old_message_is_rejected() {
+        let peer = PrivateIdentity::new_from_name("peer");
+        let server = AddressHash::new([1u8; 16]);
+        let message = Gossip::sign("node".to_owned(), Payload::Digest(digest(&[])), server, &peer);
+        let later = Utc::now() + MAX_GOSSIP_AGE + TimeDelta::seconds(1);
+        assert!(!message.check(&peer.as_identity().verifying_key, later));
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use reticulum::hash::AddressHash;
+
+use crate::server::server::DnsDatabaseRaw;
+use crate::utilites::codec::to_hex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The owner signature of the entry does not validate.
+    InvalidEntrySignature,
+    /// The entry is stored under a different name than it carries.
+    NameMismatch,
+    /// The expiry of the entry has passed.
+    Expired,
+    /// The entry does not point to any destination.
+    NoDestinations,
+    /// A destination of an entry is missing from the reverse index.
+    MissingReverseEntry,
+    /// The reverse index points to a name that does not point back.
+    StaleReverseEntry,
+    /// A verification is stored for a name and destination that do not exist.
+    OrphanedVerification,
+    /// A verification was made by a verifier which is not in the registry.
+    UnknownVerifier,
+    /// The signature of a verification does not validate.
+    InvalidVerifierSignature,
+}
+
+impl ProblemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidEntrySignature => "invalid_entry_signature",
+            Self::NameMismatch => "name_mismatch",
+            Self::Expired => "expired",
+            Self::NoDestinations => "no_destinations",
+            Self::MissingReverseEntry => "missing_reverse_entry",
+            Self::StaleReverseEntry => "stale_reverse_entry",
+            Self::OrphanedVerification => "orphaned_verification",
+            Self::UnknownVerifier => "unknown_verifier",
+            Self::InvalidVerifierSignature => "invalid_verifier_signature",
+        }
+    }
+}
+
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// The domain name the problem was found at.
+    pub name: String,
+    /// The hex encoded destination involved, should there be one.
+    pub destination: Option<String>,
+}
+
+/// The result of an integrity check over a `DnsDatabaseRaw`.
+#[derive(Default)]
+pub struct IntegrityReport {
+    pub entries_checked: usize,
+    pub verifications_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn push(&mut self, kind: ProblemKind, name: &str, destination: Option<&AddressHash>) {
+        self.problems.push(Problem {
+            kind,
+            name: name.to_owned(),
+            destination: destination.map(|d| to_hex(d.as_slice())),
+        });
+    }
+
+    /// Renders the report as a single JSON object.
+    pub fn to_json(&self) -> String {
+        let problems = self
+            .problems
+            .iter()
+            .map(|p| {
+                format!(
+                    r#"{{"kind":"{}","name":"{}","destination":{}}}"#,
+                    p.kind.as_str(),
+                    json_escape(&p.name),
+                    p.destination
+                        .as_ref()
+                        .map(|d| format!(r#""{d}""#))
+                        .unwrap_or("null".into()),
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+
+        format!(
+            r#"{{"ok":{},"entries_checked":{},"verifications_checked":{},"problems":[{}]}}"#,
+            self.is_ok(),
+            self.entries_checked,
+            self.verifications_checked,
+            problems
+        )
+    }
+}
+
+fn json_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Checks the database for inconsistencies.
+///
+/// # Behaviour
+///
+/// Every entry signature and every verifier signing is validated again, both the
+/// signings attached to the entries and the ones in the `VerificationStore`. The
+/// reverse index is compared against the forward index in both directions.
+///
+/// This function never modifies the database, it only reports.
+pub fn check_database(raw: &DnsDatabaseRaw, now: DateTime<Utc>) -> IntegrityReport {
+    let mut report = IntegrityReport::default();
+    let entry_store = raw.entry_store();
+    let registry = raw.verifier_registry();
+
+    let mut forward_pairs: HashSet<(&str, AddressHash)> = HashSet::new();
+
+    for (name, entry) in entry_store.iter_forward_index() {
+        report.entries_checked += 1;
+
+        if name != entry.name() {
+            report.push(ProblemKind::NameMismatch, name, None);
+        }
+        if !entry.verify_signature() {
+            report.push(ProblemKind::InvalidEntrySignature, name, None);
+        }
+        if entry.expiry() < now {
+            report.push(ProblemKind::Expired, name, None);
+        }
+        if entry.destinations().is_empty() {
+            report.push(ProblemKind::NoDestinations, name, None);
+        }
+
+        for destination in entry.destinations() {
+            forward_pairs.insert((name.as_str(), *destination));
+            let listed = entry_store
+                .reverse_lookup(destination)
+                .is_some_and(|names| names.contains(name));
+            if !listed {
+                report.push(ProblemKind::MissingReverseEntry, name, Some(destination));
+            }
+        }
+
+        for signing in entry.verifications() {
+            report.verifications_checked += 1;
+            match registry.get_verifier(signing.destination()) {
+                None => report.push(
+                    ProblemKind::UnknownVerifier,
+                    name,
+                    Some(signing.destination()),
+                ),
+                Some(verifier) if !signing.verify(entry, verifier) => report.push(
+                    ProblemKind::InvalidVerifierSignature,
+                    name,
+                    Some(signing.destination()),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+
+    for (destination, names) in entry_store.iter_reverse_index() {
+        for name in names {
+            if !forward_pairs.contains(&(name.as_str(), *destination)) {
+                report.push(ProblemKind::StaleReverseEntry, name, Some(destination));
+            }
+        }
+    }
+
+    for ((name, destination), signings) in raw.verification_store().iter() {
+        let Some(entry) = entry_store
+            .lookup(name)
+            .filter(|e| e.destinations().contains(destination))
+        else {
+            report.push(ProblemKind::OrphanedVerification, name, Some(destination));
+            continue;
+        };
+
+        for signing in signings {
+            report.verifications_checked += 1;
+            match registry.get_verifier(signing.destination()) {
+                None => report.push(
+                    ProblemKind::UnknownVerifier,
+                    name,
+                    Some(signing.destination()),
+                ),
+                Some(verifier) if !signing.verify(entry, verifier) => report.push(
+                    ProblemKind::InvalidVerifierSignature,
+                    name,
+                    Some(signing.destination()),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use reticulum::identity::PrivateIdentity;
+
+    use super::*;
+    use crate::server::audit::AuditLog;
+    use crate::server::policy::EntryPolicy;
+    use crate::server::registration;
+    use crate::server::server::{
+        DnsDatabase, DnsEntry, RECORD_EXPIRY, Requester, VerificationStore, Verifier,
+        VerifierRegistry, VerifierSigning,
+    };
+    use crate::utilites::codec::Writer;
+
+    fn destination() -> AddressHash {
+        AddressHash::new([2u8; 16])
+    }
+
+    fn entry(owner: &PrivateIdentity) -> DnsEntry {
+        DnsEntry::new_signed("a.node".to_owned(), vec![destination()], 1, owner)
+    }
+
+    /// A registry holding a single verifier and the store with its signing over
+    /// `entry`.
+    fn signed(entry: &DnsEntry) -> (VerificationStore, VerifierRegistry) {
+        let verifier = PrivateIdentity::new_from_name("verifier");
+        let address = AddressHash::new([7u8; 16]);
+        let key = verifier.as_identity().verifying_key;
+        let mut registry = VerifierRegistry::default();
+        registry
+            .add_verifier(Verifier::new("verifier".to_owned(), address, 1, key))
+            .unwrap();
+        let mut store = VerificationStore::default();
+        let signing = VerifierSigning::sign(entry, address, &verifier);
+        store
+            .add_verification(entry, destination(), signing, &registry)
+            .unwrap();
+        (store, registry)
+    }
+
+    /// Writes a database file by hand so that the reverse index can differ from
+    /// what the forward index implies, as it would in a file left by a faulty
+    /// server.
+    fn database(
+        entries: &[(&str, Vec<u8>)],
+        reverse: &[(AddressHash, &str)],
+        store: &VerificationStore,
+        registry: &VerifierRegistry,
+    ) -> DnsDatabaseRaw {
+        let mut writer = Writer::new();
+        writer.put_fixed(b"RNSDNSDB").put_u8(3);
+        writer.put_u32(entries.len() as u32);
+        for (name, encoded) in entries {
+            writer.put_str(name).put_bytes(encoded);
+        }
+        writer.put_u32(reverse.len() as u32);
+        for (destination, name) in reverse {
+            writer.put_address(destination).put_u32(1).put_str(name);
+        }
+        store.encode_into(&mut writer);
+        registry.encode_into(&mut writer);
+        DnsDatabaseRaw::decode(&writer.finish()).unwrap()
+    }
+
+    fn kinds(report: &IntegrityReport) -> Vec<ProblemKind> {
+        report.problems.iter().map(|p| p.kind).collect()
+    }
+
+    #[test]
+    fn clean_database_reports_no_problems() {
+        let entry = entry(&PrivateIdentity::new_from_name("owner"));
+        let (store, registry) = signed(&entry);
+        let raw = database(
+            &[("a.node", entry.encode())],
+            &[(destination(), "a.node")],
+            &store,
+            &registry,
+        );
+
+        let report = check_database(&raw, Utc::now());
+        assert!(report.is_ok(), "{}", report.to_json());
+        assert_eq!(report.entries_checked, 1);
+        assert_eq!(report.verifications_checked, 1);
+    }
+
+    #[test]
+    fn database_kept_by_the_server_stays_clean() {
+        let signer = PrivateIdentity::new_from_name("server");
+        let policy = EntryPolicy::new(16, chrono::TimeDelta::zero(), 8);
+        let operator = AddressHash::new([1u8; 16]);
+        let database = DnsDatabase::with_policy(operator, policy, AuditLog::ephemeral(signer));
+        let owner = PrivateIdentity::new_from_name("owner");
+        let link = AddressHash::new([5u8; 16]);
+        let requester = Requester::on_link(link, Some(owner.as_identity().address_hash));
+
+        for name in ["a.node", "b.node"] {
+            let entry = DnsEntry::new_signed(name.to_owned(), vec![destination()], 1, &owner);
+            let challenge = database.issue_challenge(&link);
+            let proof = owner.sign(&registration::proof_bytes(&challenge, &entry));
+            database.register_entry(&requester, entry, &proof).unwrap();
+        }
+        let moved = AddressHash::new([3u8; 16]);
+        let update = DnsEntry::new_signed("a.node".to_owned(), vec![moved], 2, &owner);
+        database.override_entry(&requester, update).unwrap();
+        let signature = owner.sign(&registration::removal_bytes("b.node", 1));
+        database
+            .remove_domain(&requester, "b.node", &signature)
+            .unwrap();
+        database.promote_staging().unwrap();
+
+        let report = check_database(&database.active(), Utc::now());
+        assert!(report.is_ok(), "{}", report.to_json());
+        assert_eq!(report.entries_checked, 1);
+    }
+
+    #[test]
+    fn tampered_entry_signature_is_reported() {
+        let entry = entry(&PrivateIdentity::new_from_name("owner"));
+        let mut encoded = entry.encode();
+        // the signature is followed only by the count of the verifications
+        let at = encoded.len() - 5;
+        encoded[at] ^= 0xff;
+        let raw = database(
+            &[("a.node", encoded)],
+            &[(destination(), "a.node")],
+            &VerificationStore::default(),
+            &VerifierRegistry::default(),
+        );
+
+        let report = check_database(&raw, Utc::now());
+        assert_eq!(kinds(&report), vec![ProblemKind::InvalidEntrySignature]);
+    }
+
+    #[test]
+    fn reverse_index_drift_is_reported_in_both_directions() {
+        let entry = entry(&PrivateIdentity::new_from_name("owner"));
+        let other = AddressHash::new([3u8; 16]);
+        let raw = database(
+            &[("a.node", entry.encode())],
+            &[(other, "a.node")],
+            &VerificationStore::default(),
+            &VerifierRegistry::default(),
+        );
+
+        let report = check_database(&raw, Utc::now());
+        assert_eq!(
+            kinds(&report),
+            vec![
+                ProblemKind::MissingReverseEntry,
+                ProblemKind::StaleReverseEntry
+            ]
+        );
+        assert_eq!(
+            report.problems[1].destination,
+            Some(to_hex(other.as_slice()))
+        );
+    }
+
+    #[test]
+    fn signing_of_unknown_verifier_is_reported() {
+        let entry = entry(&PrivateIdentity::new_from_name("owner"));
+        let (store, mut registry) = signed(&entry);
+        registry
+            .remove_verifier(&AddressHash::new([7u8; 16]))
+            .unwrap();
+        let raw = database(
+            &[("a.node", entry.encode())],
+            &[(destination(), "a.node")],
+            &store,
+            &registry,
+        );
+
+        let report = check_database(&raw, Utc::now());
+        assert_eq!(kinds(&report), vec![ProblemKind::UnknownVerifier]);
+    }
+
+    #[test]
+    fn expired_entry_is_reported() {
+        let entry = entry(&PrivateIdentity::new_from_name("owner"));
+        let raw = database(
+            &[("a.node", entry.encode())],
+            &[(destination(), "a.node")],
+            &VerificationStore::default(),
+            &VerifierRegistry::default(),
+        );
+
+        let later = Utc::now() + RECORD_EXPIRY + chrono::TimeDelta::days(1);
+        let report = check_database(&raw, later);
+        assert_eq!(kinds(&report), vec![ProblemKind::Expired]);
+    }
+}
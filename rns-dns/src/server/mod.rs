@@ -1,4 +1,20 @@
+pub mod acl;
+pub mod admin;
+pub mod announce;
+pub mod audit;
+pub mod delegation;
+pub mod denial;
+pub mod envelope;
+pub mod gossip;
+pub mod integrity;
 pub mod node;
 // pub mod payload_in;
 pub mod parser;
+pub mod policy;
+pub mod ratelimit;
+pub mod recursive;
+pub mod registration;
 pub mod server;
+pub mod transfer;
+pub mod transparency;
+pub mod zones;
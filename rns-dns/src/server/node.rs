@@ -1,22 +1,82 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Utc;
+use ed25519_dalek::VerifyingKey;
+use reticulum::identity::PrivateIdentity;
 use reticulum::iface::tcp_server::TcpServer;
+use tokio::sync::{Mutex, Notify};
 use tokio::time;
 
-use reticulum::destination::link::LinkEvent;
+use reticulum::destination::link::{Link, LinkEvent};
 use reticulum::iface::udp::UdpInterface;
 use reticulum::{destination::DestinationName, hash::AddressHash};
 //use reticulum::iface::tcp_server::TcpServer;
 use reticulum::transport::{Transport, TransportConfig};
 
-use crate::types::{self, Connection};
+use crate::resolver::answers::AnswerCache;
+use crate::resolver::envelope::AnswerError;
+use crate::server::acl::AccessList;
+use crate::server::announce::{AnnounceSchedule, Capabilities, PROTOCOL_VERSION};
+use crate::server::audit::AuditLog;
+use crate::server::delegation::TrustChain;
+use crate::server::envelope::{MAX_HOPS, Query, QueryFlags};
+use crate::server::gossip::{self, Gossip, Payload, RECORDS_PAGE_SIZE, WANT_BATCH};
+use crate::server::parser;
+use crate::server::ratelimit::{OverLimit, RateLimitKeys, RateLimiter};
+use crate::server::recursive::{self, Forwarder, Resolution, Upstreams, Waiting};
+use crate::server::registration;
+use crate::server::server::{DnsDatabase, DnsDatabaseRaw, RNSDNSERRORS, Requester, Verifier};
+use crate::server::transfer::{
+    Servers, TransferError, ZoneTransfer, changes_request, decode_change_page, decode_page,
+    zone_argument,
+};
+use crate::server::transparency::TransparencyLog;
+use crate::server::zones::{Zone, Zones, load_zones};
+use crate::types::{self, Connection, SecondaryConfig};
+use crate::utilites::codec::{Reader, to_hex};
+use crate::utilites::dedup::DuplicateFilter;
+use crate::utilites::error::RequestError;
+use crate::utilites::shutdown::{ExitStatus, SHUTDOWN_DEADLINE, Shutdown};
+
+/// How often pending notifications are sent to the verifiers.
+const NOTIFY_INTERVAL: time::Duration = time::Duration::from_secs(5);
+/// How often the staging database is promoted and persisted.
+const PROMOTE_INTERVAL: time::Duration = time::Duration::from_secs(10);
+/// How often the rate limit counters are logged.
+const STATS_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// How often a secondary syncs its zone from the primary without being notified.
+const TRANSFER_INTERVAL: time::Duration = time::Duration::from_secs(300);
+/// The shortest time between two syncs of a secondary, however often it is
+/// notified.
+const MIN_SYNC_INTERVAL: time::Duration = time::Duration::from_secs(10);
+/// How often the zones are compared with every peer.
+const GOSSIP_INTERVAL: time::Duration = time::Duration::from_secs(30);
 
 /// The router that handles routing between nodes on the local network. May be connected to other nodes.
+///
+/// # Behaviour
+///
+/// On SIGINT or SIGTERM the server stops announcing and refuses new links and
+/// queries. The queries already being answered get `SHUTDOWN_DEADLINE` to
+/// finish, then the staging databases of all zones are promoted and persisted
+/// and all links are closed.
 pub async fn start_server(
     node_settings: types::NodeSettings,
     destination_settings: types::DestinationConfig,
-) {
+    server_config: types::ServerConfig,
+) -> ExitStatus {
     log::info!("Starting RNS-DNS");
 
     let private_id = node_settings.private_identity.extract();
+    let zone_configs = match &server_config.zones_path {
+        Some(path) => load_zones(path).unwrap_or_else(|e| {
+            log::fatal!("failed to load zones {}: {e}", path.display());
+            std::process::exit(1);
+        }),
+        None => Vec::new(),
+    };
 
     // the label "router" is entirely cosmetic and does not affect the functionality in any way.
     let mut transport = Transport::new(TransportConfig::new("server", &private_id, true));
@@ -112,12 +172,204 @@ pub async fn start_server(
         destination_settings.application_space
     );
 
-    let announce_loop = async || loop {
-        log::trace!("SEND ANNOUNCE {}", destination_hash);
-        transport.send_announce(&destination, None).await;
-        time::sleep(time::Duration::from_secs(15)).await;
+    let primary = open_zone(
+        &server_config.primary_zone(),
+        &destination_settings.app_name,
+        destination_hash,
+        &private_id,
+    );
+    let mut zones = match primary {
+        Ok(primary) => Zones::new(primary),
+        Err(e) => {
+            log::fatal!("{e}");
+            return ExitStatus::Unexpected;
+        }
     };
+    for config in &zone_configs {
+        // a zone without an identity of its own signs as the server
+        let zone_id = config
+            .identity
+            .as_ref()
+            .map_or_else(|| private_id.clone(), |identity| identity.extract());
+        let server = zone_id.as_identity().address_hash;
+        let added = open_zone(config, &config.suffix, server, &zone_id)
+            .and_then(|zone| zones.add(zone));
+        if let Err(e) = added {
+            log::fatal!("{e}");
+            return ExitStatus::Unexpected;
+        }
+        log::info!("Serving zone {} as {server}", config.suffix);
+    }
+
+    let secondary = server_config.secondary.as_ref();
+    if let Some(secondary) = secondary
+        && zones.get(&secondary.zone).is_none()
+    {
+        log::fatal!("no zone {} to transfer into", secondary.zone);
+        return ExitStatus::Unexpected;
+    }
 
+    let resolver = server_config.resolver.as_ref();
+    let capabilities =
+        Capabilities::sign(&zones.suffixes(), resolver.is_some(), &private_id).encode();
+    let shutdown = Shutdown::default();
+    let announce_loop = async || {
+        let mut schedule = AnnounceSchedule::new(server_config.announce);
+        loop {
+            // a server that is going away should not attract new clients
+            if !shutdown.is_stopping() {
+                log::trace!("SEND ANNOUNCE {}", destination_hash);
+                transport
+                    .send_announce(&destination, Some(&capabilities))
+                    .await;
+            }
+            time::sleep(schedule.next_delay()).await;
+        }
+    };
+
+    let rate_limiter = Mutex::new(RateLimiter::new(server_config.rate_limit.clone()));
+    // copies of a query are recognised by the id and the nonce the requester
+    // chose, per requester so that nobody can suppress the query of another by
+    // sending its id and nonce first. Every path builds its own link, so copies
+    // are only recognised across paths for a requester that identified itself,
+    // anyone else is told apart by its link
+    let recent_queries = Mutex::new(DuplicateFilter::default());
+    // the primary sends its notices over every path as well
+    let recent_notices = Mutex::new(DuplicateFilter::default());
+    // the serial of the primary the zone of a secondary is in sync with, `None`
+    // until a full transfer completed
+    let synced: Mutex<Option<u64>> = Mutex::new(None);
+    // woken by the primary when the zone changed
+    let sync_now = Notify::new();
+    // a resolver forwards lookups of names it is not authoritative for to the
+    // upstream servers it links to
+    let upstreams = Mutex::new(Upstreams::default());
+    let upstream_links: Mutex<HashMap<AddressHash, Arc<Mutex<Link>>>> =
+        Mutex::new(HashMap::new());
+    let forwarder = Mutex::new(Forwarder::default());
+    let answers = Mutex::new(AnswerCache::default());
+    let resolve = async |link_id: AddressHash, payload: &str| {
+        let (Some(_), Some(query)) = (resolver, Query::parse(payload)) else {
+            return Resolution::Local;
+        };
+        let Some(name) = recursive::lookup_name(query.request) else {
+            return Resolution::Local;
+        };
+        if zones.is_authoritative(name) {
+            return Resolution::Local;
+        }
+        let answer = |response: String| {
+            Resolution::Answer(parser::wrap(&zones, Some(&query), response))
+        };
+        if query.flags.hops >= MAX_HOPS {
+            return answer(RequestError::TooManyHops.response());
+        }
+        let cached = answers
+            .lock()
+            .await
+            .get(query.request, Utc::now())
+            .map(str::to_owned);
+        if let Some(cached) = cached {
+            return answer(cached);
+        }
+
+        let links = upstream_links.lock().await;
+        let upstream = upstreams
+            .lock()
+            .await
+            .select(name, |destination| links.contains_key(destination));
+        let Some((upstream, link)) =
+            upstream.and_then(|upstream| Some((upstream, links.get(&upstream)?.clone())))
+        else {
+            return answer(RequestError::NoUpstream.response());
+        };
+        drop(links);
+        let flags = QueryFlags {
+            hops: query.flags.hops + 1,
+        };
+        let waiting = Waiting::new(link_id, &query, upstream);
+        let forwarded = forwarder.lock().await.forward(waiting, flags, Utc::now());
+        let packet = link.lock().await.data_packet(forwarded.as_bytes());
+        match packet {
+            Ok(packet) => {
+                log::trace!("FORWARDED {} to {upstream}", query.id);
+                transport.send_packet(packet).await;
+                Resolution::Forwarded
+            }
+            Err(_) => answer(RequestError::NoUpstream.response()),
+        }
+    };
+    // the answers of the upstream servers are validated before they are cached
+    // and passed on
+    let answer_forwarded = async |upstream: &AddressHash, payload: &str| {
+        let Some(resolver) = resolver else {
+            return;
+        };
+        let Some(key) = upstreams.lock().await.get(upstream).map(|upstream| upstream.key) else {
+            return;
+        };
+        let now = Utc::now();
+        let accepted = forwarder.lock().await.accept(upstream, payload, &key, now);
+        let (waiting, response) = match accepted {
+            Ok(accepted) => accepted,
+            // the same answer arrived over another path
+            Err(AnswerError::Duplicate) => return,
+            Err(e) => {
+                log::warn!("dropped answer of upstream {upstream}: {e:?}");
+                return;
+            }
+        };
+
+        let name = recursive::lookup_name(&waiting.request).unwrap_or_default();
+        let response = match recursive::validate(&response, name, &key, &resolver.anchors, now) {
+            Ok(expires) => {
+                if let Some(expires) = expires {
+                    let request = waiting.request.clone();
+                    answers
+                        .lock()
+                        .await
+                        .insert(request, response.clone(), expires, now);
+                }
+                response
+            }
+            Err(e) => {
+                log::warn!("invalid answer of upstream {upstream} for {name}: {e:?}");
+                RequestError::InvalidUpstreamAnswer.response()
+            }
+        };
+        let response = parser::wrap(&zones, Some(&waiting.query()), response);
+        let Some(link) = transport.find_in_link(&waiting.link).await else {
+            return;
+        };
+        let packet = link.lock().await.data_packet(response.as_bytes());
+        if let Ok(packet) = packet {
+            transport.send_packet(packet).await;
+        }
+    };
+    // the peers accept registrations as well, their entries are replicated by
+    // gossip and a message is only accepted when signed with the key the peer
+    // announced
+    let peer_links: Mutex<HashMap<AddressHash, Arc<Mutex<Link>>>> =
+        Mutex::new(HashMap::new());
+    let peer_keys: Mutex<HashMap<AddressHash, VerifyingKey>> = Mutex::new(HashMap::new());
+    // the identities of the configured secondaries and peers, only they may
+    // transfer the zones
+    let servers = Mutex::new(Servers::default());
+    let handle_gossip = async |payload: &str| {
+        let Some(message) = gossip::decode_gossip(payload) else {
+            return Vec::new();
+        };
+        let key = peer_keys.lock().await.get(&message.server).copied();
+        if !key.is_some_and(|key| message.check(&key, Utc::now())) {
+            log::warn!("dropped gossip of {}", message.server);
+            return Vec::new();
+        }
+        replicate(&zones, message)
+    };
+    // the links opened by clients, closed on shutdown
+    let in_links: Mutex<HashMap<AddressHash, Arc<Mutex<Link>>>> = Mutex::new(HashMap::new());
+    // the identities the requesters proved on their links, see `IDENTIFY`
+    let identities: Mutex<HashMap<AddressHash, AddressHash>> = Mutex::new(HashMap::new());
     let in_event_loop = async || {
         let mut in_link_events = transport.in_link_events();
         while let Ok(link_event) = in_link_events.recv().await {
@@ -131,27 +383,125 @@ pub async fn start_server(
                         payload
                     );
 
+                    let Some(_in_flight) = shutdown.begin() else {
+                        log::trace!("REFUSED QUERY ({}) during shutdown", link_event.id);
+                        continue;
+                    };
+
                     // link
                     let link_id = link_event.id;
                     let link = transport.find_in_link(&link_id).await.unwrap();
-                    let link = link.lock().await;
 
-                    // response
-                    let pong = link
-                        .data_packet(format!("server-response").as_bytes())
-                        .unwrap();
-                    drop(link);
-                    // send
-                    transport.send_packet(pong).await;
+                    // a peer that identified itself is limited over all of its links
+                    let identity = identities.lock().await.get(&link_id).copied();
+                    let keys = RateLimitKeys {
+                        link: link_id,
+                        identity,
+                    };
+                    let mut rate_limiter = rate_limiter.lock().await;
+                    let limited = rate_limiter.check(&keys, Instant::now());
+                    let over_limit = rate_limiter.over_limit();
+                    drop(rate_limiter);
+                    let requester = Requester::on_link(link_id, identity);
+
+                    // copies count against the rate limit like any other query,
+                    // and only a query the limit let through is remembered
+                    if limited.is_ok()
+                        && let Some(query) = Query::parse(payload)
+                        && !recent_queries.lock().await.first_seen(
+                            (identity.unwrap_or(link_id), query.id, query.nonce),
+                            Instant::now(),
+                        )
+                    {
+                        log::trace!("DUPLICATE QUERY {} ({link_id})", query.id);
+                        continue;
+                    }
+
+                    // a notice only makes a secondary sync earlier, the changes
+                    // themselves are signed by the zone, and is never answered
+                    if let Some(notice) = payload.strip_prefix("NOTIFY ") {
+                        if limited.is_ok()
+                            && servers.lock().await.require_primary(&requester).is_ok()
+                            && let (Some(secondary), Some((zone, serial))) =
+                                (secondary, parse_notice(notice))
+                            && zone == zone_argument(&secondary.zone)
+                            && recent_notices.lock().await.first_seen(serial, Instant::now())
+                            && synced.lock().await.is_none_or(|synced| synced < serial)
+                        {
+                            log::trace!("NOTIFIED of serial {serial} ({link_id})");
+                            sync_now.notify_one();
+                        }
+                        continue;
+                    }
+
+                    // a peer is answered with as many messages as there are
+                    // differences
+                    if let Some(message) = payload.strip_prefix("GOSSIP ") {
+                        let replies = match limited.is_ok() {
+                            true => handle_gossip(message).await,
+                            false => Vec::new(),
+                        };
+                        for reply in replies {
+                            let packet = link.lock().await.data_packet(reply.as_bytes());
+                            if let Ok(packet) = packet {
+                                transport.send_packet(packet).await;
+                            }
+                        }
+                        continue;
+                    }
+
+                    // response, failed requests are answered with their error code
+                    let response = match limited {
+                        Ok(()) if payload.starts_with("IDENTIFY ") => {
+                            match parser::identify(&zones, &requester, payload) {
+                                Ok(identity) => {
+                                    identities.lock().await.insert(link_id, identity);
+                                    format!("IDENTIFIED {}", to_hex(identity.as_slice()))
+                                }
+                                Err(e) => e.response(),
+                            }
+                        }
+                        Ok(()) => match resolve(link_id, payload).await {
+                            Resolution::Local => {
+                                let servers = servers.lock().await;
+                                parser::respond(&zones, &servers, &requester, payload)
+                            }
+                            Resolution::Answer(response) => response,
+                            // answered once the upstream server answered
+                            Resolution::Forwarded => continue,
+                        },
+                        Err(scope) => {
+                            log::trace!("RATE LIMITED {link_id} by {scope:?}");
+                            if over_limit == OverLimit::Drop {
+                                continue;
+                            }
+                            RequestError::RateLimited.response()
+                        }
+                    };
+                    let packet = link.lock().await.data_packet(response.as_bytes());
+                    match packet {
+                        Ok(packet) => transport.send_packet(packet).await,
+                        Err(_) => log::warn!("response to {link_id} does not fit a packet"),
+                    }
                 }
                 LinkEvent::Activated => {
                     log::trace!(
                         "IN LINK ACTIVATED {} ({})",
                         link_event.address_hash,
                         link_event.id
-                    )
+                    );
+                    let Some(link) = transport.find_in_link(&link_event.id).await else {
+                        continue;
+                    };
+                    if shutdown.is_stopping() {
+                        link.lock().await.close();
+                        continue;
+                    }
+                    in_links.lock().await.insert(link_event.id, link);
                 }
                 LinkEvent::Closed => {
+                    in_links.lock().await.remove(&link_event.id);
+                    identities.lock().await.remove(&link_event.id);
                     log::trace!(
                         "IN LINK CLOSED {} ({})",
                         link_event.address_hash,
@@ -162,8 +512,690 @@ pub async fn start_server(
         }
         log::info!("IN LINK LOOP EXIT")
     };
-    tokio::select! {
-      _ = announce_loop() => log::info!("announce loop exited"),
-      _ = in_event_loop() => log::info!("in event loop exited"),
+    // links to the verifiers, used to tell them about changes of entries
+    let verifier_links: Mutex<HashMap<AddressHash, Arc<Mutex<Link>>>> =
+        Mutex::new(HashMap::new());
+    // the link to the primary server, should this be a secondary
+    let primary_link: Mutex<Option<Arc<Mutex<Link>>>> = Mutex::new(None);
+    // the links to the secondaries, used to notify them about changes
+    let secondary_links: Mutex<HashMap<AddressHash, Arc<Mutex<Link>>>> =
+        Mutex::new(HashMap::new());
+    // a server identifies itself on every link it opens to another server before
+    // it asks for anything, the ids of the links on which that succeeded
+    let identified: Mutex<HashSet<AddressHash>> = Mutex::new(HashSet::new());
+    let server_link = async |id: &AddressHash, destination: &AddressHash| {
+        let mut links: Vec<_> = primary_link.lock().await.iter().cloned().collect();
+        links.extend(secondary_links.lock().await.get(destination).cloned());
+        links.extend(peer_links.lock().await.get(destination).cloned());
+        for link in links {
+            if link.lock().await.id() == id {
+                return Some(link);
+            }
+        }
+        None
+    };
+    // asks for a challenge to identify with should the link not be identified yet
+    let is_identified = async |link: &Arc<Mutex<Link>>| {
+        let id = *link.lock().await.id();
+        if identified.lock().await.contains(&id) {
+            return true;
+        }
+        let packet = link.lock().await.data_packet(b"CHALLENGE");
+        if let Ok(packet) = packet {
+            transport.send_packet(packet).await;
+        }
+        false
+    };
+
+    let verifier_link_loop = async || {
+        let mut announce_recv = transport.recv_announces().await;
+        while let Ok(announce) = announce_recv.recv().await {
+            let desc = announce.destination.lock().await.desc;
+            let is_verifier = zones.iter().any(|zone| {
+                zone.database
+                    .active()
+                    .verifier_registry()
+                    .get_verifier(&desc.address_hash)
+                    .is_some()
+            });
+            let mut verifier_links = verifier_links.lock().await;
+            if is_verifier && !verifier_links.contains_key(&desc.address_hash) {
+                let link = transport.link(desc).await;
+                log::trace!("VERIFIER LINK {}", desc.address_hash);
+                verifier_links.insert(desc.address_hash, link);
+            }
+            drop(verifier_links);
+            let is_primary =
+                secondary.is_some_and(|secondary| secondary.primary == desc.address_hash);
+            if is_primary {
+                servers.lock().await.primary = Some(desc.identity.address_hash);
+            }
+            let mut primary_link = primary_link.lock().await;
+            if is_primary && primary_link.is_none() {
+                log::trace!("PRIMARY LINK {}", desc.address_hash);
+                *primary_link = Some(transport.link(desc).await);
+            }
+            drop(primary_link);
+            if let Some(resolver) = resolver
+                && desc.address_hash != destination_hash
+                && let Ok(capabilities) =
+                    Capabilities::decode(&mut Reader::new(announce.app_data.as_slice()))
+                && capabilities.verify(&desc.identity.verifying_key)
+                && capabilities.supports(PROTOCOL_VERSION)
+            {
+                // a discovered server is only asked about the suffixes it holds
+                let configured = resolver.upstreams.contains(&desc.address_hash);
+                let key = desc.identity.verifying_key;
+                let learned = (configured || !capabilities.zones.is_empty())
+                    && upstreams.lock().await.learn(
+                        desc.address_hash,
+                        key,
+                        &capabilities,
+                        configured,
+                    );
+                let mut upstream_links = upstream_links.lock().await;
+                if learned && !upstream_links.contains_key(&desc.address_hash) {
+                    log::trace!("UPSTREAM LINK {}", desc.address_hash);
+                    upstream_links.insert(desc.address_hash, transport.link(desc).await);
+                }
+            }
+            let mut secondary_links = secondary_links.lock().await;
+            let is_secondary = server_config.secondaries.contains(&desc.address_hash);
+            if is_secondary {
+                let identity = desc.identity.address_hash;
+                servers.lock().await.secondaries.insert(identity);
+            }
+            if is_secondary && !secondary_links.contains_key(&desc.address_hash) {
+                log::trace!("SECONDARY LINK {}", desc.address_hash);
+                secondary_links.insert(desc.address_hash, transport.link(desc).await);
+            }
+            drop(secondary_links);
+            let is_peer = server_config.peers.contains(&desc.address_hash);
+            if is_peer {
+                let key = desc.identity.verifying_key;
+                peer_keys.lock().await.insert(desc.address_hash, key);
+                let identity = desc.identity.address_hash;
+                servers.lock().await.peers.insert(identity);
+            }
+            let mut peer_links = peer_links.lock().await;
+            if is_peer && !peer_links.contains_key(&desc.address_hash) {
+                log::trace!("PEER LINK {}", desc.address_hash);
+                peer_links.insert(desc.address_hash, transport.link(desc).await);
+            }
+        }
+    };
+
+    // a secondary transfers its zone from the primary, one page after the other,
+    // and afterwards only asks for the changes since
+    let transfer: Mutex<Option<ZoneTransfer>> = Mutex::new(None);
+    let send_to_primary = async |request: String| {
+        let Some(link) = primary_link.lock().await.clone() else {
+            return;
+        };
+        let packet = link.lock().await.data_packet(request.as_bytes());
+        if let Ok(packet) = packet {
+            transport.send_packet(packet).await;
+        }
+    };
+    let start_transfer = async |secondary: &SecondaryConfig| {
+        let started = ZoneTransfer::new(secondary.zone.clone(), secondary.zone_key);
+        let request = started.request();
+        *transfer.lock().await = Some(started);
+        send_to_primary(request).await;
+    };
+    let transfer_loop = async || {
+        let Some(secondary) = secondary else {
+            return std::future::pending().await;
+        };
+        loop {
+            let link = primary_link.lock().await.clone();
+            if !shutdown.is_stopping()
+                && let Some(link) = link
+                && is_identified(&link).await
+            {
+                let serial = *synced.lock().await;
+                match serial {
+                    Some(serial) => send_to_primary(changes_request(&secondary.zone, serial)).await,
+                    None => start_transfer(secondary).await,
+                }
+            }
+            time::sleep(MIN_SYNC_INTERVAL).await;
+            // a notice of the primary cuts the wait short
+            let _ = time::timeout(TRANSFER_INTERVAL - MIN_SYNC_INTERVAL, sync_now.notified()).await;
+        }
+    };
+    let transfer_page = async |payload: &str| {
+        let (Some(secondary), Some(page)) = (secondary, decode_page(payload)) else {
+            return;
+        };
+        let mut transfer = transfer.lock().await;
+        let Some(progress) = transfer.as_mut() else {
+            return;
+        };
+        let next = match progress.accept(page) {
+            Ok(None) => progress.request(),
+            Ok(Some(entries)) => {
+                let tree_size = progress.tree_size();
+                *transfer = None;
+                drop(transfer);
+                let count = entries.len();
+                let zone = zones.get(&secondary.zone).map(|zone| &zone.database);
+                let requester = Requester::new(secondary.primary, None);
+                let loaded = zone.map(|database| {
+                    database
+                        .replace_entries(&requester, entries)
+                        .map_err(|e| format!("{e:?}"))
+                        .and_then(|_| database.promote_staging())
+                });
+                match loaded {
+                    Some(Ok(())) => {
+                        *synced.lock().await = tree_size;
+                        log::info!("transferred {count} entries of {}", secondary.zone)
+                    }
+                    Some(Err(e)) => log::error!("failed to load transfer: {e}"),
+                    None => {}
+                }
+                return;
+            }
+            // the zone changed, start over from the first page
+            Err(TransferError::Changed) => progress.request(),
+            Err(e) => {
+                log::warn!("aborted transfer from {}: {e:?}", secondary.primary);
+                *transfer = None;
+                return;
+            }
+        };
+        drop(transfer);
+        send_to_primary(next).await;
+    };
+    let change_page = async |payload: &str| {
+        let (Some(secondary), Some(page)) = (secondary, decode_change_page(payload)) else {
+            return;
+        };
+        let Some(zone) = zones.get(&secondary.zone) else {
+            return;
+        };
+        let mut synced = synced.lock().await;
+        let Some(serial) = *synced else {
+            return;
+        };
+        if let Err(e) = page.check(&secondary.zone, &secondary.zone_key, serial) {
+            log::warn!("rejected changes from {}: {e:?}", secondary.primary);
+            return;
+        }
+
+        let (to, last, count) = (page.to, page.last, page.changes.len());
+        let requester = Requester::new(secondary.primary, None);
+        let applied = zone
+            .database
+            .apply_changes(&requester, page.changes)
+            .map_err(|e| format!("{e:?}"))
+            .and_then(|_| zone.database.promote_staging());
+        if let Err(e) = applied {
+            log::error!("failed to apply changes: {e}");
+            // only a full transfer brings a partly changed zone back in sync
+            *synced = None;
+            return;
+        }
+        *synced = Some(to);
+        drop(synced);
+        if count > 0 {
+            let zone = &secondary.zone;
+            log::info!("applied {count} changes of {zone} up to serial {to}");
+        }
+        if !last {
+            send_to_primary(changes_request(&secondary.zone, to)).await;
+        }
+    };
+
+    let notify_loop = async || loop {
+        time::sleep(NOTIFY_INTERVAL).await;
+        for zone in zones.iter() {
+            let mut undelivered = Vec::new();
+            for notification in zone.database.take_notifications() {
+                let link = verifier_links
+                    .lock()
+                    .await
+                    .get(&notification.verifier)
+                    .cloned();
+                let packet = match link {
+                    Some(link) => {
+                        let notice = parser::verifier_notice(
+                            &notification.name,
+                            notification.entry.as_ref(),
+                        );
+                        link.lock().await.data_packet(notice.as_bytes()).ok()
+                    }
+                    None => None,
+                };
+                match packet {
+                    Some(packet) => transport.send_packet(packet).await,
+                    None => undelivered.push(notification),
+                }
+            }
+            zone.database.requeue_notifications(undelivered);
+        }
+    };
+
+    // responses of the verifiers arrive on the links the server opened
+    let out_event_loop = async || {
+        let mut out_link_events = transport.out_link_events();
+        while let Ok(link_event) = out_link_events.recv().await {
+            match link_event.event {
+                LinkEvent::Data(payload) => {
+                    let Ok(payload) = str::from_utf8(payload.as_slice()) else {
+                        continue;
+                    };
+                    if let Some(challenge) = registration::parse_challenge(payload) {
+                        let server = link_event.address_hash;
+                        let Some(link) = server_link(&link_event.id, &server).await else {
+                            continue;
+                        };
+                        let request =
+                            registration::identify_request(&challenge, &server, &private_id);
+                        let packet = link.lock().await.data_packet(request.as_bytes());
+                        if let Ok(packet) = packet {
+                            transport.send_packet(packet).await;
+                        }
+                        continue;
+                    }
+                    if payload.starts_with("IDENTIFIED ") {
+                        log::trace!("IDENTIFIED to {}", link_event.address_hash);
+                        identified.lock().await.insert(link_event.id);
+                        continue;
+                    }
+                    if let Some(answer) = payload.strip_prefix("ANSWER ")
+                        && resolver.is_some()
+                    {
+                        answer_forwarded(&link_event.address_hash, answer).await;
+                        continue;
+                    }
+                    if let Some(message) = payload.strip_prefix("GOSSIP ") {
+                        let replies = handle_gossip(message).await;
+                        let link = peer_links
+                            .lock()
+                            .await
+                            .get(&link_event.address_hash)
+                            .cloned();
+                        let Some(link) = link else {
+                            continue;
+                        };
+                        for reply in replies {
+                            let packet = link.lock().await.data_packet(reply.as_bytes());
+                            if let Ok(packet) = packet {
+                                transport.send_packet(packet).await;
+                            }
+                        }
+                        continue;
+                    }
+                    if payload.starts_with("INSYNC ")
+                        && server_config.peers.contains(&link_event.address_hash)
+                    {
+                        continue;
+                    }
+                    if let Some(secondary) =
+                        secondary.filter(|s| s.primary == link_event.address_hash)
+                    {
+                        if let Some(page) = payload.strip_prefix("XFR ") {
+                            transfer_page(page).await;
+                            continue;
+                        }
+                        if let Some(page) = payload.strip_prefix("IXFR ") {
+                            change_page(page).await;
+                            continue;
+                        }
+                        if payload == RequestError::from(RNSDNSERRORS::HistoryTooShort).response() {
+                            log::info!("transferring {} in full", secondary.zone);
+                            *synced.lock().await = None;
+                            start_transfer(secondary).await;
+                            continue;
+                        }
+                    }
+                    if let Err(e) =
+                        parser::verifier_response(&zones, &link_event.address_hash, payload)
+                    {
+                        log::warn!(
+                            "failed to handle response of verifier {}: {}",
+                            link_event.address_hash,
+                            e.code()
+                        );
+                    }
+                }
+                LinkEvent::Activated => {
+                    log::trace!(
+                        "OUT LINK ACTIVATED {} ({})",
+                        link_event.address_hash,
+                        link_event.id
+                    );
+                    if let Some(link) = server_link(&link_event.id, &link_event.address_hash).await
+                    {
+                        is_identified(&link).await;
+                    }
+                }
+                LinkEvent::Closed => {
+                    // the link is rebuilt on the next announce of the verifier
+                    identified.lock().await.remove(&link_event.id);
+                    verifier_links.lock().await.remove(&link_event.address_hash);
+                    secondary_links.lock().await.remove(&link_event.address_hash);
+                    upstream_links.lock().await.remove(&link_event.address_hash);
+                    peer_links.lock().await.remove(&link_event.address_hash);
+                    if secondary.is_some_and(|s| s.primary == link_event.address_hash) {
+                        *primary_link.lock().await = None;
+                    }
+                    log::trace!(
+                        "OUT LINK CLOSED {} ({})",
+                        link_event.address_hash,
+                        link_event.id
+                    )
+                }
+            }
+        }
+        log::info!("OUT LINK LOOP EXIT")
+    };
+
+    // staged changes only become visible to queries once they are promoted, the
+    // secondaries are told right after
+    let promote_loop = async || {
+        let mut notified: HashMap<&str, u64> = HashMap::new();
+        loop {
+            time::sleep(PROMOTE_INTERVAL).await;
+            flush_zones(&zones);
+            for zone in zones.iter() {
+                let Some(head) = zone.database.tree_head() else {
+                    continue;
+                };
+                if notified.insert(&zone.suffix, head.tree_size) == Some(head.tree_size) {
+                    continue;
+                }
+                let notice = format!("NOTIFY {} {}", zone_argument(&zone.suffix), head.tree_size);
+                let links: Vec<_> = secondary_links.lock().await.values().cloned().collect();
+                for link in links {
+                    // a secondary ignores notices of a primary it does not know
+                    if !is_identified(&link).await {
+                        continue;
+                    }
+                    let packet = link.lock().await.data_packet(notice.as_bytes());
+                    if let Ok(packet) = packet {
+                        transport.send_packet(packet).await;
+                    }
+                }
+            }
+        }
+    };
+
+    // every zone is compared with every peer, the differences are then sent by
+    // whichever of the two holds the newer version
+    let gossip_loop = async || {
+        if server_config.peers.is_empty() {
+            return std::future::pending().await;
+        }
+        loop {
+            time::sleep(GOSSIP_INTERVAL).await;
+            if shutdown.is_stopping() {
+                continue;
+            }
+            let peers: Vec<_> = peer_links.lock().await.values().cloned().collect();
+            let mut links = Vec::new();
+            for link in peers {
+                if is_identified(&link).await {
+                    links.push(link);
+                }
+            }
+            for zone in zones.iter() {
+                let digest = Payload::Digest(gossip::digest(&zone.database.gossip_versions()));
+                let Some(message) = zones.primary().database.sign_gossip(&zone.suffix, digest)
+                else {
+                    continue;
+                };
+                let message = message.to_message();
+                for link in &links {
+                    let packet = link.lock().await.data_packet(message.as_bytes());
+                    if let Ok(packet) = packet {
+                        transport.send_packet(packet).await;
+                    }
+                }
+            }
+        }
+    };
+
+    // the counters are logged so that they can be picked up by monitoring
+    let stats_loop = async || {
+        let mut last = None;
+        loop {
+            time::sleep(STATS_INTERVAL).await;
+            let counters = rate_limiter.lock().await.counters();
+            if last != Some(counters) {
+                log::info!("rate limit {}", counters.to_json());
+                last = Some(counters);
+            }
+        }
+    };
+
+    // the loops keep running while the queries in flight are drained
+    let shutdown_loop = async || {
+        let signal = shutdown.requested().await;
+        log::info!("received {signal:?}, shutting down");
+        match shutdown.drain(SHUTDOWN_DEADLINE).await {
+            true => ExitStatus::Clean,
+            false => ExitStatus::DeadlineExceeded,
+        }
+    };
+
+    let status = tokio::select! {
+      _ = announce_loop() => { log::info!("announce loop exited"); ExitStatus::Unexpected },
+      _ = in_event_loop() => { log::info!("in event loop exited"); ExitStatus::Unexpected },
+      _ = out_event_loop() => { log::info!("out event loop exited"); ExitStatus::Unexpected },
+      _ = verifier_link_loop() => {
+          log::info!("verifier link loop exited");
+          ExitStatus::Unexpected
+      },
+      _ = notify_loop() => { log::info!("notify loop exited"); ExitStatus::Unexpected },
+      _ = promote_loop() => { log::info!("promote loop exited"); ExitStatus::Unexpected },
+      _ = stats_loop() => { log::info!("stats loop exited"); ExitStatus::Unexpected },
+      _ = transfer_loop() => { log::info!("transfer loop exited"); ExitStatus::Unexpected },
+      _ = gossip_loop() => { log::info!("gossip loop exited"); ExitStatus::Unexpected },
+      status = shutdown_loop() => status,
+    };
+
+    let flushed = flush_zones(&zones);
+    let mut links: Vec<_> = in_links.lock().await.drain().map(|(_, link)| link).collect();
+    links.extend(verifier_links.lock().await.drain().map(|(_, link)| link));
+    links.extend(primary_link.lock().await.take());
+    links.extend(secondary_links.lock().await.drain().map(|(_, link)| link));
+    links.extend(upstream_links.lock().await.drain().map(|(_, link)| link));
+    links.extend(peer_links.lock().await.drain().map(|(_, link)| link));
+    for link in links {
+        link.lock().await.close();
+    }
+    log::info!("server stopped");
+    flushed.worse(status)
+}
+
+/// Parses the arguments of a `NOTIFY <zone> <serial>` notice.
+fn parse_notice(notice: &str) -> Option<(&str, u64)> {
+    let (zone, serial) = notice.trim().split_once(' ')?;
+    Some((zone, serial.parse().ok()?))
+}
+
+/// Handles a gossip message of a peer whose signature was checked and returns
+/// the messages to send back, see `gossip`.
+fn replicate(zones: &Zones, message: Gossip) -> Vec<String> {
+    let Some(zone) = zones.get(&message.zone) else {
+        return Vec::new();
+    };
+    let sign = |payload: Payload| {
+        let signed = zones.primary().database.sign_gossip(&zone.suffix, payload);
+        signed.map(|gossip| gossip.to_message())
+    };
+    let versions = zone.database.gossip_versions();
+    match message.payload {
+        Payload::Digest(theirs) => {
+            let buckets = gossip::differing(&gossip::digest(&versions), &theirs);
+            if buckets.is_empty() {
+                return vec![format!("INSYNC {}", zone_argument(&zone.suffix))];
+            }
+            let versions = gossip::in_buckets(&versions, &buckets);
+            sign(Payload::Summary { buckets, versions })
+                .into_iter()
+                .collect()
+        }
+        Payload::Summary {
+            buckets,
+            versions: theirs,
+        } => {
+            let ours = gossip::in_buckets(&versions, &buckets);
+            let theirs = gossip::in_buckets(&theirs, &buckets);
+            let (push, want) = gossip::reconcile(&ours, &theirs);
+            let mut replies: Vec<String> = push
+                .chunks(RECORDS_PAGE_SIZE)
+                .filter_map(|names| sign(zone.database.gossip_records(names)))
+                .collect();
+            let zone_arg = zone_argument(&zone.suffix);
+            replies.extend(
+                want.chunks(WANT_BATCH)
+                    .map(|names| format!("WANT {zone_arg} {}", names.join(" "))),
+            );
+            replies
+        }
+        Payload::Records { entries, removals } => {
+            // a peer may only replicate the names of the zone it named
+            let in_zone = |name: &str| zones.route(name).suffix == zone.suffix;
+            let entries = entries
+                .into_iter()
+                .filter(|entry| in_zone(entry.name()))
+                .collect();
+            let removals = removals
+                .into_iter()
+                .filter(|(name, _)| in_zone(name))
+                .collect();
+            let requester = Requester::new(message.server, None);
+            let merged = zone
+                .database
+                .merge_replicated(&requester, entries, removals);
+            match merged {
+                Ok(0) => {}
+                Ok(merged) => {
+                    let zone = zone_argument(&zone.suffix);
+                    log::info!("merged {merged} changes of {zone} from {}", message.server)
+                }
+                Err(e) => log::error!("failed to merge changes from {}: {e:?}", message.server),
+            }
+            Vec::new()
+        }
+    }
+}
+
+/// Promotes the staged changes of every zone and persists them, as on every
+/// tick of the promote loop.
+fn flush_zones(zones: &Zones) -> ExitStatus {
+    let mut status = ExitStatus::Clean;
+    for zone in zones.iter() {
+        if let Err(e) = zone.database.promote_staging() {
+            log::error!("failed to promote staging of zone {}: {e}", zone.suffix);
+            status = ExitStatus::FlushFailed;
+            continue;
+        }
+        if let Some(path) = &zone.database_path
+            && let Err(e) = zone.database.save(path)
+        {
+            log::error!("failed to persist database {}: {e}", path.display());
+            status = ExitStatus::FlushFailed;
+        }
+    }
+    status
+}
+
+/// Opens the database of a zone and sets it up to sign as `server`.
+///
+/// # Errors
+///
+/// Fails should the zone not be able to register itself as verifier. Failing to
+/// open the database or the chain is fatal, see `open_database`.
+fn open_zone(
+    config: &types::ZoneConfig,
+    name: &str,
+    server: AddressHash,
+    private_id: &PrivateIdentity,
+) -> Result<Zone, String> {
+    let database = open_database(config, private_id);
+
+    // the server always vouches for its own records at the highest trust level
+    let key = private_id.as_identity().verifying_key;
+    let server_verifier = Verifier::new(name.to_owned(), server, 0, key);
+    if database
+        .set_server_verifier(server_verifier, private_id.clone())
+        .is_err()
+        || database.promote_staging().is_err()
+    {
+        return Err(format!("failed to register zone {} as verifier", config.suffix));
+    }
+
+    if let Some(path) = &config.chain_path {
+        let chain = TrustChain::load(path).unwrap_or_else(|e| {
+            log::fatal!("failed to load trust chain {}: {e}", path.display());
+            std::process::exit(1);
+        });
+        // a chain for a different server would make every answer fail validation
+        let matches = chain
+            .delegations()
+            .last()
+            .is_some_and(|leaf| *leaf.server() == server && *leaf.key() == key);
+        if !matches {
+            log::warn!("trust chain {} does not end at this server", path.display());
+        }
+        database.set_trust_chain(chain);
     }
+
+    Ok(Zone {
+        suffix: config.suffix.clone(),
+        server,
+        database,
+        database_path: config.database_path.clone(),
+    })
+}
+
+/// Builds the database from the zone config and loads the database file should
+/// one exist. Failing to open the database, the audit log, the transparency log
+/// or the access list is fatal since the server would otherwise start with an empty or unaudited
+/// database.
+pub fn open_database(config: &types::ZoneConfig, private_id: &PrivateIdentity) -> DnsDatabase {
+    let audit_log = match config.audit_log_path() {
+        Some(path) => AuditLog::open(&path, private_id.clone()).unwrap_or_else(|e| {
+            log::fatal!("failed to open audit log {}: {e}", path.display());
+            std::process::exit(1);
+        }),
+        None => AuditLog::ephemeral(private_id.clone()),
+    };
+    let transparency_log = match config.transparency_log_path() {
+        Some(path) => TransparencyLog::open(&path).unwrap_or_else(|e| {
+            log::fatal!("failed to open transparency log {}: {e}", path.display());
+            std::process::exit(1);
+        }),
+        None => TransparencyLog::ephemeral(),
+    };
+    let access_list = match &config.acl_path {
+        Some(path) => AccessList::load(path).unwrap_or_else(|e| {
+            log::fatal!("failed to load access list {}: {e}", path.display());
+            std::process::exit(1);
+        }),
+        None => AccessList::default(),
+    };
+    let operator = private_id.as_identity().address_hash;
+    let database = DnsDatabase::with_policy(operator, config.policy.clone(), audit_log)
+        .with_transparency_log(transparency_log)
+        .with_access_list(access_list);
+
+    if let Some(path) = config.database_path.as_ref().filter(|p| p.exists()) {
+        let raw = DnsDatabaseRaw::load(path).unwrap_or_else(|e| {
+            log::fatal!("failed to load database {}: {e}", path.display());
+            std::process::exit(1);
+        });
+        if database.load(raw).is_err() {
+            log::fatal!("failed to load database {}", path.display());
+            std::process::exit(1);
+        }
+        log::info!("Loaded database from {}", path.display());
+    }
+
+    database
 }
@@ -1,4 +1,16 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use reticulum::hash::AddressHash;
+
+use crate::server::envelope::Query;
+use crate::server::gossip::WANT_BATCH;
+use crate::server::registration;
+use crate::server::server::{DnsDatabase, DnsEntry, RNSDNSERRORS, Requester};
+use crate::server::transfer::Servers;
+use crate::server::transparency::encode_hashes;
+use crate::server::zones::{Zone, Zones};
+use crate::utilites::codec::{Writer, parse_address};
 use crate::utilites::error::{self, RequestError};
+use crate::verifier::{decode_entry, decode_signing, encode_entry};
 
 pub struct ParsedRequest<'a> {
     pub command: &'a str,
@@ -15,15 +27,462 @@ pub fn select_request(request: &str) -> Result<ParsedRequest<'_>, RequestError>
     Ok(ParsedRequest { command, args })
 }
 
-pub fn request_router(request: &str) -> Result<(), RequestError> {
+/// Answers a request as it arrived over a link.
+///
+/// # Behaviour
+///
+/// Failed requests are answered with `RequestError::response`. Should the
+/// request be wrapped in `QUERY <id> <nonce> <request>` the response is wrapped
+/// in an answer signed by the server, `ANSWER <answer>`, which binds it to that
+/// query. Unwrapped requests are answered as they are.
+///
+/// Answers are always signed by the primary zone, since that is the identity
+/// the server announces, whichever zone the request was routed to.
+pub fn respond(zones: &Zones, servers: &Servers, requester: &Requester, payload: &str) -> String {
+    let query = Query::parse(payload);
+    let request = query.as_ref().map_or(payload, |query| query.request);
+    let response = match request_router(zones, servers, requester, request) {
+        Ok(response) => response,
+        Err(e) => e.response(),
+    };
+    wrap(zones, query.as_ref(), response)
+}
+
+/// Wraps the response to a query into an answer signed by the primary zone,
+/// `ANSWER <answer>`. The response to an unwrapped request is returned as it is.
+pub fn wrap(zones: &Zones, query: Option<&Query>, response: String) -> String {
+    let database = &zones.primary().database;
+    match query.and_then(|query| database.sign_answer(query, response.clone())) {
+        Some(answer) => format!("ANSWER {}", URL_SAFE_NO_PAD.encode(answer.encode())),
+        None => response,
+    }
+}
+
+/// Routes the request to its handler and returns the response that should be
+/// sent back to the requester.
+///
+/// Requests about a name are handled by the zone of that name. `CHALLENGE`,
+/// `HEAD` and `CONSISTENCY` take the zone as an optional last argument and are
+/// handled by the primary zone without it. `VERIFIER` is always answered from
+/// the registry of the primary zone. `TRANSFER` and `CHANGES` are only answered
+/// for the configured secondaries and `WANT` for the configured peers, see
+/// `Servers`.
+///
+/// `CHECK` runs the integrity check of a zone for the operator, anyone else is
+/// answered with `ERR 17`.
+pub fn request_router(
+    zones: &Zones,
+    servers: &Servers,
+    requester: &Requester,
+    request: &str,
+) -> Result<String, RequestError> {
     let parsed = select_request(request)?;
+    let args = parsed.args.as_slice();
+    let zone_of = |name: Option<&&str>| match name {
+        Some(name) => &zones.route(name).database,
+        None => &zones.primary().database,
+    };
 
     match parsed.command {
-        "LOOKUP" => Ok(()),
-        "PING" => Ok(()),
-        "UPDATE" => Ok(()),
-        "CREATE" => Ok(()),
+        "LOOKUP" => lookup(zone_of(args.first()), args),
+        "PING" => Ok("PONG".to_owned()),
+        "UPDATE" => update(zones, requester, args),
+        "CHALLENGE" => {
+            let link = requester.link.ok_or(RNSDNSERRORS::MissingChallenge)?;
+            let database = zone_of(args.first());
+            Ok(registration::format_challenge(&database.issue_challenge(&link)))
+        }
+        "CREATE" => create(zones, requester, args),
+        "RENEW" => renew(zones, requester, args),
+        "REMOVE" => remove(zones, requester, args),
+        "ATTACH" => attach(zone_of(args.first()), requester, args),
+        "VERIFIER" => verifier_status(&zones.primary().database, args),
+        "CHECK" => check(zone_of(args.first()), requester),
+        "HEAD" => tree_head(zone_of(args.first())),
+        "CONSISTENCY" => consistency(zone_of(args.get(2)), args),
+        "TRANSFER" => {
+            servers.require_secondary(requester)?;
+            transfer(zones, args)
+        }
+        "CHANGES" => {
+            servers.require_secondary(requester)?;
+            changes(zones, args)
+        }
+        "WANT" => {
+            servers.require_peer(requester)?;
+            want(zones, args)
+        }
+
+        _ => Err(RequestError::UnknownCommand),
+    }
+}
+
+/// `LOOKUP <name> [<min level> <max level>]` answers with the entry of the name.
+///
+/// The levels correspond to the `level` field of a query and limit the
+/// verifications that are included to the given trust levels. Without them all
+/// verifications are included.
+///
+/// The answer is `ENTRY <entry> [CHAIN <chain>] [PROOF <proof>]`. Should the
+/// server have a trust chain it is appended so that the entry can be validated
+/// from a root anchor, the signing of the server itself is always included in
+/// that case since the chain ends in it. The proof shows that the entry is part
+/// of the transparency log under the current signed tree head.
+///
+/// Should the name not exist the answer is `NXNAME <denial> [CHAIN <chain>]`, the
+/// signed names around it which prove that it is missing. The chain leads to the
+/// key the denial is signed with.
+fn lookup(database: &DnsDatabase, args: &[&str]) -> Result<String, RequestError> {
+    let (name, levels) = match args {
+        [name] => (name, 0..=u32::MAX),
+        [name, min, max] => {
+            let min = min.parse::<u8>().map_err(|_| RequestError::FailedToParse)?;
+            let max = max.parse::<u8>().map_err(|_| RequestError::FailedToParse)?;
+            (name, min as u32..=max as u32)
+        }
+        _ => return Err(RequestError::FailedToParse),
+    };
+
+    let active = database.active();
+    let chain = database.trust_chain();
+    let levels = match chain {
+        Some(_) => 0..=*levels.end(),
+        None => levels,
+    };
+    let Some(entry) = active.entry_store().lookup(name) else {
+        let denial = database
+            .deny_existence(name)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        let mut response = format!("NXNAME {}", URL_SAFE_NO_PAD.encode(denial.encode()));
+        if let Some(chain) = chain {
+            response.push_str(" CHAIN ");
+            response.push_str(&URL_SAFE_NO_PAD.encode(chain.encode()));
+        }
+        return Ok(response);
+    };
+    let entry = entry.with_verifications_in(levels, active.verifier_registry());
+
+    let mut response = format!("ENTRY {}", encode_entry(&entry));
+    if let Some(chain) = chain {
+        response.push_str(" CHAIN ");
+        response.push_str(&URL_SAFE_NO_PAD.encode(chain.encode()));
+    }
+    if let Some(proof) = database.inclusion_proof(&entry) {
+        response.push_str(" PROOF ");
+        response.push_str(&URL_SAFE_NO_PAD.encode(proof.encode()));
+    }
+    Ok(response)
+}
+
+/// `HEAD [<zone>]` answers with the signed head of the transparency log.
+fn tree_head(database: &DnsDatabase) -> Result<String, RequestError> {
+    let head = database.tree_head().ok_or(RNSDNSERRORS::NotFound)?;
+    let mut writer = Writer::new();
+    head.encode_into(&mut writer);
+    Ok(format!("HEAD {}", URL_SAFE_NO_PAD.encode(writer.finish())))
+}
+
+/// `CHECK [<zone>]` answers with the integrity report of the zone as JSON,
+/// `REPORT <report>`.
+fn check(database: &DnsDatabase, requester: &Requester) -> Result<String, RequestError> {
+    let report = database.check_integrity(requester)?;
+    Ok(format!("REPORT {}", report.to_json()))
+}
+
+/// `CONSISTENCY <first size> <second size> [<zone>]` answers with the proof that
+/// the transparency log of the second size extends the one of the first size.
+fn consistency(database: &DnsDatabase, args: &[&str]) -> Result<String, RequestError> {
+    let ([first, second] | [first, second, _]) = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let first = first.parse::<u64>().map_err(|_| RequestError::FailedToParse)?;
+    let second = second.parse::<u64>().map_err(|_| RequestError::FailedToParse)?;
+    let proof = database
+        .consistency_proof(first, second)
+        .ok_or(RNSDNSERRORS::NotFound)?;
+
+    let mut writer = Writer::new();
+    encode_hashes(&mut writer, &proof);
+    Ok(format!(
+        "CONSISTENCY {first} {second} {}",
+        URL_SAFE_NO_PAD.encode(writer.finish())
+    ))
+}
+
+/// `TRANSFER <zone> [<after>]` answers with the page of a zone transfer that
+/// follows the name `<after>`, see `transfer`. The zone is given by its suffix,
+/// `.` for the primary zone.
+///
+/// The answer is `XFR <page>`.
+fn transfer(zones: &Zones, args: &[&str]) -> Result<String, RequestError> {
+    let (zone, after) = match args {
+        [zone] => (*zone, None),
+        [zone, after] => (*zone, Some(*after)),
+        _ => return Err(RequestError::FailedToParse),
+    };
+    let zone = transfer_zone(zones, zone)?;
+    let page = zone
+        .database
+        .transfer_page(&zone.suffix, after)
+        .ok_or(RNSDNSERRORS::NotFound)?;
+    Ok(format!("XFR {}", URL_SAFE_NO_PAD.encode(page.encode())))
+}
+
+/// `CHANGES <zone> <serial>` answers with the changes of a zone made after it had
+/// the serial, see `change_page`.
+///
+/// The answer is `IXFR <page>`, or `ERR 23` should the changes no longer be
+/// remembered.
+fn changes(zones: &Zones, args: &[&str]) -> Result<String, RequestError> {
+    let [zone, since] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let since = since.parse::<u64>().map_err(|_| RequestError::FailedToParse)?;
+    let zone = transfer_zone(zones, zone)?;
+    let page = zone.database.change_page(&zone.suffix, since)?;
+    Ok(format!("IXFR {}", URL_SAFE_NO_PAD.encode(page.encode())))
+}
+
+/// `WANT <zone> <name>...` answers with the entries and removals of the names a
+/// peer asked for, see `gossip`. Only the first `WANT_BATCH` names are answered
+/// and names the zone never held are left out.
+///
+/// The answer is `GOSSIP <records>`, signed by the primary zone.
+fn want(zones: &Zones, args: &[&str]) -> Result<String, RequestError> {
+    let [zone, names @ ..] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let zone = transfer_zone(zones, zone)?;
+    let names: Vec<String> = names
+        .iter()
+        .take(WANT_BATCH)
+        .map(|name| name.to_string())
+        .collect();
+    let records = zone.database.gossip_records(&names);
+    let gossip = zones
+        .primary()
+        .database
+        .sign_gossip(&zone.suffix, records)
+        .ok_or(RNSDNSERRORS::NotFound)?;
+    Ok(gossip.to_message())
+}
+
+/// The zone named in a transfer or by a peer, `.` for the primary zone.
+fn transfer_zone<'a>(zones: &'a Zones, zone: &str) -> Result<&'a Zone, RNSDNSERRORS> {
+    match zone {
+        "." => Ok(zones.primary()),
+        suffix => zones.get(suffix).ok_or(RNSDNSERRORS::NotFound),
+    }
+}
 
+/// `IDENTIFY <identity> <proof>` proves that the requester holds an identity and
+/// returns its address hash, see `registration`. The caller remembers it for the
+/// link and answers `IDENTIFIED <identity hash>`.
+///
+/// The challenge has to be requested from the primary zone, `CHALLENGE`.
+pub fn identify(
+    zones: &Zones,
+    requester: &Requester,
+    request: &str,
+) -> Result<AddressHash, RequestError> {
+    let parsed = select_request(request)?;
+    let ("IDENTIFY", [identity, proof]) = (parsed.command, parsed.args.as_slice()) else {
+        return Err(RequestError::FailedToParse);
+    };
+    let identity = registration::decode_identity(identity).ok_or(RequestError::FailedToParse)?;
+    let proof = registration::decode_proof(proof).ok_or(RequestError::FailedToParse)?;
+    let link = requester.link.ok_or(RNSDNSERRORS::MissingChallenge)?;
+    Ok(zones.primary().database.identify(&link, &identity, &proof)?)
+}
+
+/// `CREATE <entry> <proof>` registers a new entry. The proof is the signature of
+/// the owner key over the challenge the requester was issued and the entry, see
+/// `registration::proof_bytes`.
+///
+/// The challenge has to be requested from the zone of the name, `CHALLENGE <name>`.
+fn create(zones: &Zones, requester: &Requester, args: &[&str]) -> Result<String, RequestError> {
+    let [entry, proof] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let entry = decode_entry(entry).ok_or(RequestError::FailedToParse)?;
+    let proof = registration::decode_proof(proof).ok_or(RequestError::FailedToParse)?;
+    let name = entry.name().to_owned();
+    let database = &zones.route(&name).database;
+    database.register_entry(requester, entry, &proof)?;
+    Ok(format!("OK {name}"))
+}
+
+/// `UPDATE <entry>` replaces an entry with a newer version signed by its owner.
+///
+/// The answer is `OK <name> <serial>`. An update whose serial is not higher than
+/// the stored one is answered with `ERR 22 <current serial>`.
+fn update(zones: &Zones, requester: &Requester, args: &[&str]) -> Result<String, RequestError> {
+    let [entry] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let entry = decode_entry(entry).ok_or(RequestError::FailedToParse)?;
+    let (name, serial) = (entry.name().to_owned(), entry.serial());
+    zones.route(&name).database.override_entry(requester, entry)?;
+    Ok(format!("OK {name} {serial}"))
+}
+
+/// `RENEW <name>` extends the expiry of an entry. The requester has to have
+/// identified itself as the owner, `IDENTIFY`.
+fn renew(zones: &Zones, requester: &Requester, args: &[&str]) -> Result<String, RequestError> {
+    let [name] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    zones.route(name).database.renew_entry(requester, name)?;
+    Ok(format!("OK {name}"))
+}
+
+/// `REMOVE <name> <signature>` removes an entry. The signature is the one of the
+/// owner over the name and the serial of the entry, see
+/// `registration::removal_bytes`.
+fn remove(zones: &Zones, requester: &Requester, args: &[&str]) -> Result<String, RequestError> {
+    let [name, signature] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let signature = registration::decode_proof(signature).ok_or(RequestError::FailedToParse)?;
+    zones
+        .route(name)
+        .database
+        .remove_domain(requester, name, &signature)?;
+    Ok(format!("OK {name}"))
+}
+
+/// `ATTACH <name> <signing>` attaches the signing a verifier returned to the
+/// owner. The signing is validated by the database.
+fn attach(
+    database: &DnsDatabase,
+    requester: &Requester,
+    args: &[&str],
+) -> Result<String, RequestError> {
+    let [name, signing] = args else {
+        return Err(RequestError::FailedToParse);
+    };
+    let signing = decode_signing(signing).ok_or(RequestError::FailedToParse)?;
+    database.attach_signing(requester, name, signing)?;
+    Ok(format!("OK {name}"))
+}
+
+/// The most destinations a single `VERIFIER` query may ask about.
+const MAX_VERIFIER_QUERY: usize = 16;
+
+/// `VERIFIER <destination> [<destination> ...]` answers with the signed record
+/// of each verifier, or that it was revoked or is unknown.
+///
+/// This is the look-up a client makes for the destination of a `VerifierSigning`.
+/// The answer is `STATUS` followed by one signed status per destination in the
+/// order they were asked for, so that a client can refresh its whole cache with
+/// few round trips.
+fn verifier_status(database: &DnsDatabase, args: &[&str]) -> Result<String, RequestError> {
+    if args.is_empty() || args.len() > MAX_VERIFIER_QUERY {
+        return Err(RequestError::FailedToParse);
+    }
+    let mut response = String::from("STATUS");
+    for destination in args {
+        let destination = parse_address(destination).ok_or(RequestError::FailedToParse)?;
+        let status = database
+            .verifier_status(&destination)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        response.push(' ');
+        response.push_str(&URL_SAFE_NO_PAD.encode(status.encode()));
+    }
+    Ok(response)
+}
+
+/// Handles a response of a verifier that was sent over a link the server opened
+/// to the verifier. `verifier` is the destination of that link.
+pub fn verifier_response(
+    zones: &Zones,
+    verifier: &AddressHash,
+    response: &str,
+) -> Result<(), RequestError> {
+    let parsed = select_request(response)?;
+    let requester = Requester::new(*verifier, None);
+
+    match (parsed.command, parsed.args.as_slice()) {
+        ("SIGNING", [name, signing]) => {
+            let signing = decode_signing(signing).ok_or(RequestError::FailedToParse)?;
+            // a verifier may only hand in its own signings
+            if signing.destination() != verifier {
+                return Err(RequestError::FailedToParse);
+            }
+            zones
+                .route(name)
+                .database
+                .attach_signing(&requester, name, signing)?;
+            Ok(())
+        }
+        ("WITHDRAW", [name]) => {
+            zones
+                .route(name)
+                .database
+                .withdraw_signing(&requester, name, verifier)?;
+            Ok(())
+        }
+        ("ERR", _) => {
+            log::warn!("verifier {verifier} responded with {response}");
+            Ok(())
+        }
         _ => Err(RequestError::UnknownCommand),
     }
 }
+
+/// Builds the message that tells a verifier about a change of an entry it
+/// vouched for.
+pub fn verifier_notice(name: &str, entry: Option<&DnsEntry>) -> String {
+    match entry {
+        Some(entry) => format!("CHANGED {}", encode_entry(entry)),
+        None => format!("REMOVED {name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reticulum::identity::PrivateIdentity;
+
+    use super::*;
+    use crate::server::audit::AuditLog;
+    use crate::server::policy::EntryPolicy;
+
+    fn zones() -> Zones {
+        let signer = PrivateIdentity::new_from_name("server");
+        let operator = signer.as_identity().address_hash;
+        let policy = EntryPolicy::new(16, chrono::TimeDelta::zero(), 8);
+        Zones::new(Zone {
+            suffix: String::new(),
+            server: operator,
+            database: DnsDatabase::with_policy(operator, policy, AuditLog::ephemeral(signer)),
+            database_path: None,
+        })
+    }
+
+    fn entry(serial: u64, owner: &PrivateIdentity) -> DnsEntry {
+        let destinations = vec![AddressHash::new([2u8; 16])];
+        DnsEntry::new_signed("a.node".to_owned(), destinations, serial, owner)
+    }
+
+    #[test]
+    fn stale_update_is_answered_with_the_current_serial() {
+        let (zones, servers) = (zones(), Servers::default());
+        let owner = PrivateIdentity::new_from_name("owner");
+        let link = AddressHash::new([5u8; 16]);
+        let requester = Requester::on_link(link, Some(owner.as_identity().address_hash));
+        let database = &zones.primary().database;
+        let challenge = database.issue_challenge(&link);
+        let registered = entry(2, &owner);
+        let proof = owner.sign(&registration::proof_bytes(&challenge, &registered));
+        database.register_entry(&requester, registered, &proof).unwrap();
+
+        let update = |serial| {
+            let request = format!("UPDATE {}", encode_entry(&entry(serial, &owner)));
+            respond(&zones, &servers, &requester, &request)
+        };
+        assert_eq!(update(2), "ERR 22 2");
+        assert_eq!(update(1), "ERR 22 2");
+        assert_eq!(update(3), "OK a.node 3");
+        assert_eq!(update(3), "ERR 22 3");
+    }
+}
@@ -0,0 +1,231 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use x25519_dalek::PublicKey;
+
+use crate::server::server::{DnsEntry, DnsEntryStore, RNSDNSERRORS};
+
+/// This is the policy that guards every mutation of the `DnsEntryStore`.
+///
+/// # Fields
+/// `max_names_per_key` - The maximum amount of names a single owner key may hold.
+/// `min_update_interval` - The minimum time that has to pass between two updates
+/// of the same name.
+/// `max_destinations` - The maximum amount of destinations a single entry may
+/// point to.
+///
+/// # Reasoning
+///
+/// The `DnsEntryStore` itself does not offer any security features and will
+/// happily accept thousands of names from a single identity. The policy lives
+/// in front of it so that the store stays simple and the limits can be tuned by
+/// the operator without touching the store.
+///
+/// # Security
+///
+/// The policy only limits what a single key can do. An attacker with many keys
+/// is not stopped by this and has to be dealt with at the routing level.
+#[derive(Clone, Debug)]
+pub struct EntryPolicy {
+    /// The maximum amount of names a single owner key may hold.
+    pub max_names_per_key: usize,
+    /// The minimum time between two updates of the same name.
+    pub min_update_interval: TimeDelta,
+    /// The maximum amount of destinations per entry.
+    pub max_destinations: usize,
+}
+
+impl Default for EntryPolicy {
+    fn default() -> Self {
+        Self {
+            max_names_per_key: 16,
+            min_update_interval: TimeDelta::minutes(10),
+            max_destinations: 8,
+        }
+    }
+}
+
+impl EntryPolicy {
+    pub fn new(
+        max_names_per_key: usize,
+        min_update_interval: TimeDelta,
+        max_destinations: usize,
+    ) -> Self {
+        Self {
+            max_names_per_key,
+            min_update_interval,
+            max_destinations,
+        }
+    }
+
+    /// Checks whether a new entry may be added to the store.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::TooManyDestinations` or `RNSDNSERRORS::QuotaExceeded`
+    /// should the entry violate the policy.
+    pub fn check_add(
+        &self,
+        store: &DnsEntryStore,
+        public_key: &PublicKey,
+        destinations: usize,
+    ) -> Result<(), RNSDNSERRORS> {
+        if destinations > self.max_destinations {
+            return Err(RNSDNSERRORS::TooManyDestinations);
+        }
+        if store.count_entries_by_key(public_key) >= self.max_names_per_key {
+            return Err(RNSDNSERRORS::QuotaExceeded);
+        }
+        Ok(())
+    }
+
+    /// Checks whether an existing entry may be replaced by `entry`.
+    ///
+    /// # Behaviour
+    ///
+    /// Should the owner key change then the quota of the new key is checked as
+    /// well.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotFound` should there be no entry to replace, new
+    /// names have to pass `check_add`. Returns `RNSDNSERRORS::UpdateTooFrequent`
+    /// should the previous update be too recent, along with the errors of
+    /// `check_add`.
+    pub fn check_override(
+        &self,
+        store: &DnsEntryStore,
+        entry: &DnsEntry,
+        now: DateTime<Utc>,
+    ) -> Result<(), RNSDNSERRORS> {
+        if entry.destinations().len() > self.max_destinations {
+            return Err(RNSDNSERRORS::TooManyDestinations);
+        }
+
+        let existing = store.lookup(entry.name()).ok_or(RNSDNSERRORS::NotFound)?;
+
+        if now - existing.timestamp() < self.min_update_interval {
+            return Err(RNSDNSERRORS::UpdateTooFrequent);
+        }
+        if existing.public_key() != entry.public_key()
+            && store.count_entries_by_key(entry.public_key()) >= self.max_names_per_key
+        {
+            return Err(RNSDNSERRORS::QuotaExceeded);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reticulum::hash::AddressHash;
+    use reticulum::identity::PrivateIdentity;
+
+    use super::*;
+
+    fn policy() -> EntryPolicy {
+        EntryPolicy::new(2, TimeDelta::minutes(10), 2)
+    }
+
+    fn entry(name: &str, destinations: usize, owner: &PrivateIdentity) -> DnsEntry {
+        let destinations = (0..destinations)
+            .map(|i| AddressHash::new([i as u8; 16]))
+            .collect();
+        DnsEntry::new_signed(name.to_owned(), destinations, 1, owner)
+    }
+
+    fn store_of(entries: Vec<DnsEntry>) -> DnsEntryStore {
+        let mut store = DnsEntryStore::new_empty();
+        for entry in entries {
+            store.override_entry(entry);
+        }
+        store
+    }
+
+    #[test]
+    fn add_within_limits_is_allowed() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let store = store_of(vec![entry("a.node", 1, &owner)]);
+        let key = owner.as_identity().public_key;
+        assert!(policy().check_add(&store, &key, 2).is_ok());
+    }
+
+    #[test]
+    fn add_with_too_many_destinations_is_rejected() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let key = owner.as_identity().public_key;
+        let result = policy().check_add(&DnsEntryStore::new_empty(), &key, 3);
+        assert!(matches!(result, Err(RNSDNSERRORS::TooManyDestinations)));
+    }
+
+    #[test]
+    fn add_beyond_the_quota_of_the_key_is_rejected() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let store = store_of(vec![entry("a.node", 1, &owner), entry("b.node", 1, &owner)]);
+        let key = owner.as_identity().public_key;
+        let result = policy().check_add(&store, &key, 1);
+        assert!(matches!(result, Err(RNSDNSERRORS::QuotaExceeded)));
+    }
+
+    #[test]
+    fn override_after_the_interval_is_allowed() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let existing = entry("a.node", 1, &owner);
+        let later = existing.timestamp() + TimeDelta::minutes(10);
+        let store = store_of(vec![existing]);
+        let result = policy().check_override(&store, &entry("a.node", 2, &owner), later);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn override_of_a_missing_name_is_not_found() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let update = entry("a.node", 1, &owner);
+        let now = update.timestamp();
+        let result = policy().check_override(&DnsEntryStore::new_empty(), &update, now);
+        assert!(matches!(result, Err(RNSDNSERRORS::NotFound)));
+    }
+
+    #[test]
+    fn override_with_too_many_destinations_is_rejected() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let existing = entry("a.node", 1, &owner);
+        let later = existing.timestamp() + TimeDelta::minutes(10);
+        let store = store_of(vec![existing]);
+        let result = policy().check_override(&store, &entry("a.node", 3, &owner), later);
+        assert!(matches!(result, Err(RNSDNSERRORS::TooManyDestinations)));
+    }
+
+    #[test]
+    fn override_within_the_interval_is_rejected() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let existing = entry("a.node", 1, &owner);
+        let soon = existing.timestamp() + TimeDelta::minutes(9);
+        let store = store_of(vec![existing]);
+        let result = policy().check_override(&store, &entry("a.node", 1, &owner), soon);
+        assert!(matches!(result, Err(RNSDNSERRORS::UpdateTooFrequent)));
+    }
+
+    #[test]
+    fn override_to_a_key_beyond_its_quota_is_rejected() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let other = PrivateIdentity::new_from_name("other");
+        let existing = entry("a.node", 1, &owner);
+        let later = existing.timestamp() + TimeDelta::minutes(10);
+        let store = store_of(vec![
+            existing,
+            entry("b.node", 1, &other),
+            entry("c.node", 1, &other),
+        ]);
+        let result = policy().check_override(&store, &entry("a.node", 1, &other), later);
+        assert!(matches!(result, Err(RNSDNSERRORS::QuotaExceeded)));
+    }
+
+    #[test]
+    fn override_keeping_the_key_ignores_its_quota() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let existing = entry("a.node", 1, &owner);
+        let later = existing.timestamp() + TimeDelta::minutes(10);
+        let store = store_of(vec![existing, entry("b.node", 1, &owner)]);
+        let result = policy().check_override(&store, &entry("a.node", 1, &owner), later);
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use reticulum::hash::AddressHash;
+
+/// The most buckets kept per scope before full ones are forgotten.
+const MAX_BUCKETS: usize = 4096;
+
+/// The size and refill rate of a token bucket.
+///
+/// `capacity` - How many queries may arrive in a burst.
+/// `refill_per_second` - How many queries per second are allowed on average.
+#[derive(Clone, Copy, Debug)]
+pub struct BucketConfig {
+    pub capacity: f64,
+    pub refill_per_second: f64,
+}
+
+impl BucketConfig {
+    pub fn new(capacity: f64, refill_per_second: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_second,
+        }
+    }
+}
+
+/// What happens to a query that is over the limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverLimit {
+    /// The query is answered with `ERR 3` so that an honest client can back off.
+    Reply,
+    /// The query is dropped without an answer, which costs the server nothing.
+    Drop,
+}
+
+/// The limits the server applies to incoming queries.
+///
+/// # Fields
+/// `link` - The limit of a single link.
+/// `identity` - The limit of a peer identity over all of its links.
+/// `over_limit` - What happens to queries over any of the limits.
+///
+/// # Reasoning
+///
+/// A peer can open as many links as it likes, so the per link limit alone is
+/// easily avoided. The identity limit catches a peer that identified itself
+/// across all of its links.
+///
+/// There is no limit per interface `AddressHash`. The `LinkEventData` of the
+/// transport only carries the link id and the destination, the interface a
+/// packet arrived on stays inside the transport, so a query can not be
+/// attributed to an interface. Should the transport ever expose it, a third
+/// scope next to `link` and `identity` is all that is needed.
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    pub link: BucketConfig,
+    pub identity: BucketConfig,
+    pub over_limit: OverLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            link: BucketConfig::new(10.0, 2.0),
+            identity: BucketConfig::new(20.0, 4.0),
+            over_limit: OverLimit::Reply,
+        }
+    }
+}
+
+/// The scope whose limit a query exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitedBy {
+    Link,
+    Identity,
+}
+
+/// What a query is attributed to. `identity` is the identity the peer proved on
+/// the link with `IDENTIFY`, `None` while it did not identify itself.
+pub struct RateLimitKeys {
+    pub link: AddressHash,
+    pub identity: Option<AddressHash>,
+}
+
+#[derive(Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, config: &BucketConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.refill_per_second).min(config.capacity);
+        self.updated = now;
+    }
+}
+
+/// The buckets of one scope.
+#[derive(Default)]
+struct Buckets {
+    buckets: HashMap<AddressHash, TokenBucket>,
+}
+
+impl Buckets {
+    /// Refills the bucket of `key` and returns whether it holds a token.
+    fn has_token(&mut self, key: &AddressHash, config: &BucketConfig, now: Instant) -> bool {
+        if self.buckets.len() >= MAX_BUCKETS {
+            // a full bucket is the same as one that was never used
+            self.buckets.retain(|_, bucket| {
+                bucket.refill(config, now);
+                bucket.tokens < config.capacity
+            });
+        }
+        let bucket = self.buckets.entry(*key).or_insert(TokenBucket {
+            tokens: config.capacity,
+            updated: now,
+        });
+        bucket.refill(config, now);
+        bucket.tokens >= 1.0
+    }
+
+    fn take(&mut self, key: &AddressHash) {
+        if let Some(bucket) = self.buckets.get_mut(key) {
+            bucket.tokens -= 1.0;
+        }
+    }
+}
+
+/// The counters of the rate limiter, for monitoring.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitCounters {
+    pub allowed: u64,
+    pub limited_link: u64,
+    pub limited_identity: u64,
+}
+
+impl RateLimitCounters {
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"allowed":{},"limited_link":{},"limited_identity":{}}}"#,
+            self.allowed, self.limited_link, self.limited_identity
+        )
+    }
+}
+
+/// Token bucket rate limiting of incoming queries.
+///
+/// # Behaviour
+///
+/// A query has to find a token in every bucket it is attributed to and only
+/// then takes one from each, so a query rejected by one scope does not use up
+/// the budget of the others.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    links: Buckets,
+    identities: Buckets,
+    counters: RateLimitCounters,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            links: Buckets::default(),
+            identities: Buckets::default(),
+            counters: RateLimitCounters::default(),
+        }
+    }
+
+    pub fn over_limit(&self) -> OverLimit {
+        self.config.over_limit
+    }
+
+    pub fn counters(&self) -> RateLimitCounters {
+        self.counters
+    }
+
+    /// Checks a query against every limit it is attributed to.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first scope whose limit the query exceeds.
+    pub fn check(&mut self, keys: &RateLimitKeys, now: Instant) -> Result<(), LimitedBy> {
+        let config = &self.config;
+        let limited = if !self.links.has_token(&keys.link, &config.link, now) {
+            Some(LimitedBy::Link)
+        } else if keys
+            .identity
+            .is_some_and(|key| !self.identities.has_token(&key, &config.identity, now))
+        {
+            Some(LimitedBy::Identity)
+        } else {
+            None
+        };
+
+        match limited {
+            Some(scope) => {
+                match scope {
+                    LimitedBy::Link => self.counters.limited_link += 1,
+                    LimitedBy::Identity => self.counters.limited_identity += 1,
+                }
+                Err(scope)
+            }
+            None => {
+                self.links.take(&keys.link);
+                if let Some(key) = &keys.identity {
+                    self.identities.take(key);
+                }
+                self.counters.allowed += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            link: BucketConfig::new(2.0, 1.0),
+            identity: BucketConfig::new(3.0, 1.0),
+            over_limit: OverLimit::Reply,
+        })
+    }
+
+    fn keys(link: u8, identity: Option<u8>) -> RateLimitKeys {
+        RateLimitKeys {
+            link: AddressHash::new([link; 16]),
+            identity: identity.map(|identity| AddressHash::new([identity; 16])),
+        }
+    }
+
+    #[test]
+    fn burst_up_to_the_capacity_is_allowed() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        assert_eq!(limiter.check(&keys(1, None), now), Ok(()));
+        assert_eq!(limiter.check(&keys(1, None), now), Ok(()));
+        assert_eq!(limiter.check(&keys(1, None), now), Err(LimitedBy::Link));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.check(&keys(1, None), now).unwrap();
+        limiter.check(&keys(1, None), now).unwrap();
+        assert!(limiter.check(&keys(1, None), now).is_err());
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check(&keys(1, None), later), Ok(()));
+        assert!(limiter.check(&keys(1, None), later).is_err());
+    }
+
+    #[test]
+    fn refill_is_capped_at_the_capacity() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.check(&keys(1, None), now).unwrap();
+
+        let later = now + Duration::from_secs(60);
+        assert_eq!(limiter.check(&keys(1, None), later), Ok(()));
+        assert_eq!(limiter.check(&keys(1, None), later), Ok(()));
+        assert!(limiter.check(&keys(1, None), later).is_err());
+    }
+
+    #[test]
+    fn identity_is_limited_across_its_links() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        for link in 1..=3 {
+            assert_eq!(limiter.check(&keys(link, Some(9)), now), Ok(()));
+        }
+        assert_eq!(limiter.check(&keys(4, Some(9)), now), Err(LimitedBy::Identity));
+        assert_eq!(limiter.check(&keys(4, None), now), Ok(()));
+    }
+
+    #[test]
+    fn rejected_query_does_not_take_a_token() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.check(&keys(1, Some(9)), now).unwrap();
+        limiter.check(&keys(1, Some(9)), now).unwrap();
+        assert_eq!(limiter.check(&keys(1, Some(9)), now), Err(LimitedBy::Link));
+
+        // the identity still holds the token the link refused to spend
+        assert_eq!(limiter.check(&keys(2, Some(9)), now), Ok(()));
+        assert_eq!(limiter.check(&keys(2, Some(9)), now), Err(LimitedBy::Identity));
+    }
+
+    #[test]
+    fn counters_count_every_outcome() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        for link in 1..=4 {
+            let _ = limiter.check(&keys(link, Some(9)), now);
+        }
+        for _ in 0..3 {
+            let _ = limiter.check(&keys(5, None), now);
+        }
+
+        let counters = limiter.counters();
+        assert_eq!(counters.allowed, 5);
+        assert_eq!(counters.limited_identity, 1);
+        assert_eq!(counters.limited_link, 1);
+    }
+}
@@ -0,0 +1,317 @@
+//! The resolver mode of the server, in which queries for names the server is
+//! not authoritative for are forwarded to upstream servers.
+//!
+//! A forwarded query is sent as a query of its own with the hop counter of the
+//! original one counted up. Once the upstream server answered, the answer is
+//! validated, cached until its records expire and sent back to the client in an
+//! envelope signed by this server.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::VerifyingKey;
+use reticulum::hash::AddressHash;
+
+use crate::resolver::envelope::{AnswerError, PendingQueries, QUERY_TIMEOUT};
+use crate::resolver::negative::NEGATIVE_TTL;
+use crate::resolver::{DenialAnswer, EntryAnswer, zone_key};
+use crate::server::announce::Capabilities;
+use crate::server::delegation::{ChainError, TrustAnchor, is_within};
+use crate::server::envelope::{NONCE_LENGTH, Query, QueryFlags};
+use crate::server::parser::select_request;
+
+/// The most upstream servers a resolver keeps links to.
+pub const MAX_UPSTREAMS: usize = 16;
+
+/// What a resolver does with a query.
+pub enum Resolution {
+    /// The query is answered from the zones of the server.
+    Local,
+    /// The query is answered right away with this wrapped answer, e.g. from the
+    /// cache.
+    Answer(String),
+    /// The query was forwarded and is answered once the upstream server did.
+    Forwarded,
+}
+
+/// Returns the name a request looks up, should it be a `LOOKUP`. Only lookups
+/// are forwarded, every other request is about the zones of this server.
+pub fn lookup_name(request: &str) -> Option<&str> {
+    let parsed = select_request(request).ok()?;
+    match parsed.command {
+        "LOOKUP" => parsed.args.first().copied(),
+        _ => None,
+    }
+}
+
+/// A server queries can be forwarded to.
+///
+/// # Fields
+/// `key` - The key the server signs its answers with.
+/// `zones` - The suffixes the server announced to be authoritative for.
+/// `configured` - Whether the operator configured the server as upstream rather
+/// than it being discovered from its announce.
+pub struct Upstream {
+    pub key: VerifyingKey,
+    pub zones: Vec<String>,
+    pub configured: bool,
+}
+
+/// The upstream servers known to a resolver.
+///
+/// # Reasoning
+///
+/// A discovered server that announced a suffix containing the name is asked
+/// first, the one with the longest suffix, since it holds the records. Names no
+/// announced suffix contains are forwarded to a configured upstream, which may
+/// itself be a resolver.
+#[derive(Default)]
+pub struct Upstreams {
+    upstreams: HashMap<AddressHash, Upstream>,
+}
+
+impl Upstreams {
+    /// Learns about a server from its verified capabilities.
+    ///
+    /// Returns `false` should there already be `MAX_UPSTREAMS` other servers.
+    pub fn learn(
+        &mut self,
+        destination: AddressHash,
+        key: VerifyingKey,
+        capabilities: &Capabilities,
+        configured: bool,
+    ) -> bool {
+        if !self.upstreams.contains_key(&destination) && self.upstreams.len() >= MAX_UPSTREAMS {
+            return false;
+        }
+        let upstream = Upstream {
+            key,
+            zones: capabilities.zones.clone(),
+            configured,
+        };
+        self.upstreams.insert(destination, upstream);
+        true
+    }
+
+    pub fn get(&self, destination: &AddressHash) -> Option<&Upstream> {
+        self.upstreams.get(destination)
+    }
+
+    /// Picks the server to forward a lookup of `name` to among those `usable`.
+    pub fn select(&self, name: &str, usable: impl Fn(&AddressHash) -> bool) -> Option<AddressHash> {
+        let usable: Vec<_> = self
+            .upstreams
+            .iter()
+            .filter(|(destination, _)| usable(destination))
+            .collect();
+        let authoritative = usable
+            .iter()
+            .filter_map(|(destination, upstream)| {
+                let suffix = upstream.zones.iter().filter(|zone| is_within(name, zone));
+                suffix
+                    .map(|zone| zone.len())
+                    .max()
+                    .map(|len| (len, **destination))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, destination)| destination);
+        authoritative.or_else(|| {
+            usable
+                .iter()
+                .find(|(_, upstream)| upstream.configured)
+                .map(|(destination, _)| **destination)
+        })
+    }
+}
+
+/// A query of a client that waits for the answer of an upstream server.
+///
+/// # Fields
+/// `link` - The link the query arrived on.
+/// `id` - The id the client chose.
+/// `nonce` - The nonce the client chose.
+/// `request` - The request of the client.
+/// `upstream` - The server the query was forwarded to.
+pub struct Waiting {
+    pub link: AddressHash,
+    pub id: u16,
+    pub nonce: [u8; NONCE_LENGTH],
+    pub request: String,
+    pub upstream: AddressHash,
+    sent_at: DateTime<Utc>,
+}
+
+impl Waiting {
+    pub fn new(link: AddressHash, query: &Query, upstream: AddressHash) -> Self {
+        Self {
+            link,
+            id: query.id,
+            nonce: query.nonce,
+            request: query.request.to_owned(),
+            upstream,
+            sent_at: Utc::now(),
+        }
+    }
+
+    /// The query of the client, to sign the answer for.
+    pub fn query(&self) -> Query<'_> {
+        Query {
+            id: self.id,
+            nonce: self.nonce,
+            flags: QueryFlags::default(),
+            request: &self.request,
+        }
+    }
+}
+
+/// Keeps track of the queries forwarded to upstream servers.
+///
+/// # Fields
+/// `pending` - The forwarded queries, by the id this server chose for them.
+/// `waiting` - The queries of the clients, by the same id.
+#[derive(Default)]
+pub struct Forwarder {
+    pending: PendingQueries,
+    waiting: HashMap<u16, Waiting>,
+}
+
+impl Forwarder {
+    /// Registers the query of a client and returns the query to send upstream,
+    /// which carries `flags`.
+    pub fn forward(&mut self, waiting: Waiting, flags: QueryFlags, now: DateTime<Utc>) -> String {
+        self.waiting
+            .retain(|_, waiting| now - waiting.sent_at <= QUERY_TIMEOUT);
+        let (id, query) = self.pending.prepare_with(&waiting.request, flags, now);
+        self.waiting.insert(id, waiting);
+        query
+    }
+
+    /// Verifies the answer of an upstream server, everything after the `ANSWER`
+    /// command, and returns the query of the client it belongs to with the
+    /// response.
+    ///
+    /// # Errors
+    ///
+    /// See `PendingQueries::accept`. An answer from another server than the
+    /// query was forwarded to is a mismatch.
+    pub fn accept(
+        &mut self,
+        upstream: &AddressHash,
+        payload: &str,
+        key: &VerifyingKey,
+        now: DateTime<Utc>,
+    ) -> Result<(Waiting, String), AnswerError> {
+        let (id, response) = self.pending.accept_with_id(payload, key, now)?;
+        match self.waiting.remove(&id) {
+            Some(waiting) if waiting.upstream == *upstream => Ok((waiting, response)),
+            _ => Err(AnswerError::Mismatch),
+        }
+    }
+}
+
+/// Why the answer of an upstream server was not passed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The answer could not be parsed.
+    Malformed,
+    /// The answer is about another name than the one looked up.
+    WrongName,
+    /// The entry is not signed by its owner and the zone, or the denial not by
+    /// the zone.
+    InvalidSignature,
+    /// The chain does not lead from an anchor to the zone of the name.
+    Chain(ChainError),
+    /// The proof does not show the entry to be in the transparency log.
+    NotInLog,
+    /// The entry or the denial already expired.
+    Expired,
+}
+
+/// Validates the response of an upstream server to a lookup of `name` and
+/// returns until when it may be cached.
+///
+/// # Behaviour
+///
+/// Entries have to be signed by their owner and carry a signing of the key of
+/// their zone, as does the proof should there be one. Denials have to be
+/// signed by the key of the zone. With anchors the chain has to lead from one
+/// of them to the zone, without anchors the key of the zone is taken from the
+/// chain as is, or is the key of the upstream server itself.
+///
+/// Entries are cached until they expire, denials as long as a client relies on
+/// them. Other responses, e.g. errors, are passed on but not cached, `None`.
+///
+/// # Errors
+///
+/// Returns the first check the response does not pass.
+pub fn validate(
+    response: &str,
+    name: &str,
+    upstream_key: &VerifyingKey,
+    anchors: &[TrustAnchor],
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, ValidationError> {
+    if let Some(answer) = response.strip_prefix("ENTRY ") {
+        let EntryAnswer {
+            entry,
+            chain,
+            proof,
+        } = EntryAnswer::parse(answer).ok_or(ValidationError::Malformed)?;
+        if entry.name() != name {
+            return Err(ValidationError::WrongName);
+        }
+        if !anchors.is_empty() {
+            let chain = chain
+                .as_ref()
+                .ok_or(ValidationError::Chain(ChainError::NoAnchor))?;
+            chain
+                .validate(anchors, &entry, now)
+                .map_err(ValidationError::Chain)?;
+        }
+        let key = zone_key(chain.as_ref(), upstream_key);
+        let signed = entry
+            .verifications()
+            .iter()
+            .any(|signing| signing.verify_with_key(&entry, &key));
+        if !entry.verify_signature() || !signed {
+            return Err(ValidationError::InvalidSignature);
+        }
+        if proof.is_some_and(|proof| !proof.verify(&entry, &key)) {
+            return Err(ValidationError::NotInLog);
+        }
+        if entry.expiry() <= now {
+            return Err(ValidationError::Expired);
+        }
+        return Ok(Some(entry.expiry()));
+    }
+
+    if let Some(answer) = response.strip_prefix("NXNAME ") {
+        let DenialAnswer { denial, chain } =
+            DenialAnswer::parse(answer).ok_or(ValidationError::Malformed)?;
+        if denial.name != name {
+            return Err(ValidationError::WrongName);
+        }
+        let key = match anchors.is_empty() {
+            true => zone_key(chain.as_ref(), upstream_key),
+            false => {
+                let chain = chain
+                    .as_ref()
+                    .ok_or(ValidationError::Chain(ChainError::NoAnchor))?;
+                let zone = chain
+                    .validate_zone(anchors, name, now)
+                    .map_err(ValidationError::Chain)?;
+                *zone.key()
+            }
+        };
+        if !denial.verify(&key) {
+            return Err(ValidationError::InvalidSignature);
+        }
+        let expires = denial.timestamp + NEGATIVE_TTL;
+        if expires <= now {
+            return Err(ValidationError::Expired);
+        }
+        return Ok(Some(expires));
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, TimeDelta, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use reticulum::hash::AddressHash;
+use reticulum::identity::{Identity, PrivateIdentity};
+use x25519_dalek::PublicKey;
+
+use crate::server::server::{DnsEntry, RNSDNSERRORS};
+use crate::utilites::codec::{Reader, Writer, from_hex, to_hex};
+use crate::verifier::encode_entry;
+
+// The registration protocol
+//
+// owner  -> server : CHALLENGE
+// server -> owner  : CHALLENGE <challenge>
+// owner  -> server : CREATE <entry> <proof>
+// server -> owner  : OK <name> | ERR <code>
+//
+// The challenge is sent hex encoded, the entry and the proof base64 encoded.
+//
+// The identification protocol
+//
+// node   -> server : CHALLENGE
+// server -> node   : CHALLENGE <challenge>
+// node   -> server : IDENTIFY <identity> <proof>
+// server -> node   : IDENTIFIED <identity hash> | ERR <code>
+//
+// The identity holds the public and the verifying key, it is sent base64 encoded
+// as is the proof. Every later request over the link is made by that identity.
+// Servers identify themselves the same way on the links they open to each other.
+//
+// The renewal and removal protocol
+//
+// owner  -> server : RENEW <name>
+// owner  -> server : REMOVE <name> <signature>
+// server -> owner  : OK <name> | ERR <code>
+//
+// A renewal has to be requested by the owner after it identified itself. The
+// removal carries the signature of the owner over the name and the serial of the
+// entry it removes, base64 encoded, so it can be relayed by anyone.
+
+/// The length of a registration challenge.
+pub const CHALLENGE_LENGTH: usize = 32;
+/// How long a challenge may be answered after it was issued.
+pub const CHALLENGE_TIMEOUT: TimeDelta = TimeDelta::minutes(2);
+/// The most challenges that are outstanding at once.
+const MAX_CHALLENGES: usize = 1024;
+/// Separates the proof from every other signature made with the owner key.
+const PROOF_CONTEXT: &[u8] = b"RNSDNS-CREATE";
+/// Separates the proof of an identification from every other signature.
+const IDENTIFY_CONTEXT: &[u8] = b"RNSDNS-IDENTIFY";
+/// Separates the removal of an entry from every other signature of the owner.
+const REMOVAL_CONTEXT: &[u8] = b"RNSDNS-REMOVE";
+
+/// Returns the bytes an owner signs to prove that it holds the key of the entry.
+pub fn proof_bytes(challenge: &[u8; CHALLENGE_LENGTH], entry: &DnsEntry) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer
+        .put_fixed(PROOF_CONTEXT)
+        .put_fixed(challenge)
+        .put_bytes(&entry.signable_bytes());
+    writer.finish()
+}
+
+/// Builds the `CREATE` request of an owner for the challenge it was issued.
+pub fn create_request(
+    challenge: &[u8; CHALLENGE_LENGTH],
+    entry: &DnsEntry,
+    private_id: &PrivateIdentity,
+) -> String {
+    let proof = private_id.sign(&proof_bytes(challenge, entry));
+    let mut writer = Writer::new();
+    writer.put_signature(&proof);
+    format!(
+        "CREATE {} {}",
+        encode_entry(entry),
+        URL_SAFE_NO_PAD.encode(writer.finish())
+    )
+}
+
+pub fn decode_proof(encoded: &str) -> Option<Signature> {
+    let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+    Reader::new(&bytes).get_signature().ok()
+}
+
+pub fn format_challenge(challenge: &[u8; CHALLENGE_LENGTH]) -> String {
+    format!("CHALLENGE {}", to_hex(challenge))
+}
+
+/// Checks that the entry is signed by its owner and that the proof was made
+/// with the same key for the challenge.
+///
+/// # Errors
+///
+/// Returns `RNSDNSERRORS::InvalidSignature` should either signature not hold.
+pub fn verify_proof(
+    challenge: &[u8; CHALLENGE_LENGTH],
+    entry: &DnsEntry,
+    proof: &Signature,
+) -> Result<(), RNSDNSERRORS> {
+    let owns_key = entry
+        .verifying_key()
+        .verify_strict(&proof_bytes(challenge, entry), proof)
+        .is_ok();
+    match entry.verify_signature() && owns_key {
+        true => Ok(()),
+        false => Err(RNSDNSERRORS::InvalidSignature),
+    }
+}
+
+/// Returns the bytes a node signs to prove that it holds an identity.
+///
+/// The destination of the server is signed as well, so that a server the node
+/// identified itself to can not pass the proof on to another one.
+pub fn identification_bytes(challenge: &[u8; CHALLENGE_LENGTH], server: &AddressHash) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer
+        .put_fixed(IDENTIFY_CONTEXT)
+        .put_fixed(challenge)
+        .put_address(server);
+    writer.finish()
+}
+
+/// Decodes the identity of an `IDENTIFY` request.
+pub fn decode_identity(encoded: &str) -> Option<Identity> {
+    let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+    let mut reader = Reader::new(&bytes);
+    let public_key = PublicKey::from(reader.get_array::<32>().ok()?);
+    let verifying_key = VerifyingKey::from_bytes(&reader.get_array().ok()?).ok()?;
+    Some(Identity::new(public_key, verifying_key))
+}
+
+/// Checks that the proof was made with the key of `identity` for the challenge
+/// and the server.
+///
+/// # Errors
+///
+/// Returns `RNSDNSERRORS::InvalidSignature` should the proof not hold.
+pub fn verify_identification(
+    challenge: &[u8; CHALLENGE_LENGTH],
+    server: &AddressHash,
+    identity: &Identity,
+    proof: &Signature,
+) -> Result<(), RNSDNSERRORS> {
+    identity
+        .verifying_key
+        .verify_strict(&identification_bytes(challenge, server), proof)
+        .map_err(|_| RNSDNSERRORS::InvalidSignature)
+}
+
+/// Builds the `IDENTIFY` request of a node for the challenge the server at
+/// `server` issued.
+pub fn identify_request(
+    challenge: &[u8; CHALLENGE_LENGTH],
+    server: &AddressHash,
+    private_id: &PrivateIdentity,
+) -> String {
+    let identity = private_id.as_identity();
+    let proof = private_id.sign(&identification_bytes(challenge, server));
+    let mut keys = Writer::new();
+    keys.put_fixed(identity.public_key.as_bytes())
+        .put_fixed(identity.verifying_key.as_bytes());
+    let mut signature = Writer::new();
+    signature.put_signature(&proof);
+    format!(
+        "IDENTIFY {} {}",
+        URL_SAFE_NO_PAD.encode(keys.finish()),
+        URL_SAFE_NO_PAD.encode(signature.finish())
+    )
+}
+
+/// Parses the challenge of a `CHALLENGE <challenge>` answer.
+pub fn parse_challenge(answer: &str) -> Option<[u8; CHALLENGE_LENGTH]> {
+    from_hex(answer.strip_prefix("CHALLENGE ")?.trim())?.try_into().ok()
+}
+
+/// Returns the bytes an owner signs to remove the version `serial` of its entry.
+///
+/// The serial is part of the signature so that a removal can not be replayed
+/// against an entry the owner registered again later with a higher serial.
+pub fn removal_bytes(name: &str, serial: u64) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer
+        .put_fixed(REMOVAL_CONTEXT)
+        .put_str(name)
+        .put_u64(serial);
+    writer.finish()
+}
+
+/// Builds the `REMOVE` request of an owner for its current entry.
+pub fn remove_request(entry: &DnsEntry, private_id: &PrivateIdentity) -> String {
+    let signature = private_id.sign(&removal_bytes(entry.name(), entry.serial()));
+    let mut writer = Writer::new();
+    writer.put_signature(&signature);
+    format!(
+        "REMOVE {} {}",
+        entry.name(),
+        URL_SAFE_NO_PAD.encode(writer.finish())
+    )
+}
+
+/// Checks that the removal of the version `serial` of `name` was signed with the
+/// key of the owner.
+///
+/// # Errors
+///
+/// Returns `RNSDNSERRORS::InvalidSignature` should the signature not hold.
+pub fn verify_removal(
+    key: &VerifyingKey,
+    name: &str,
+    serial: u64,
+    signature: &Signature,
+) -> Result<(), RNSDNSERRORS> {
+    key.verify_strict(&removal_bytes(name, serial), signature)
+        .map_err(|_| RNSDNSERRORS::InvalidSignature)
+}
+
+/// The challenges the server issued and that were not yet answered.
+///
+/// # Fields
+/// `issued` - The challenge and the time it was issued, by requester.
+///
+/// # Reasoning
+///
+/// The owner signature of an entry can be copied from any answer of the server,
+/// so it does not prove that the requester holds the key. Signing a fresh
+/// challenge does. A challenge is bound to the requester it was issued to and
+/// can only be used once.
+#[derive(Default)]
+pub struct Challenges {
+    issued: HashMap<AddressHash, ([u8; CHALLENGE_LENGTH], DateTime<Utc>)>,
+}
+
+impl Challenges {
+    /// Issues a new challenge to the requester, replacing any previous one.
+    pub fn issue(&mut self, requester: AddressHash, now: DateTime<Utc>) -> [u8; CHALLENGE_LENGTH] {
+        self.issued.retain(|_, (_, issued_at)| now - *issued_at <= CHALLENGE_TIMEOUT);
+        if self.issued.len() >= MAX_CHALLENGES
+            && let Some(oldest) = self
+                .issued
+                .iter()
+                .min_by_key(|(_, (_, issued_at))| *issued_at)
+                .map(|(requester, _)| *requester)
+        {
+            self.issued.remove(&oldest);
+        }
+
+        let mut challenge = [0u8; CHALLENGE_LENGTH];
+        OsRng.fill_bytes(&mut challenge);
+        self.issued.insert(requester, (challenge, now));
+        challenge
+    }
+
+    /// Takes the challenge of the requester, should it still be valid.
+    pub fn take(
+        &mut self,
+        requester: &AddressHash,
+        now: DateTime<Utc>,
+    ) -> Option<[u8; CHALLENGE_LENGTH]> {
+        let (challenge, issued_at) = self.issued.remove(requester)?;
+        (now - issued_at <= CHALLENGE_TIMEOUT).then_some(challenge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identify(
+        challenge: &[u8; CHALLENGE_LENGTH],
+        server: &AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> (Identity, Signature) {
+        let request = identify_request(challenge, server, private_id);
+        let args: Vec<&str> = request.split(' ').collect();
+        let ["IDENTIFY", identity, proof] = args.as_slice() else {
+            panic!("malformed request {request}");
+        };
+        (decode_identity(identity).unwrap(), decode_proof(proof).unwrap())
+    }
+
+    #[test]
+    fn identification_survives_encoding() {
+        let node = PrivateIdentity::new_from_name("node");
+        let server = AddressHash::new([1u8; 16]);
+        let challenge = [3u8; CHALLENGE_LENGTH];
+        let (identity, proof) = identify(&challenge, &server, &node);
+        assert_eq!(identity.address_hash, node.as_identity().address_hash);
+        assert!(verify_identification(&challenge, &server, &identity, &proof).is_ok());
+    }
+
+    #[test]
+    fn identification_is_bound_to_the_server() {
+        let node = PrivateIdentity::new_from_name("node");
+        let challenge = [3u8; CHALLENGE_LENGTH];
+        let (identity, proof) = identify(&challenge, &AddressHash::new([1u8; 16]), &node);
+        let other = AddressHash::new([2u8; 16]);
+        let result = verify_identification(&challenge, &other, &identity, &proof);
+        assert!(matches!(result, Err(RNSDNSERRORS::InvalidSignature)));
+    }
+
+    #[test]
+    fn identification_is_bound_to_the_challenge() {
+        let node = PrivateIdentity::new_from_name("node");
+        let server = AddressHash::new([1u8; 16]);
+        let (identity, proof) = identify(&[3u8; CHALLENGE_LENGTH], &server, &node);
+        let result = verify_identification(&[4u8; CHALLENGE_LENGTH], &server, &identity, &proof);
+        assert!(matches!(result, Err(RNSDNSERRORS::InvalidSignature)));
+    }
+
+    #[test]
+    fn challenge_survives_formatting() {
+        let challenge = [0xa5u8; CHALLENGE_LENGTH];
+        assert_eq!(parse_challenge(&format_challenge(&challenge)), Some(challenge));
+        assert_eq!(parse_challenge("CHALLENGE 00"), None);
+        assert_eq!(parse_challenge("ERR 21"), None);
+    }
+
+    #[test]
+    fn challenge_is_used_once() {
+        let mut challenges = Challenges::default();
+        let requester = AddressHash::new([1u8; 16]);
+        let now = Utc::now();
+        let challenge = challenges.issue(requester, now);
+        assert_eq!(challenges.take(&requester, now), Some(challenge));
+        assert_eq!(challenges.take(&requester, now), None);
+    }
+
+    #[test]
+    fn challenge_expires() {
+        let mut challenges = Challenges::default();
+        let requester = AddressHash::new([1u8; 16]);
+        let now = Utc::now();
+        challenges.issue(requester, now);
+        let later = now + CHALLENGE_TIMEOUT + TimeDelta::seconds(1);
+        assert_eq!(challenges.take(&requester, later), None);
+    }
+}
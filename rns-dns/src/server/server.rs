@@ -1,22 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::default;
-use std::sync::{Arc, RwLock};
+use std::fs;
+use std::io;
+use std::ops::{Bound, RangeInclusive};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock, RwLockWriteGuard};
 
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use im::HashMap as ImHashMap;
+use im::OrdMap;
 
 use chrono::DateTime;
 use chrono::Utc;
 
-use ed25519_dalek::Signature;
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
 use rand_core::OsRng;
 use reticulum::destination::Destination;
 use reticulum::hash::AddressHash;
-use reticulum::identity::Identity;
+use reticulum::identity::{Identity, PrivateIdentity};
 use x25519_dalek::PublicKey;
 
 use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 
+use crate::server::acl::AccessList;
+use crate::server::audit::{AuditAction, AuditLog};
+use crate::server::delegation::TrustChain;
+use crate::server::denial::DenialOfExistence;
+use crate::server::envelope::{Query, SignedAnswer};
+use crate::server::gossip::{Gossip, MAX_GOSSIP_AGE, Payload, Version};
+use crate::server::integrity::{self, IntegrityReport};
+use crate::server::transparency::{InclusionProof, SignedTreeHead, TransparencyLog};
+use crate::server::policy::EntryPolicy;
+use crate::server::registration::{self, CHALLENGE_LENGTH, Challenges};
+use crate::server::transfer::{
+    CHANGE_PAGE_SIZE, Change, ChangePage, MAX_HISTORY, TRANSFER_PAGE_SIZE, TransferPage,
+};
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
 pub const RECORD_EXPIRY: chrono::TimeDelta = chrono::Duration::days(365);
 
 /// This is a single dns entry. It serves to provide the destination, public key and
@@ -27,6 +49,8 @@ pub const RECORD_EXPIRY: chrono::TimeDelta = chrono::Duration::days(365);
 /// `destination` - The destination the domain points to.
 /// `public_key` - The public key from which the destination is derived from. It is
 /// also used in verification of the record.
+/// `verifying_key` - The signing half of the owner identity which validates the
+/// `signature`.
 /// `timestamp` - The timestamp at which the record was last updated.
 /// `expiry` - The timestamp at which the record will cease to be valid.
 /// `signature` - The signature to validate the record.
@@ -65,6 +89,11 @@ pub struct DnsEntry {
     /// The public key from which the destination is derived from. It is
     /// also used in verification of the record.
     public_key: PublicKey,
+    /// The key of the owner which validates the `signature`.
+    verifying_key: VerifyingKey,
+    /// The version of the record chosen by the owner. Every update has to carry
+    /// a higher serial than the record it replaces.
+    serial: u64,
     /// The timestamp at which the record was last updated.
     timestamp: DateTime<Utc>,
     /// The timestamp at which the record will cease to be valid.
@@ -77,26 +106,34 @@ pub struct DnsEntry {
     verifications: Vec<VerifierSigning>,
 }
 
-impl Default for DnsEntry {
-    fn default() -> Self {
+impl DnsEntry {
+    /// Creates an entry for the identity of its owner and signs it.
+    ///
+    /// The timestamp and expiry are only placeholders, the server sets them once
+    /// it accepts the entry.
+    pub fn new_signed(
+        name: String,
+        destinations: Vec<AddressHash>,
+        serial: u64,
+        private_id: &PrivateIdentity,
+    ) -> Self {
         let now = Utc::now();
-        Self {
-            name: "example.com".into(),
-            destinations: vec![],
-            // public_key: String::default(),
-            public_key: todo!(),
-
+        let identity = private_id.as_identity();
+        let mut entry = Self {
+            name,
+            destinations,
+            public_key: identity.public_key,
+            verifying_key: identity.verifying_key,
+            serial,
             timestamp: now,
             expiry: now + RECORD_EXPIRY,
-            // signature: String::default(),
-            signature: todo!(),
-
-            verifications: vec![],
-        }
+            signature: Signature::from_bytes(&[0u8; 64]),
+            verifications: Vec::new(),
+        };
+        entry.signature = private_id.sign(&entry.signable_bytes());
+        entry
     }
-}
 
-impl DnsEntry {
     pub fn is_entry_expired(&self) -> bool {
         self.expiry > Utc::now()
     }
@@ -105,6 +142,174 @@ impl DnsEntry {
         self.timestamp = timestamp;
         self
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn destinations(&self) -> &Vec<AddressHash> {
+        &self.destinations
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn verifying_key(&self) -> &VerifyingKey {
+        &self.verifying_key
+    }
+
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    /// Returns the address hash of the identity that owns the entry.
+    pub fn owner_identity(&self) -> AddressHash {
+        Identity::new(self.public_key, self.verifying_key).address_hash
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn expiry(&self) -> DateTime<Utc> {
+        self.expiry
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn verifications(&self) -> &Vec<VerifierSigning> {
+        &self.verifications
+    }
+
+    /// Returns a copy of the entry that only carries the verifications of
+    /// verifiers whose trust level lies within `levels`.
+    ///
+    /// This implements the `level` field of a query, a client only receives the
+    /// authorities it asked for.
+    pub fn with_verifications_in(
+        &self,
+        levels: RangeInclusive<u32>,
+        registry: &VerifierRegistry,
+    ) -> DnsEntry {
+        let mut entry = self.clone();
+        entry.verifications.retain(|signing| {
+            registry
+                .get_verifier(&signing.destination)
+                .is_some_and(|v| levels.contains(&v.trust_level))
+        });
+        entry
+    }
+
+    /// Returns the bytes that the owner signs.
+    ///
+    /// The timestamp, expiry and verifications are controlled by the server and
+    /// are therefore not part of the owner signature.
+    ///
+    /// Entries that predate serials carry the serial `0` and were signed without
+    /// it, so it is only included from `1` onwards. Such an entry can never replace
+    /// one with a serial.
+    pub fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_str(&self.name);
+        writer.put_u32(self.destinations.len() as u32);
+        for destination in &self.destinations {
+            writer.put_address(destination);
+        }
+        writer
+            .put_fixed(self.public_key.as_bytes())
+            .put_fixed(self.verifying_key.as_bytes());
+        if self.serial > 0 {
+            writer.put_u64(self.serial);
+        }
+        writer.finish()
+    }
+
+    /// Checks the owner signature of the entry.
+    pub fn verify_signature(&self) -> bool {
+        self.verifying_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    /// Encodes everything but the verifications.
+    fn encode_record(&self, writer: &mut Writer) {
+        writer.put_str(&self.name);
+        writer.put_u32(self.destinations.len() as u32);
+        for destination in &self.destinations {
+            writer.put_address(destination);
+        }
+        writer
+            .put_fixed(self.public_key.as_bytes())
+            .put_fixed(self.verifying_key.as_bytes())
+            .put_u64(self.serial)
+            .put_time(&self.timestamp)
+            .put_time(&self.expiry)
+            .put_signature(&self.signature);
+    }
+
+    /// Encodes the complete entry including the verifications.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_record(&mut writer);
+        writer.put_u32(self.verifications.len() as u32);
+        for verification in &self.verifications {
+            verification.encode_into(&mut writer);
+        }
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Self::decode_with(reader, true)
+    }
+
+    /// Decodes an entry, `has_serial` is `false` for entries of database files
+    /// that predate serials.
+    fn decode_with(reader: &mut Reader, has_serial: bool) -> Result<Self, CodecError> {
+        let name = reader.get_str()?;
+        let destinations = (0..reader.get_u32()?)
+            .map(|_| reader.get_address())
+            .collect::<Result<Vec<_>, _>>()?;
+        let public_key = PublicKey::from(reader.get_array::<32>()?);
+        let verifying_key = VerifyingKey::from_bytes(&reader.get_array()?)
+            .map_err(|_| CodecError::InvalidData)?;
+        let serial = if has_serial { reader.get_u64()? } else { 0 };
+        let timestamp = reader.get_time()?;
+        let expiry = reader.get_time()?;
+        let signature = reader.get_signature()?;
+        let verifications = (0..reader.get_u32()?)
+            .map(|_| VerifierSigning::decode(reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            name,
+            destinations,
+            public_key,
+            verifying_key,
+            serial,
+            timestamp,
+            expiry,
+            signature,
+            verifications,
+        })
+    }
+
+    /// Returns the SHA-256 digest over the encoded entry.
+    pub fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+
+    /// Returns the SHA-256 digest over the record without its verifications.
+    ///
+    /// This stays the same no matter which verifications a query asked for, so
+    /// it is what the transparency log records.
+    pub fn record_digest(&self) -> [u8; 32] {
+        let mut writer = Writer::new();
+        self.encode_record(&mut writer);
+        Sha256::digest(writer.finish()).into()
+    }
 }
 
 /// This is the the signing of a verifier. It contains minimal information. Should
@@ -122,7 +327,8 @@ impl DnsEntry {
 /// is because it is expected that the client will perform a look-up on the
 /// destination and cache the results in a long term database. The contents of this
 /// cache are unlikely to change often and not repeatedly sending this data will
-/// greatly reduce the data being sent.
+/// greatly reduce the data being sent. The look-up is the `VERIFIER` query and the
+/// cache is the `VerifierCache` of the resolver.
 ///
 /// # Security
 ///
@@ -140,6 +346,63 @@ pub struct VerifierSigning {
     signature: Signature, // sig(dnsentry.sig(), verifier.destination)
 }
 
+impl VerifierSigning {
+    pub fn new(destination: AddressHash, signature: Signature) -> Self {
+        Self {
+            destination,
+            signature,
+        }
+    }
+
+    /// Signs the entry as the verifier with the given destination.
+    pub fn sign(entry: &DnsEntry, destination: AddressHash, private_id: &PrivateIdentity) -> Self {
+        let signature = private_id.sign(&Self::signable_bytes(entry, &destination));
+        Self::new(destination, signature)
+    }
+
+    pub fn destination(&self) -> &AddressHash {
+        &self.destination
+    }
+
+    /// Returns the bytes that the verifier signs for the given entry.
+    pub fn signable_bytes(entry: &DnsEntry, destination: &AddressHash) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer
+            .put_signature(&entry.signature)
+            .put_address(destination);
+        writer.finish()
+    }
+
+    /// Checks this signing against the entry and the key of the verifier.
+    pub fn verify(&self, entry: &DnsEntry, verifier: &Verifier) -> bool {
+        verifier.destination == self.destination
+            && self.verify_with_key(entry, &verifier.public_key)
+    }
+
+    /// Checks this signing against the entry and a key that was obtained without
+    /// the verifier registry, e.g. from a delegation.
+    pub fn verify_with_key(&self, entry: &DnsEntry, key: &VerifyingKey) -> bool {
+        key.verify_strict(
+            &Self::signable_bytes(entry, &self.destination),
+            &self.signature,
+        )
+        .is_ok()
+    }
+
+    pub fn encode_into(&self, writer: &mut Writer) {
+        writer
+            .put_address(&self.destination)
+            .put_signature(&self.signature);
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            destination: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
 /// This is a representation of a verification authority, a so called `verifier`.
 ///
 /// # Fields
@@ -185,17 +448,123 @@ pub struct Verifier {
     /// The trust level of the verifier. `0` represents the highest level.
     trust_level: u32,
     /// The public key used for validating signatures
-    public_key: PublicKey,
+    public_key: VerifyingKey,
+}
+
+impl Verifier {
+    pub fn new(
+        name: String,
+        destination: AddressHash,
+        trust_level: u32,
+        public_key: VerifyingKey,
+    ) -> Self {
+        Self {
+            name,
+            destination,
+            trust_level,
+            public_key,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn destination(&self) -> &AddressHash {
+        &self.destination
+    }
+
+    pub fn trust_level(&self) -> u32 {
+        self.trust_level
+    }
+
+    pub fn public_key(&self) -> &VerifyingKey {
+        &self.public_key
+    }
+
+    pub fn encode_into(&self, writer: &mut Writer) {
+        writer
+            .put_str(&self.name)
+            .put_address(&self.destination)
+            .put_u32(self.trust_level)
+            .put_fixed(self.public_key.as_bytes());
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            name: reader.get_str()?,
+            destination: reader.get_address()?,
+            trust_level: reader.get_u32()?,
+            public_key: VerifyingKey::from_bytes(&reader.get_array()?)
+                .map_err(|_| CodecError::InvalidData)?,
+        })
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RNSDNSERRORS {
     AlreadyExists,
+    /// The owner key already holds the maximum amount of names.
+    QuotaExceeded,
+    /// The name was updated more recently than the policy allows.
+    UpdateTooFrequent,
+    /// The entry lists more destinations than the policy allows.
+    TooManyDestinations,
+    /// The staging lock was poisoned by a panicking writer.
+    StagingPoisoned,
+    /// There is no entry for the requested name.
+    NotFound,
+    /// The mutation could not be written to the audit or transparency log and was
+    /// not applied.
+    AuditFailed,
+    /// The requester is not allowed to make this change.
+    NotAuthorized,
+    /// Trust level `0` is reserved for the dns server itself.
+    ReservedTrustLevel,
+    /// The verifier is not part of the registry.
+    UnknownVerifier,
+    /// A signature did not validate.
+    InvalidSignature,
+    /// No challenge was issued to the requester or it timed out.
+    MissingChallenge,
+    /// The serial of the update is not higher than the one of the stored entry.
+    /// `current` is sent back so that the owner can resync.
+    StaleSerial { current: u64 },
+    /// The changes since the requested serial are no longer remembered, a full
+    /// transfer is needed.
+    HistoryTooShort,
+}
+
+impl RNSDNSERRORS {
+    /// Returns the error code which is reported back to the requester.
+    ///
+    /// These codes are part of the protocol and must not be renumbered.
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::AlreadyExists => 10,
+            Self::QuotaExceeded => 11,
+            Self::UpdateTooFrequent => 12,
+            Self::TooManyDestinations => 13,
+            Self::StagingPoisoned => 14,
+            Self::NotFound => 15,
+            Self::AuditFailed => 16,
+            Self::NotAuthorized => 17,
+            Self::ReservedTrustLevel => 18,
+            Self::UnknownVerifier => 19,
+            Self::InvalidSignature => 20,
+            Self::MissingChallenge => 21,
+            Self::StaleSerial { .. } => 22,
+            Self::HistoryTooShort => 23,
+        }
+    }
 }
 
 /// This is the DnsDatabase.
 ///
 /// # Fields
 /// `entries` - This is the forward index which maps from the String to the DnsEntry
+/// It is kept in sorted order so that the neighbours of a missing name can be
+/// found for a denial of existence.
 /// `reverse_index` - This is the reverse index which maps from the Destination to
 /// the domain names
 ///
@@ -213,14 +582,14 @@ pub enum RNSDNSERRORS {
 /// handled by the function caller in a responsible manner.
 #[derive(Default, Clone)]
 pub struct DnsEntryStore {
-    forward_index: ImHashMap<String, DnsEntry>,
+    forward_index: OrdMap<String, DnsEntry>,
     reverse_index: ImHashMap<AddressHash, Vec<String>>,
 }
 
 impl DnsEntryStore {
     pub fn new_empty() -> Self {
         Self {
-            forward_index: ImHashMap::new(),
+            forward_index: OrdMap::new(),
             reverse_index: ImHashMap::new(),
         }
     }
@@ -245,6 +614,7 @@ impl DnsEntryStore {
         name: &String,
         destination: &AddressHash,
         public_key: &PublicKey,
+        verifying_key: &VerifyingKey,
         signature: Signature,
     ) -> Result<(), RNSDNSERRORS> {
         //
@@ -253,10 +623,12 @@ impl DnsEntryStore {
         }
 
         // get the domain names and if there are none just default to an empty vec
-        // let domain_names = self.reverse_index.entry(*destination).or_default();
+        let domain_names = self.reverse_index.entry(*destination).or_default();
 
         // add the domain name if it is not already present
-        // if !domain_names.contains(name) { domain_names.push(name.clone()); }
+        if !domain_names.contains(name) {
+            domain_names.push(name.clone());
+        }
 
         let now = Utc::now();
 
@@ -268,6 +640,8 @@ impl DnsEntryStore {
                 name: name.clone(),
                 destinations: vec![*destination],
                 public_key: public_key.clone(),
+                verifying_key: *verifying_key,
+                serial: 0,
                 timestamp: now,
                 expiry: now + RECORD_EXPIRY,
                 signature,
@@ -282,17 +656,55 @@ impl DnsEntryStore {
     ///
     /// # Behaviour
     ///
-    /// THIS FUNCTION DOES NOT UPDATE THE REVERSE INDEX.
-    ///
-    /// It first searches for the entry using the name from the entry which it then
-    /// overrides in its entirety
+    /// The entry stored under the name of `entry` is replaced in its entirety, or
+    /// `entry` is inserted should there be none yet. The reverse index is updated
+    /// for the destinations the entry lost and gained.
     pub fn override_entry(&mut self, entry: DnsEntry) {
-        let a = self.forward_index.entry(entry.name.clone()).or_default();
-        *a = entry;
+        let name = entry.name.clone();
+        self.unindex(&name);
+        for destination in &entry.destinations {
+            let names = self.reverse_index.entry(*destination).or_default();
+            if !names.contains(&name) {
+                names.push(name.clone());
+            }
+        }
+        self.forward_index.insert(name, entry);
+    }
+
+    /// Removes the name from the reverse index under every destination of the
+    /// entry stored for it.
+    fn unindex(&mut self, name: &str) {
+        let Some(entry) = self.forward_index.get(name) else {
+            return;
+        };
+        for destination in &entry.destinations {
+            let Some(names) = self.reverse_index.get_mut(destination) else {
+                continue;
+            };
+            names.retain(|n| n != name);
+            if names.is_empty() {
+                self.reverse_index.remove(destination);
+            }
+        }
+    }
+
+    /// Sets the expiry of an entry to `RECORD_EXPIRY` after `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotFound` should there be no such entry.
+    pub fn renew_entry(&mut self, name: &str, now: DateTime<Utc>) -> Result<(), RNSDNSERRORS> {
+        let entry = self
+            .forward_index
+            .get_mut(name)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        entry.expiry = now + RECORD_EXPIRY;
+        Ok(())
     }
 
-    /// Remove an entry from the forward index
+    /// Removes an entry from the forward and the reverse index.
     pub fn remove_domain(&mut self, domain: &str) {
+        self.unindex(domain);
         self.forward_index.remove(domain);
     }
 
@@ -305,6 +717,41 @@ impl DnsEntryStore {
         self.forward_index.get(name)
     }
 
+    /// Returns the names that come directly before and after `name` in sorted
+    /// order.
+    ///
+    /// # Behaviour
+    ///
+    /// `name` itself is never returned, so for a missing name these are the two
+    /// neighbours that prove there is nothing in between. `None` stands for the
+    /// start or the end of the index.
+    pub fn neighbours(&self, name: &str) -> (Option<&String>, Option<&String>) {
+        let name = name.to_owned();
+        let predecessor = self.forward_index.range(..name.clone()).next_back();
+        let successor = self
+            .forward_index
+            .range((Bound::Excluded(name), Bound::Unbounded))
+            .next();
+        (predecessor.map(|(k, _)| k), successor.map(|(k, _)| k))
+    }
+
+    /// Returns up to `limit` entries that come after `after` in sorted order, or
+    /// from the start should `after` be `None`, and whether these are the last
+    /// ones.
+    pub fn page(&self, after: Option<&str>, limit: usize) -> (Vec<DnsEntry>, bool) {
+        let start = match after {
+            Some(after) => Bound::Excluded(after.to_owned()),
+            None => Bound::Unbounded,
+        };
+        let mut entries = self.forward_index.range((start, Bound::Unbounded));
+        let page: Vec<DnsEntry> = entries
+            .by_ref()
+            .take(limit)
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        (page, entries.next().is_none())
+    }
+
     /// Returns the list of domain names which are associated with this destination.
     ///
     /// # Behaviour
@@ -334,6 +781,62 @@ impl DnsEntryStore {
         }
     }
 
+    /// Returns the amount of domain names that are owned by the given public key.
+    pub fn count_entries_by_key(&self, public_key: &PublicKey) -> usize {
+        self.forward_index
+            .values()
+            .filter(|entry| entry.public_key == *public_key)
+            .count()
+    }
+
+    /// Iterates over the forward index.
+    pub fn iter_forward_index(&self) -> impl Iterator<Item = (&String, &DnsEntry)> {
+        self.forward_index.iter()
+    }
+
+    /// Iterates over the reverse index.
+    pub fn iter_reverse_index(&self) -> impl Iterator<Item = (&AddressHash, &Vec<String>)> {
+        self.reverse_index.iter()
+    }
+
+    /// Encodes both indices. The reverse index is stored as is rather than
+    /// rebuilt on load so that drift between the two survives a restart and can
+    /// be detected.
+    pub fn encode_into(&self, writer: &mut Writer) {
+        writer.put_u32(self.forward_index.len() as u32);
+        for (name, entry) in &self.forward_index {
+            writer.put_str(name).put_bytes(&entry.encode());
+        }
+        writer.put_u32(self.reverse_index.len() as u32);
+        for (destination, names) in &self.reverse_index {
+            writer.put_address(destination);
+            writer.put_u32(names.len() as u32);
+            for name in names {
+                writer.put_str(name);
+            }
+        }
+    }
+
+    /// Decodes both indices. Database files before version 3 store entries
+    /// without a serial.
+    pub fn decode(reader: &mut Reader, version: u8) -> Result<Self, CodecError> {
+        let mut store = Self::new_empty();
+        for _ in 0..reader.get_u32()? {
+            let name = reader.get_str()?;
+            let mut entry_reader = Reader::new(reader.get_bytes()?);
+            let entry = DnsEntry::decode_with(&mut entry_reader, version >= 3)?;
+            store.forward_index.insert(name, entry);
+        }
+        for _ in 0..reader.get_u32()? {
+            let destination = reader.get_address()?;
+            let names = (0..reader.get_u32()?)
+                .map(|_| reader.get_str())
+                .collect::<Result<Vec<_>, _>>()?;
+            store.reverse_index.insert(destination, names);
+        }
+        Ok(store)
+    }
+
     /// Returns all of the forward index entries.
     pub fn get_active_forward_index(&self) -> Vec<DnsEntry> {
         self.forward_index.values().cloned().collect()
@@ -350,101 +853,2295 @@ pub struct VerificationStore {
     verifier_signings: ImHashMap<(String, AddressHash), Vec<VerifierSigning>>,
 }
 
-#[allow(unused_variables)]
 impl VerificationStore {
+    /// Iterates over every stored signing list.
+    pub fn iter(&self) -> impl Iterator<Item = (&(String, AddressHash), &Vec<VerifierSigning>)> {
+        self.verifier_signings.iter()
+    }
+
+    pub fn encode_into(&self, writer: &mut Writer) {
+        writer.put_u32(self.verifier_signings.len() as u32);
+        for ((name, destination), signings) in &self.verifier_signings {
+            writer.put_str(name).put_address(destination);
+            writer.put_u32(signings.len() as u32);
+            for signing in signings {
+                signing.encode_into(writer);
+            }
+        }
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let mut store = Self::default();
+        for _ in 0..reader.get_u32()? {
+            let key = (reader.get_str()?, reader.get_address()?);
+            let signings = (0..reader.get_u32()?)
+                .map(|_| VerifierSigning::decode(reader))
+                .collect::<Result<Vec<_>, _>>()?;
+            store.verifier_signings.insert(key, signings);
+        }
+        Ok(store)
+    }
+
+    /// Adds the signing of a verifier for the entry at the given destination.
+    ///
+    /// # Behaviour
+    ///
+    /// The signing is validated against the key of the verifier from the registry
+    /// before it is stored. A previous signing of the same verifier is replaced.
+    /// The signings are kept sorted by trust level, most trusted first.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotFound` should the entry not point to the
+    /// destination, `RNSDNSERRORS::UnknownVerifier` should the verifier not be
+    /// registered and `RNSDNSERRORS::InvalidSignature` should the signing not
+    /// validate.
     pub fn add_verification(
         &mut self,
-        name: String,
+        entry: &DnsEntry,
         destination: AddressHash,
-        signature: VerifierSigning,
+        signing: VerifierSigning,
+        registry: &VerifierRegistry,
     ) -> Result<(), RNSDNSERRORS> {
-        // TODO: verify the actual signature before doing anything
-        // maybe call on verify_verifier_signature()
-        todo!()
+        if !entry.destinations.contains(&destination) {
+            return Err(RNSDNSERRORS::NotFound);
+        }
+        if registry.get_verifier(&signing.destination).is_none() {
+            return Err(RNSDNSERRORS::UnknownVerifier);
+        }
+        if !self.verify_verifier_signature(entry, &signing, registry) {
+            return Err(RNSDNSERRORS::InvalidSignature);
+        }
+
+        let signings = self
+            .verifier_signings
+            .entry((entry.name.clone(), destination))
+            .or_default();
+        signings.retain(|s| s.destination != signing.destination);
+        signings.push(signing);
+        registry.sort_signings(signings);
+        Ok(())
     }
-    pub fn verify_verifier_signature(&self) -> bool {
-        todo!()
+
+    /// Checks the signing against the entry and the verifier key in the registry.
+    pub fn verify_verifier_signature(
+        &self,
+        entry: &DnsEntry,
+        signing: &VerifierSigning,
+        registry: &VerifierRegistry,
+    ) -> bool {
+        registry
+            .get_verifier(&signing.destination)
+            .is_some_and(|verifier| signing.verify(entry, verifier))
     }
 
-    pub fn get_domains_by_verifier(&self, verifier: Verifier) -> Vec<&DnsEntry> {
-        // will require additional args
-        // this might be really hard to keep clean
+    /// Removes the signing of a verifier and returns it.
+    pub fn remove_verification(
+        &mut self,
+        name: &str,
+        destination: &AddressHash,
+        verifier: &AddressHash,
+    ) -> Result<VerifierSigning, RNSDNSERRORS> {
+        let key = (name.to_owned(), *destination);
+        let signings = self
+            .verifier_signings
+            .get_mut(&key)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        let index = signings
+            .iter()
+            .position(|s| s.destination == *verifier)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        let signing = signings.remove(index);
+        if signings.is_empty() {
+            self.verifier_signings.remove(&key);
+        }
+        Ok(signing)
+    }
 
-        todo!()
+    /// Removes every signing that was made for the domain.
+    pub fn remove_domain(&mut self, name: &str) {
+        self.verifier_signings.retain(|(n, _), _| n != name);
     }
-    pub fn count_verifications(&self, name: String, destination: AddressHash) -> u32 {
-        todo!()
+
+    /// Removes every signing that was made for the entry. Signings are only kept
+    /// for the destinations of the entry, so unlike `remove_domain` this does
+    /// not have to go over the whole store.
+    pub fn remove_entry(&mut self, entry: &DnsEntry) {
+        for destination in &entry.destinations {
+            let key = (entry.name.clone(), *destination);
+            self.verifier_signings.remove(&key);
+        }
     }
-    pub fn get_verifications_for_domain(
-        &self,
-        name: String,
-        destination: AddressHash,
-    ) -> Result<&Vec<VerifierSigning>, RNSDNSERRORS> {
-        todo!()
+
+    /// Drops every signing of the domain which does not validate against `entry`
+    /// anymore, e.g. because the entry was replaced and its signature changed.
+    ///
+    /// Returns the destinations of the verifiers whose signings were dropped.
+    pub fn prune_invalid(
+        &mut self,
+        entry: &DnsEntry,
+        registry: &VerifierRegistry,
+    ) -> Vec<AddressHash> {
+        let mut dropped = Vec::new();
+        let keys: Vec<(String, AddressHash)> = self
+            .verifier_signings
+            .keys()
+            .filter(|(name, _)| *name == entry.name)
+            .cloned()
+            .collect();
+
+        for key in keys {
+            let pointed_to = entry.destinations.contains(&key.1);
+            let Some(signings) = self.verifier_signings.get_mut(&key) else {
+                continue;
+            };
+            signings.retain(|signing| {
+                let valid = pointed_to
+                    && registry
+                        .get_verifier(&signing.destination)
+                        .is_some_and(|v| signing.verify(entry, v));
+                if !valid && !dropped.contains(&signing.destination) {
+                    dropped.push(signing.destination);
+                }
+                valid
+            });
+            if signings.is_empty() {
+                self.verifier_signings.remove(&key);
+            }
+        }
+        dropped
     }
-}
 
-#[derive(Default, Clone)]
-pub struct VerifierRegistry {
-    verifiers: ImHashMap<AddressHash, Verifier>,
-}
+    /// Re-validates and re-sorts every signing list the verifier appears in.
+    ///
+    /// # Behaviour
+    ///
+    /// This is run after the verifier was changed in the registry. Signings of a
+    /// verifier that was removed or whose key changed no longer validate and are
+    /// dropped, signings of a verifier whose trust level changed are moved to
+    /// their new rank. Signings of domains that no longer exist are dropped too.
+    ///
+    /// Returns the names of the affected domains.
+    pub fn refresh_verifier(
+        &mut self,
+        verifier: &AddressHash,
+        entry_store: &DnsEntryStore,
+        registry: &VerifierRegistry,
+    ) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .get_domains_by_verifier(verifier)
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
 
-impl VerifierRegistry {
-    pub fn add_verifier() {
-        todo!()
+        for name in &names {
+            let Some(entry) = entry_store.lookup(name) else {
+                self.remove_domain(name);
+                continue;
+            };
+            self.prune_invalid(entry, registry);
+            for destination in &entry.destinations {
+                let key = (name.clone(), *destination);
+                if let Some(signings) = self.verifier_signings.get_mut(&key) {
+                    registry.sort_signings(signings);
+                }
+            }
+        }
+        names
     }
-    pub fn get_verifier() {
-        todo!()
+
+    /// Returns every domain and destination the verifier has signed for.
+    pub fn get_domains_by_verifier(&self, verifier: &AddressHash) -> Vec<&(String, AddressHash)> {
+        self.verifier_signings
+            .iter()
+            .filter(|(_, signings)| signings.iter().any(|s| s.destination == *verifier))
+            .map(|(key, _)| key)
+            .collect()
     }
-    pub fn get_entries_by_trust_level() {
-        todo!()
+
+    pub fn count_verifications(&self, name: &str, destination: &AddressHash) -> u32 {
+        self.verifier_signings
+            .get(&(name.to_owned(), *destination))
+            .map_or(0, |signings| signings.len() as u32)
     }
-    pub fn get_entries_by_verifier() {
-        todo!()
+
+    /// Returns the signings for the domain at the destination, sorted by trust
+    /// level.
+    pub fn get_verifications_for_domain(
+        &self,
+        name: &str,
+        destination: &AddressHash,
+    ) -> Result<&Vec<VerifierSigning>, RNSDNSERRORS> {
+        self.verifier_signings
+            .get(&(name.to_owned(), *destination))
+            .ok_or(RNSDNSERRORS::NotFound)
     }
-    pub fn is_entry_trusted() {
-        todo!()
+
+    /// Collects the signings of every destination of the entry, one per
+    /// verifier and sorted by trust level. This is what is attached to the entry
+    /// in answers.
+    pub fn collect_for_entry(
+        &self,
+        entry: &DnsEntry,
+        registry: &VerifierRegistry,
+    ) -> Vec<VerifierSigning> {
+        let mut collected: Vec<VerifierSigning> = Vec::new();
+        for destination in &entry.destinations {
+            let Ok(signings) = self.get_verifications_for_domain(&entry.name, destination) else {
+                continue;
+            };
+            for signing in signings {
+                if !collected.iter().any(|s| s.destination == signing.destination) {
+                    collected.push(signing.clone());
+                }
+            }
+        }
+        registry.sort_signings(&mut collected);
+        collected
     }
 }
 
-#[derive(Default, Clone)]
-pub struct DnsDatabaseRaw {
-    entry_store: DnsEntryStore,
-    verification_store: VerificationStore,
-    verifier_registry: VerifierRegistry,
+/// The state of a verifier destination as known to the registry.
+#[derive(Clone)]
+pub enum VerifierStatus {
+    /// The verifier is registered and its signings count.
+    Active(Verifier),
+    /// The verifier was revoked at the given time, its signings no longer count.
+    Revoked(DateTime<Utc>),
+    /// The destination was never registered or was removed without revocation.
+    Unknown,
 }
 
-pub struct DnsDatabase {
-    active: ArcSwap<DnsDatabaseRaw>,
-    staging: RwLock<DnsDatabaseRaw>,
-}
+impl VerifierStatus {
+    pub fn encode_into(&self, writer: &mut Writer) {
+        match self {
+            Self::Unknown => {
+                writer.put_u8(0);
+            }
+            Self::Active(verifier) => {
+                writer.put_u8(1);
+                verifier.encode_into(writer);
+            }
+            Self::Revoked(revoked_at) => {
+                writer.put_u8(2).put_time(revoked_at);
+            }
+        }
+    }
 
-impl DnsDatabase {
-    pub fn new() -> Self {
-        Self {
-            active: ArcSwap::new(Arc::new(DnsDatabaseRaw::default())),
-            staging: RwLock::new(DnsDatabaseRaw::default()),
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        match reader.get_u8()? {
+            0 => Ok(Self::Unknown),
+            1 => Ok(Self::Active(Verifier::decode(reader)?)),
+            2 => Ok(Self::Revoked(reader.get_time()?)),
+            _ => Err(CodecError::InvalidData),
         }
     }
+}
 
-    pub fn promote_staging(&self) -> Result<(), String> {
-        let staging_guard = self.staging.write()
-            .map_err(|e| format!("Staging lock poisoned: {}", e))?;
+/// The answer to a verifier status query, signed by the dns server.
+///
+/// # Fields
+/// `destination` - The verifier destination that was asked about.
+/// `status` - What the registry knows about the destination.
+/// `timestamp` - The time at which the answer was made.
+/// `server` - The destination of the server that signed the answer.
+/// `signature` - The signature of the server over all of the above.
+///
+/// # Security
+///
+/// A client should only replace its cached verifier records with answers that
+/// validate against the key of the server it trusts. The timestamp allows the
+/// client to reject answers older than the records it already holds.
+#[derive(Clone)]
+pub struct SignedVerifierStatus {
+    pub destination: AddressHash,
+    pub status: VerifierStatus,
+    pub timestamp: DateTime<Utc>,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
 
-        // clone and wrap
-        let new_active_raw = staging_guard.clone();
-        let new_active_arc = Arc::new(new_active_raw);
+impl SignedVerifierStatus {
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer.put_address(&self.destination);
+        self.status.encode_into(writer);
+        writer.put_time(&self.timestamp).put_address(&self.server);
+    }
 
-        // ignore old value
-        self.active.swap(new_active_arc);
-        Ok(())
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
     }
 
-    pub fn get_entry_reader(&self, key: &str) {
-        let active_guard = self.active.load();
-        // TODO: Implement the logic that searches each indes
+    /// Creates the answer and signs it with the identity of the server.
+    pub fn sign(
+        destination: AddressHash,
+        status: VerifierStatus,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut signed = Self {
+            destination,
+            status,
+            timestamp: Utc::now(),
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        signed.signature = private_id.sign(&signed.signable_bytes());
+        signed
+    }
+
+    /// Checks the signature against the key of the server.
+    pub fn verify(&self, server_key: &VerifyingKey) -> bool {
+        server_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            destination: reader.get_address()?,
+            status: VerifierStatus::decode(reader)?,
+            timestamp: reader.get_time()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+/// This is the registry of every verifier the dns server accepts signings from.
+///
+/// # Fields
+/// `verifiers` - The registered verifiers by destination.
+/// `revoked` - The destinations of revoked verifiers and when they were revoked.
+///
+/// # Reasoning
+///
+/// Revocations are kept after the verifier is gone so that a client holding a
+/// cached record can learn that it must drop it, removing the verifier alone
+/// would be indistinguishable from a verifier the server never knew about.
+#[derive(Default, Clone)]
+pub struct VerifierRegistry {
+    verifiers: ImHashMap<AddressHash, Verifier>,
+    revoked: ImHashMap<AddressHash, DateTime<Utc>>,
+}
+
+impl VerifierRegistry {
+    /// Registers the dns server itself as a verifier.
+    ///
+    /// # Behaviour
+    ///
+    /// The trust level is always forced to `0` since that level is reserved for the
+    /// server. Any previous server verifier is replaced.
+    pub fn set_server_verifier(&mut self, mut verifier: Verifier) {
+        verifier.trust_level = 0;
+        self.verifiers.retain(|_, v| v.trust_level != 0);
+        self.verifiers.insert(verifier.destination, verifier);
+    }
+
+    /// Adds a new verifier to the registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::ReservedTrustLevel` for a trust level of `0` and
+    /// `RNSDNSERRORS::AlreadyExists` should the destination already be registered.
+    pub fn add_verifier(&mut self, verifier: Verifier) -> Result<(), RNSDNSERRORS> {
+        if verifier.trust_level == 0 {
+            return Err(RNSDNSERRORS::ReservedTrustLevel);
+        }
+        if self.verifiers.contains_key(&verifier.destination) {
+            return Err(RNSDNSERRORS::AlreadyExists);
+        }
+        // a revoked destination may be registered again, e.g. with a new key
+        self.revoked.remove(&verifier.destination);
+        self.verifiers.insert(verifier.destination, verifier);
+        Ok(())
+    }
+
+    /// Replaces an existing verifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotFound` should the verifier not exist and
+    /// `RNSDNSERRORS::ReservedTrustLevel` should either the old or the new record
+    /// hold the trust level of the server.
+    pub fn update_verifier(&mut self, verifier: Verifier) -> Result<(), RNSDNSERRORS> {
+        let existing = self
+            .verifiers
+            .get(&verifier.destination)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        if existing.trust_level == 0 || verifier.trust_level == 0 {
+            return Err(RNSDNSERRORS::ReservedTrustLevel);
+        }
+        self.verifiers.insert(verifier.destination, verifier);
+        Ok(())
+    }
+
+    /// Removes a verifier. The server itself can not be removed.
+    pub fn remove_verifier(&mut self, destination: &AddressHash) -> Result<Verifier, RNSDNSERRORS> {
+        match self.verifiers.get(destination) {
+            None => Err(RNSDNSERRORS::NotFound),
+            Some(v) if v.trust_level == 0 => Err(RNSDNSERRORS::ReservedTrustLevel),
+            Some(_) => self.verifiers.remove(destination).ok_or(RNSDNSERRORS::NotFound),
+        }
+    }
+
+    /// Removes a verifier and remembers that it was revoked.
+    ///
+    /// # Errors
+    ///
+    /// The same as `VerifierRegistry::remove_verifier`.
+    pub fn revoke_verifier(
+        &mut self,
+        destination: &AddressHash,
+        now: DateTime<Utc>,
+    ) -> Result<Verifier, RNSDNSERRORS> {
+        let verifier = self.remove_verifier(destination)?;
+        self.revoked.insert(*destination, now);
+        Ok(verifier)
+    }
+
+    /// Returns what the registry knows about the destination.
+    pub fn status(&self, destination: &AddressHash) -> VerifierStatus {
+        if let Some(verifier) = self.verifiers.get(destination) {
+            return VerifierStatus::Active(verifier.clone());
+        }
+        match self.revoked.get(destination) {
+            Some(revoked_at) => VerifierStatus::Revoked(*revoked_at),
+            None => VerifierStatus::Unknown,
+        }
+    }
+
+    /// Returns the verifier with the given destination should one exist.
+    pub fn get_verifier(&self, destination: &AddressHash) -> Option<&Verifier> {
+        self.verifiers.get(destination)
+    }
+
+    /// Returns every verifier whose trust level lies within `levels`.
+    ///
+    /// The result is sorted by trust level, the most trusted verifier first.
+    pub fn get_entries_by_trust_level(&self, levels: RangeInclusive<u32>) -> Vec<&Verifier> {
+        let mut verifiers: Vec<&Verifier> = self
+            .verifiers
+            .values()
+            .filter(|v| levels.contains(&v.trust_level))
+            .collect();
+        verifiers.sort_by(|a, b| a.trust_level.cmp(&b.trust_level).then(a.name.cmp(&b.name)));
+        verifiers
+    }
+
+    /// Returns every entry of the store that the verifier has vouched for.
+    pub fn get_entries_by_verifier<'a>(
+        &self,
+        destination: &AddressHash,
+        entry_store: &'a DnsEntryStore,
+    ) -> Vec<&'a DnsEntry> {
+        entry_store
+            .iter_forward_index()
+            .map(|(_, entry)| entry)
+            .filter(|entry| {
+                entry
+                    .verifications
+                    .iter()
+                    .any(|signing| signing.destination == *destination)
+            })
+            .collect()
+    }
+
+    /// Sorts the signings by the trust level of their verifier, most trusted
+    /// first. Signings of unknown verifiers are moved to the end.
+    pub fn sort_signings(&self, signings: &mut [VerifierSigning]) {
+        signings.sort_by_key(|signing| {
+            self.verifiers
+                .get(&signing.destination)
+                .map_or(u32::MAX, |v| v.trust_level)
+        });
+    }
+
+    /// Returns whether the entry carries at least one valid signing of a known
+    /// verifier whose trust level is `max_trust_level` or better.
+    pub fn is_entry_trusted(&self, entry: &DnsEntry, max_trust_level: u32) -> bool {
+        entry.verifications.iter().any(|signing| {
+            self.verifiers
+                .get(&signing.destination)
+                .is_some_and(|v| v.trust_level <= max_trust_level && signing.verify(entry, v))
+        })
+    }
+
+    pub fn encode_into(&self, writer: &mut Writer) {
+        writer.put_u32(self.verifiers.len() as u32);
+        for verifier in self.verifiers.values() {
+            verifier.encode_into(writer);
+        }
+        writer.put_u32(self.revoked.len() as u32);
+        for (destination, revoked_at) in &self.revoked {
+            writer.put_address(destination).put_time(revoked_at);
+        }
+    }
+
+    /// Decodes the registry of a database file of the given format `version`.
+    ///
+    /// Files of version `1` predate revocations and carry none.
+    pub fn decode(reader: &mut Reader, version: u8) -> Result<Self, CodecError> {
+        let mut registry = Self::default();
+        for _ in 0..reader.get_u32()? {
+            let verifier = Verifier::decode(reader)?;
+            registry.verifiers.insert(verifier.destination, verifier);
+        }
+        if version >= 2 {
+            for _ in 0..reader.get_u32()? {
+                let destination = reader.get_address()?;
+                registry.revoked.insert(destination, reader.get_time()?);
+            }
+        }
+        Ok(registry)
+    }
+}
+
+/// A mutation of a single name as it is recorded, see `DnsDatabase::apply_batch`.
+///
+/// # Fields
+/// `name` - The name that changed.
+/// `before` - The entry before the mutation, `None` should the name be new.
+/// `after` - The entry after the mutation, `None` should the name be removed.
+/// `removal` - The removal to remember once the mutation is recorded.
+struct Mutation {
+    name: String,
+    before: Option<DnsEntry>,
+    after: Option<DnsEntry>,
+    removal: Option<Version>,
+}
+
+impl Mutation {
+    fn change(name: String, before: Option<DnsEntry>, after: Option<DnsEntry>) -> Self {
+        Self {
+            name,
+            before,
+            after,
+            removal: None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct DnsDatabaseRaw {
+    entry_store: DnsEntryStore,
+    verification_store: VerificationStore,
+    verifier_registry: VerifierRegistry,
+}
+
+/// The first bytes of every database file.
+const DATABASE_MAGIC: &[u8; 8] = b"RNSDNSDB";
+/// The version of the database file format. Version `2` added verifier
+/// revocations and version `3` entry serials, older files are still read.
+const DATABASE_VERSION: u8 = 3;
+
+impl DnsDatabaseRaw {
+    pub fn entry_store(&self) -> &DnsEntryStore {
+        &self.entry_store
+    }
+
+    pub fn verification_store(&self) -> &VerificationStore {
+        &self.verification_store
+    }
+
+    pub fn verifier_registry(&self) -> &VerifierRegistry {
+        &self.verifier_registry
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_fixed(DATABASE_MAGIC).put_u8(DATABASE_VERSION);
+        self.entry_store.encode_into(&mut writer);
+        self.verification_store.encode_into(&mut writer);
+        self.verifier_registry.encode_into(&mut writer);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut reader = Reader::new(bytes);
+        if reader.get_fixed(DATABASE_MAGIC.len())? != DATABASE_MAGIC {
+            return Err(CodecError::InvalidData);
+        }
+        let version = reader.get_u8()?;
+        if !(1..=DATABASE_VERSION).contains(&version) {
+            return Err(CodecError::InvalidData);
+        }
+        Ok(Self {
+            entry_store: DnsEntryStore::decode(&mut reader, version)?,
+            verification_store: VerificationStore::decode(&mut reader)?,
+            verifier_registry: VerifierRegistry::decode(&mut reader, version)?,
+        })
+    }
+
+    /// Reads a database file from disk.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::decode(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))
+    }
+
+    /// Writes the database to disk.
+    ///
+    /// The file is written next to the target first and then moved over it so
+    /// that a crash never leaves a half written database behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.encode())?;
+        fs::rename(tmp, path)
+    }
+}
+
+/// The identity on whose behalf a mutation of the `DnsDatabase` is made.
+///
+/// `link` is the link the request arrived over, `None` for changes the server
+/// makes itself, e.g. for the operator or from a transfer.
+///
+/// `identity` is the identity the requester proved to hold, see `registration`,
+/// or `None` should it not have identified itself. The id of a link is never an
+/// identity, anyone can open as many links as they like.
+///
+/// `signature` is the signature of the request that caused the mutation, should
+/// the request have been signed.
+#[derive(Clone, Copy)]
+pub struct Requester {
+    pub link: Option<AddressHash>,
+    pub identity: Option<AddressHash>,
+    pub signature: Option<Signature>,
+}
+
+impl Requester {
+    /// A requester the server acts for without a link.
+    pub fn new(identity: AddressHash, signature: Option<Signature>) -> Self {
+        Self {
+            link: None,
+            identity: Some(identity),
+            signature,
+        }
+    }
+
+    /// A requester on a link, `identity` is the identity it proved on the link.
+    pub fn on_link(link: AddressHash, identity: Option<AddressHash>) -> Self {
+        Self {
+            link: Some(link),
+            identity,
+            signature: None,
+        }
+    }
+
+    /// The identity of the requester for logs, or that it is unidentified.
+    pub fn describe(&self) -> String {
+        match (self.identity, self.link) {
+            (Some(identity), _) => identity.to_string(),
+            (None, Some(link)) => format!("unidentified requester on link {link}"),
+            (None, None) => "unidentified requester".to_owned(),
+        }
+    }
+}
+
+/// A change that a verifier has to be told about since it vouched for the entry.
+///
+/// `entry` is the new version of the entry or `None` should the entry have been
+/// removed.
+#[derive(Clone)]
+pub struct VerifierNotification {
+    pub verifier: AddressHash,
+    pub name: String,
+    pub entry: Option<DnsEntry>,
+}
+
+/// This is the database of the dns server.
+///
+/// # Fields
+/// `active` - The snapshot that answers queries.
+/// `staging` - The copy that mutations are applied to until it is promoted.
+/// `operator` - The identity of the server operator.
+/// `policy` - The policy applied to entry mutations.
+/// `audit_log` - The log every entry mutation is recorded in.
+/// `notifications` - The changes that still have to be sent to verifiers.
+/// `server_signer` - The destination and identity the server signs entries with.
+/// `trust_chain` - The delegations from a root down to this server.
+/// `transparency` - The Merkle tree over every change of a record.
+/// `tree_head` - The signed head of the transparency log as of the active snapshot.
+/// `history` - The most recent changes by the size of the transparency log after
+/// them, for incremental transfers.
+/// `tombstones` - The versions of the removed entries, so that peers do not
+/// replicate them back, see `gossip`. They only live in memory.
+/// `challenges` - The registration challenges that were not yet answered.
+/// `access_list` - The rules which identities may change names under which suffixes.
+///
+/// # Security
+///
+/// The verifier registry may only be modified by the `operator`, which is the
+/// identity of the dns server itself.
+pub struct DnsDatabase {
+    active: ArcSwap<DnsDatabaseRaw>,
+    staging: RwLock<DnsDatabaseRaw>,
+    operator: AddressHash,
+    policy: EntryPolicy,
+    audit_log: Mutex<AuditLog>,
+    notifications: Mutex<Vec<VerifierNotification>>,
+    server_signer: OnceLock<(AddressHash, PrivateIdentity)>,
+    trust_chain: OnceLock<TrustChain>,
+    transparency: Mutex<TransparencyLog>,
+    tree_head: ArcSwapOption<SignedTreeHead>,
+    history: Mutex<VecDeque<(u64, Change)>>,
+    tombstones: Mutex<BTreeMap<String, Version>>,
+    challenges: Mutex<Challenges>,
+    access_list: AccessList,
+}
+
+impl DnsDatabase {
+    pub fn new(operator: AddressHash, audit_log: AuditLog) -> Self {
+        Self::with_policy(operator, EntryPolicy::default(), audit_log)
+    }
+
+    pub fn with_policy(operator: AddressHash, policy: EntryPolicy, audit_log: AuditLog) -> Self {
+        Self {
+            active: ArcSwap::new(Arc::new(DnsDatabaseRaw::default())),
+            staging: RwLock::new(DnsDatabaseRaw::default()),
+            operator,
+            policy,
+            audit_log: Mutex::new(audit_log),
+            notifications: Mutex::new(Vec::new()),
+            server_signer: OnceLock::new(),
+            trust_chain: OnceLock::new(),
+            transparency: Mutex::new(TransparencyLog::ephemeral()),
+            tree_head: ArcSwapOption::empty(),
+            history: Mutex::new(VecDeque::new()),
+            tombstones: Mutex::new(BTreeMap::new()),
+            challenges: Mutex::new(Challenges::default()),
+            access_list: AccessList::default(),
+        }
+    }
+
+    fn notify(&self, notifications: impl IntoIterator<Item = VerifierNotification>) {
+        if let Ok(mut pending) = self.notifications.lock() {
+            pending.extend(notifications);
+        }
+    }
+
+    /// Takes every notification that still has to be sent to a verifier.
+    pub fn take_notifications(&self) -> Vec<VerifierNotification> {
+        self.notifications
+            .lock()
+            .map(|mut pending| std::mem::take(&mut *pending))
+            .unwrap_or_default()
+    }
+
+    /// Puts notifications back that could not be delivered yet.
+    pub fn requeue_notifications(&self, notifications: Vec<VerifierNotification>) {
+        self.notify(notifications);
+    }
+
+    pub fn operator(&self) -> &AddressHash {
+        &self.operator
+    }
+
+    /// Replaces the access list, which is open by default.
+    pub fn with_access_list(mut self, access_list: AccessList) -> Self {
+        self.access_list = access_list;
+        self
+    }
+
+    /// Checks the access list before a name is changed. `identity` is `None` for a
+    /// requester that did not identify itself. The operator may change every name.
+    fn check_access(
+        &self,
+        identity: Option<&AddressHash>,
+        name: &str,
+    ) -> Result<(), RNSDNSERRORS> {
+        if identity == Some(&self.operator) {
+            return Ok(());
+        }
+        self.access_list.check(identity, name).map_err(|rule| {
+            let identity = identity.map_or("unidentified requester".to_owned(), |i| i.to_string());
+            log::warn!("denied change of {name} by {identity}, rule \"{rule}\"");
+            RNSDNSERRORS::NotAuthorized
+        })
+    }
+
+    fn require_operator(&self, requester: &Requester) -> Result<(), RNSDNSERRORS> {
+        if requester.identity != Some(self.operator) {
+            log::warn!("rejected operator request by {}", requester.describe());
+            return Err(RNSDNSERRORS::NotAuthorized);
+        }
+        Ok(())
+    }
+
+    /// Registers the dns server itself in the staging registry at trust level `0`.
+    ///
+    /// From then on the server signs every entry it accepts with `private_id`.
+    pub fn set_server_verifier(
+        &self,
+        verifier: Verifier,
+        private_id: PrivateIdentity,
+    ) -> Result<(), RNSDNSERRORS> {
+        let destination = verifier.destination;
+        self.write_staging()?
+            .verifier_registry
+            .set_server_verifier(verifier);
+        let _ = self.server_signer.set((destination, private_id));
+        Ok(())
+    }
+
+    /// Sets the chain of delegations that is attached to answers. This can only
+    /// be done once, on startup.
+    pub fn set_trust_chain(&self, chain: TrustChain) {
+        let _ = self.trust_chain.set(chain);
+    }
+
+    /// Returns the chain of delegations of this server, should it have one.
+    pub fn trust_chain(&self) -> Option<&TrustChain> {
+        self.trust_chain.get().filter(|chain| !chain.is_empty())
+    }
+
+    /// Attaches the signing of the server to every destination of the entry and
+    /// returns the entry with its verifications rebuilt from the store.
+    fn server_sign(
+        &self,
+        mut entry: DnsEntry,
+        store: &mut VerificationStore,
+        registry: &VerifierRegistry,
+    ) -> DnsEntry {
+        if let Some((server, private_id)) = self.server_signer.get() {
+            let signing = VerifierSigning::sign(&entry, *server, private_id);
+            for destination in entry.destinations.clone() {
+                let result = store.add_verification(&entry, destination, signing.clone(), registry);
+                if let Err(e) = result {
+                    log::error!("failed to sign {} as server: {e:?}", entry.name);
+                }
+            }
+        }
+        entry.verifications = store.collect_for_entry(&entry, registry);
+        entry
+    }
+
+    /// Adds a verifier to the staging registry. Only the operator may do this.
+    pub fn add_verifier(
+        &self,
+        requester: &Requester,
+        verifier: Verifier,
+    ) -> Result<(), RNSDNSERRORS> {
+        self.require_operator(requester)?;
+        self.write_staging()?.verifier_registry.add_verifier(verifier)
+    }
+
+    /// Applies `change` to a copy of the registry and brings the signings of the
+    /// verifier in line with it.
+    ///
+    /// # Behaviour
+    ///
+    /// Every entry the verifier vouched for has its verifications rebuilt, which
+    /// drops signings that no longer validate and re-ranks the remaining ones. A
+    /// record is written to the audit log for each of these entries.
+    fn change_registry<T>(
+        &self,
+        requester: &Requester,
+        verifier: &AddressHash,
+        change: impl FnOnce(&mut VerifierRegistry) -> Result<T, RNSDNSERRORS>,
+    ) -> Result<T, RNSDNSERRORS> {
+        self.require_operator(requester)?;
+        let mut staging = self.write_staging()?;
+
+        let mut raw = staging.clone();
+        let result = change(&mut raw.verifier_registry)?;
+        let names = raw.verification_store.refresh_verifier(
+            verifier,
+            &raw.entry_store,
+            &raw.verifier_registry,
+        );
+
+        let resign = |raw: &mut DnsDatabaseRaw, name: String| {
+            let before = raw.entry_store.lookup(&name)?.clone();
+            let mut after = before.clone();
+            after.verifications = raw
+                .verification_store
+                .collect_for_entry(&after, &raw.verifier_registry);
+            raw.entry_store.override_entry(after.clone());
+            Some(Mutation::change(name, Some(before), Some(after)))
+        };
+        // the registry is applied along with the first entry that is recorded
+        let action = AuditAction::VerifierChanged;
+        self.apply_batch(&mut staging, raw, action, requester, names, resign)?;
+        Ok(result)
+    }
+
+    /// Replaces a verifier in the staging registry. Only the operator may do this.
+    ///
+    /// Signings made with a previous key are dropped and the remaining ones are
+    /// re-ranked by the new trust level.
+    pub fn update_verifier(
+        &self,
+        requester: &Requester,
+        verifier: Verifier,
+    ) -> Result<(), RNSDNSERRORS> {
+        let destination = verifier.destination;
+        self.change_registry(requester, &destination, |registry| {
+            registry.update_verifier(verifier)
+        })
+    }
+
+    /// Removes a verifier from the staging registry along with all of its
+    /// signings. Only the operator may do this.
+    pub fn remove_verifier(
+        &self,
+        requester: &Requester,
+        destination: &AddressHash,
+    ) -> Result<Verifier, RNSDNSERRORS> {
+        self.change_registry(requester, destination, |registry| {
+            registry.remove_verifier(destination)
+        })
+    }
+
+    /// Revokes a verifier, e.g. because its key was compromised. Only the
+    /// operator may do this.
+    ///
+    /// This is `DnsDatabase::remove_verifier` except that the revocation is
+    /// remembered and reported by verifier status queries.
+    pub fn revoke_verifier(
+        &self,
+        requester: &Requester,
+        destination: &AddressHash,
+    ) -> Result<Verifier, RNSDNSERRORS> {
+        self.change_registry(requester, destination, |registry| {
+            registry.revoke_verifier(destination, Utc::now())
+        })
+    }
+
+    /// Answers a verifier status query from the active snapshot.
+    ///
+    /// Returns `None` should the server not have a signing identity yet.
+    pub fn verifier_status(&self, destination: &AddressHash) -> Option<SignedVerifierStatus> {
+        let (server, private_id) = self.server_signer.get()?;
+        let status = self.active.load().verifier_registry.status(destination);
+        Some(SignedVerifierStatus::sign(
+            *destination,
+            status,
+            *server,
+            private_id,
+        ))
+    }
+
+    /// Checks the integrity of the active snapshot, see `integrity`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotAuthorized` for anyone but the operator, since a
+    /// check verifies every signature of the zone and is far too expensive to be
+    /// triggered by anyone over the network.
+    pub fn check_integrity(&self, requester: &Requester) -> Result<IntegrityReport, RNSDNSERRORS> {
+        self.require_operator(requester)?;
+        Ok(integrity::check_database(&self.active.load(), Utc::now()))
+    }
+
+    /// Answers a lookup of a missing name from the active snapshot with the
+    /// names around it.
+    ///
+    /// Returns `None` should the name exist or the server not have a signing
+    /// identity yet.
+    pub fn deny_existence(&self, name: &str) -> Option<DenialOfExistence> {
+        let (server, private_id) = self.server_signer.get()?;
+        let active = self.active.load();
+        if active.entry_store.lookup(name).is_some() {
+            return None;
+        }
+        let (predecessor, successor) = active.entry_store.neighbours(name);
+        Some(DenialOfExistence::sign(
+            name.to_owned(),
+            predecessor.cloned(),
+            successor.cloned(),
+            *server,
+            private_id,
+        ))
+    }
+
+    /// Returns the page of a zone transfer that follows `after`, taken from the
+    /// active snapshot and signed as `zone`.
+    ///
+    /// Returns `None` should the server not have a signing identity or tree head
+    /// yet.
+    pub fn transfer_page(&self, zone: &str, after: Option<&str>) -> Option<TransferPage> {
+        let (server, private_id) = self.server_signer.get()?;
+        // the head is signed when a snapshot is promoted, take it first so that it
+        // is never newer than the snapshot
+        let tree_size = self.tree_head()?.tree_size;
+        let active = self.active.load();
+        let (entries, last) = active.entry_store.page(after, TRANSFER_PAGE_SIZE);
+        Some(TransferPage::sign(
+            zone.to_owned(),
+            tree_size,
+            after.map(str::to_owned),
+            entries,
+            last,
+            *server,
+            private_id,
+        ))
+    }
+
+    /// Returns the page of an incremental transfer with the changes made after
+    /// the zone had the serial `since`, up to the serial of the active snapshot,
+    /// signed as `zone`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotFound` should the server not have a signing
+    /// identity or tree head yet and `RNSDNSERRORS::HistoryTooShort` should the
+    /// changes since `since` no longer be remembered or `since` lie ahead of the
+    /// zone. The history only lives in memory, so this is also the answer after
+    /// a restart.
+    pub fn change_page(&self, zone: &str, since: u64) -> Result<ChangePage, RNSDNSERRORS> {
+        let (server, private_id) = self.server_signer.get().ok_or(RNSDNSERRORS::NotFound)?;
+        let serial = self.tree_head().ok_or(RNSDNSERRORS::NotFound)?.tree_size;
+        if since > serial {
+            return Err(RNSDNSERRORS::HistoryTooShort);
+        }
+
+        let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        let changes: Vec<Change> = history
+            .iter()
+            .skip_while(|(size, _)| *size <= since)
+            .take_while(|(size, _)| *size <= serial)
+            .take(CHANGE_PAGE_SIZE)
+            .map(|(_, change)| change.clone())
+            .collect();
+        let first = history
+            .iter()
+            .find(|(size, _)| *size > since)
+            .map(|(size, _)| *size);
+        if since < serial && first != Some(since + 1) {
+            return Err(RNSDNSERRORS::HistoryTooShort);
+        }
+
+        let last = since + changes.len() as u64 == serial;
+        Ok(ChangePage::sign(
+            zone.to_owned(),
+            since,
+            changes,
+            last,
+            *server,
+            private_id,
+        ))
+    }
+
+    /// Returns the version of every name of the active snapshot and of every
+    /// removed one, sorted by name, see `gossip`.
+    pub fn gossip_versions(&self) -> Vec<(String, Version)> {
+        let active = self.active.load();
+        let mut versions = self
+            .tombstones
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for (name, entry) in active.entry_store.iter_forward_index() {
+            // the removal may be staged but not yet promoted
+            let version = Version::of(entry);
+            if versions.get(name).is_none_or(|removed| version.wins(removed)) {
+                versions.insert(name.clone(), version);
+            }
+        }
+        versions.into_iter().collect()
+    }
+
+    /// Returns the entries and removals of `names` held in the active snapshot,
+    /// as `Payload::Records`. Names that were never held are left out.
+    ///
+    /// The verifications are left out as well, a peer signs the entries itself.
+    pub fn gossip_records(&self, names: &[String]) -> Payload {
+        let active = self.active.load();
+        let tombstones = self.tombstones.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut entries = Vec::new();
+        let mut removals = Vec::new();
+        for name in names {
+            let entry = active.entry_store.lookup(name);
+            match (entry, tombstones.get(name)) {
+                (Some(entry), Some(removed)) if removed.wins(&Version::of(entry)) => {
+                    removals.push((name.clone(), *removed));
+                }
+                (Some(entry), _) => entries.push(DnsEntry {
+                    verifications: Vec::new(),
+                    ..entry.clone()
+                }),
+                (None, Some(removed)) => removals.push((name.clone(), *removed)),
+                (None, None) => {}
+            }
+        }
+        Payload::Records { entries, removals }
+    }
+
+    /// Signs a gossip message about `zone` as the server.
+    ///
+    /// Returns `None` should the server not have a signing identity yet.
+    pub fn sign_gossip(&self, zone: &str, payload: Payload) -> Option<Gossip> {
+        let (server, private_id) = self.server_signer.get()?;
+        Some(Gossip::sign(zone.to_owned(), payload, *server, private_id))
+    }
+
+    /// Wraps the response to a query into an answer signed by the server.
+    ///
+    /// Returns `None` should the server not have a signing identity yet.
+    pub fn sign_answer(&self, query: &Query, response: String) -> Option<SignedAnswer> {
+        let (server, private_id) = self.server_signer.get()?;
+        Some(SignedAnswer::sign(query, response, *server, private_id))
+    }
+
+    pub fn policy(&self) -> &EntryPolicy {
+        &self.policy
+    }
+
+    fn write_staging(&self) -> Result<RwLockWriteGuard<'_, DnsDatabaseRaw>, RNSDNSERRORS> {
+        self.staging
+            .write()
+            .map_err(|_| RNSDNSERRORS::StagingPoisoned)
+    }
+
+    /// Appends an entry to the audit log and the change to the transparency log.
+    ///
+    /// This has to be called while holding the staging lock and before the
+    /// mutation is applied so that a mutation is never applied without a record.
+    /// A new entry replaces the removal of its name, the removals themselves are
+    /// remembered by the paths that hold the signature of the owner, see
+    /// `remember_removal`.
+    fn record(
+        &self,
+        action: AuditAction,
+        name: &str,
+        requester: &Requester,
+        before: Option<&DnsEntry>,
+        after: Option<&DnsEntry>,
+    ) -> Result<(), RNSDNSERRORS> {
+        let mut audit_log = self
+            .audit_log
+            .lock()
+            .map_err(|_| RNSDNSERRORS::AuditFailed)?;
+        audit_log
+            .append(action, name, requester, before, after)
+            .map_err(|e| {
+                log::error!("failed to append to the audit log: {e}");
+                RNSDNSERRORS::AuditFailed
+            })?;
+
+        let mut transparency = self
+            .transparency
+            .lock()
+            .map_err(|_| RNSDNSERRORS::AuditFailed)?;
+        let size = transparency.size();
+        transparency.append(name, after).map_err(|e| {
+            log::error!("failed to append to the transparency log: {e}");
+            RNSDNSERRORS::AuditFailed
+        })?;
+
+        // a change the log already holds is not appended again and gets no serial
+        if transparency.size() > size {
+            let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+            let change = Change {
+                name: name.to_owned(),
+                entry: after.cloned(),
+            };
+            history.push_back((transparency.size(), change));
+            if history.len() > MAX_HISTORY {
+                history.pop_front();
+            }
+        }
+
+        if after.is_some() {
+            self.tombstones
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(name);
+        }
+        Ok(())
+    }
+
+    /// Applies a batch of mutations to the staging database one after the other
+    /// and returns how many of them were applied.
+    ///
+    /// # Behaviour
+    ///
+    /// `raw` is a copy of the staging database with the changes that come before
+    /// the batch applied. For every item `apply` makes its mutation on `raw` and
+    /// returns it, or `None` should the item be skipped. Every mutation is
+    /// recorded before the staging database is set to `raw`, so should recording
+    /// fail part way the staging database holds exactly the mutations that were
+    /// recorded. The stores share their structure, which makes setting them
+    /// cheap.
+    fn apply_batch<I>(
+        &self,
+        staging: &mut DnsDatabaseRaw,
+        mut raw: DnsDatabaseRaw,
+        action: AuditAction,
+        requester: &Requester,
+        items: impl IntoIterator<Item = I>,
+        mut apply: impl FnMut(&mut DnsDatabaseRaw, I) -> Option<Mutation>,
+    ) -> Result<usize, RNSDNSERRORS> {
+        let mut applied = 0;
+        let mut result = Ok(());
+        for item in items {
+            let Some(mutation) = apply(&mut raw, item) else {
+                continue;
+            };
+            let (before, after) = (mutation.before.as_ref(), mutation.after.as_ref());
+            result = self.record(action, &mutation.name, requester, before, after);
+            if result.is_err() {
+                break;
+            }
+            if let Some(removal) = mutation.removal {
+                self.remember_removal(&mutation.name, removal);
+            }
+            *staging = raw.clone();
+            applied += 1;
+        }
+        if result.is_ok() {
+            *staging = raw;
+        }
+        result.map(|()| applied)
+    }
+
+    /// Remembers the removal of a name, so that peers do not replicate it back,
+    /// see `gossip`.
+    fn remember_removal(&self, name: &str, removal: Version) {
+        self.tombstones
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_owned(), removal);
+    }
+
+    /// Issues a challenge to the requester on `link`, see `registration`.
+    pub fn issue_challenge(&self, link: &AddressHash) -> [u8; CHALLENGE_LENGTH] {
+        // the challenges hold no invariants a panicking holder could have broken
+        let mut challenges = self.challenges.lock().unwrap_or_else(PoisonError::into_inner);
+        challenges.issue(*link, Utc::now())
+    }
+
+    /// Checks that the requester on `link` holds `identity` and returns its
+    /// address hash, see `registration`. The challenge of the link is used up
+    /// whether the identification succeeds or not.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::MissingChallenge` should no valid challenge have been
+    /// issued on the link and `RNSDNSERRORS::InvalidSignature` should the proof
+    /// not hold.
+    pub fn identify(
+        &self,
+        link: &AddressHash,
+        identity: &Identity,
+        proof: &Signature,
+    ) -> Result<AddressHash, RNSDNSERRORS> {
+        let challenge = self
+            .challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take(link, Utc::now())
+            .ok_or(RNSDNSERRORS::MissingChallenge)?;
+        let (server, _) = self.server_signer.get().ok_or(RNSDNSERRORS::NotAuthorized)?;
+        registration::verify_identification(&challenge, server, identity, proof)?;
+        Ok(identity.address_hash)
+    }
+
+    /// Registers an entry that a node submitted over a link.
+    ///
+    /// # Behaviour
+    ///
+    /// The challenge of the requester is used up whether the registration
+    /// succeeds or not. The timestamp, expiry and verifications of the submitted
+    /// entry are ignored and set by the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::MissingChallenge` should no valid challenge have been
+    /// issued to the requester, `RNSDNSERRORS::InvalidSignature` should the entry
+    /// or the proof not be signed by the key of the entry,
+    /// `RNSDNSERRORS::NotAuthorized` should the access list not let the identity
+    /// of the requester register the name, the policy violation or
+    /// `RNSDNSERRORS::AlreadyExists` should the name already be taken.
+    pub fn register_entry(
+        &self,
+        requester: &Requester,
+        entry: DnsEntry,
+        proof: &Signature,
+    ) -> Result<(), RNSDNSERRORS> {
+        let now = Utc::now();
+        let link = requester.link.ok_or(RNSDNSERRORS::MissingChallenge)?;
+        let challenge = self
+            .challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take(&link, now)
+            .ok_or(RNSDNSERRORS::MissingChallenge)?;
+        registration::verify_proof(&challenge, &entry, proof)?;
+        self.check_access(requester.identity.as_ref(), &entry.name)?;
+
+        let mut staging = self.write_staging()?;
+        if staging.entry_store.lookup(&entry.name).is_some() {
+            return Err(RNSDNSERRORS::AlreadyExists);
+        }
+        self.policy.check_add(
+            &staging.entry_store,
+            &entry.public_key,
+            entry.destinations.len(),
+        )?;
+
+        let entry = DnsEntry {
+            timestamp: now,
+            expiry: now + RECORD_EXPIRY,
+            verifications: Vec::new(),
+            ..entry
+        };
+        let mut verification_store = staging.verification_store.clone();
+        let entry = self.server_sign(entry, &mut verification_store, &staging.verifier_registry);
+
+        self.record(AuditAction::Add, &entry.name, requester, None, Some(&entry))?;
+        staging.entry_store.override_entry(entry);
+        staging.verification_store = verification_store;
+        Ok(())
+    }
+
+    /// Overrides an entry in the staging database after checking it against the
+    /// policy. The timestamp of the entry is set by the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::InvalidSignature` should the entry not be signed by
+    /// its owner, `RNSDNSERRORS::NotFound` should there be no entry to override,
+    /// a new name has to be registered, `RNSDNSERRORS::NotAuthorized` should the
+    /// access list not let the identity of the requester change the name or the
+    /// entry be signed by a different key than the stored entry and
+    /// `RNSDNSERRORS::StaleSerial` should its serial not be higher than the stored
+    /// one. The latter stops an old update from being replayed to roll the entry
+    /// back.
+    pub fn override_entry(
+        &self,
+        requester: &Requester,
+        mut entry: DnsEntry,
+    ) -> Result<(), RNSDNSERRORS> {
+        if !entry.verify_signature() {
+            return Err(RNSDNSERRORS::InvalidSignature);
+        }
+        self.check_access(requester.identity.as_ref(), &entry.name)?;
+        let mut staging = self.write_staging()?;
+        let existing = staging
+            .entry_store
+            .lookup(&entry.name)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        if existing.verifying_key != entry.verifying_key {
+            return Err(RNSDNSERRORS::NotAuthorized);
+        }
+        if entry.serial <= existing.serial {
+            return Err(RNSDNSERRORS::StaleSerial {
+                current: existing.serial,
+            });
+        }
+
+        let now = Utc::now();
+        self.policy
+            .check_override(&staging.entry_store, &entry, now)?;
+        entry.update_timestamp(now);
+
+        // the verifications are controlled by the server, signings made for the
+        // previous version of the entry no longer hold
+        let mut verification_store = staging.verification_store.clone();
+        let mut dropped = verification_store.prune_invalid(&entry, &staging.verifier_registry);
+        let entry = self.server_sign(entry, &mut verification_store, &staging.verifier_registry);
+        if let Some((server, _)) = self.server_signer.get() {
+            dropped.retain(|verifier| verifier != server);
+        }
+
+        self.record(
+            AuditAction::Override,
+            &entry.name,
+            requester,
+            staging.entry_store.lookup(&entry.name),
+            Some(&entry),
+        )?;
+        // the verifiers whose signings were dropped may re-sign the new entry
+        self.notify(dropped.into_iter().map(|verifier| VerifierNotification {
+            verifier,
+            name: entry.name.clone(),
+            entry: Some(entry.clone()),
+        }));
+        staging.entry_store.override_entry(entry);
+        staging.verification_store = verification_store;
+        Ok(())
+    }
+
+    /// Replaces the entries of the staging database with those transferred from
+    /// the primary server, see `transfer::ZoneTransfer`.
+    ///
+    /// # Behaviour
+    ///
+    /// Every entry that differs from the staged one is recorded and applied, as
+    /// is the removal of every entry that is no longer part of the zone. The
+    /// transferred entries are signed by this server again since the signings of
+    /// the primary refer to verifiers this server may not know.
+    pub fn replace_entries(
+        &self,
+        requester: &Requester,
+        entries: Vec<DnsEntry>,
+    ) -> Result<(), RNSDNSERRORS> {
+        let mut staging = self.write_staging()?;
+        let transferred: HashSet<&str> = entries.iter().map(|entry| entry.name()).collect();
+        let removed: Vec<Change> = staging
+            .entry_store
+            .iter_forward_index()
+            .filter(|(name, _)| !transferred.contains(name.as_str()))
+            .map(|(name, _)| Change {
+                name: name.clone(),
+                entry: None,
+            })
+            .collect();
+        let transferred = entries.into_iter().map(|entry| Change {
+            name: entry.name.clone(),
+            entry: Some(entry),
+        });
+        let changes = removed.into_iter().chain(transferred);
+
+        let apply = |raw: &mut DnsDatabaseRaw, change: Change| {
+            let unchanged = change.entry.as_ref().is_some_and(|entry| {
+                raw.entry_store
+                    .lookup(&change.name)
+                    .is_some_and(|before| before.record_digest() == entry.record_digest())
+            });
+            match unchanged {
+                true => None,
+                false => self.transfer_change(raw, change),
+            }
+        };
+        let raw = staging.clone();
+        let action = AuditAction::Transferred;
+        self.apply_batch(&mut staging, raw, action, requester, changes, apply)?;
+        Ok(())
+    }
+
+    /// Applies the changes of an incremental transfer to the staging database.
+    ///
+    /// # Behaviour
+    ///
+    /// As with `replace_entries` the entries are signed as the server and their
+    /// verifications dropped. Every change is recorded as
+    /// `AuditAction::Transferred`. The caller has to check the changes, see
+    /// `ChangePage::check`.
+    pub fn apply_changes(
+        &self,
+        requester: &Requester,
+        changes: Vec<Change>,
+    ) -> Result<(), RNSDNSERRORS> {
+        let mut staging = self.write_staging()?;
+        let apply = |raw: &mut DnsDatabaseRaw, change| self.transfer_change(raw, change);
+        let raw = staging.clone();
+        let action = AuditAction::Transferred;
+        self.apply_batch(&mut staging, raw, action, requester, changes, apply)?;
+        Ok(())
+    }
+
+    /// Applies a transferred change to `raw` and returns it as it is recorded,
+    /// `None` should it remove a name that is not held.
+    fn transfer_change(&self, raw: &mut DnsDatabaseRaw, change: Change) -> Option<Mutation> {
+        let before = raw.entry_store.lookup(&change.name).cloned();
+        if let Some(before) = &before {
+            raw.verification_store.remove_entry(before);
+        }
+        match change.entry {
+            Some(entry) => {
+                let entry = DnsEntry {
+                    verifications: Vec::new(),
+                    ..entry
+                };
+                let registry = &raw.verifier_registry;
+                let entry = self.server_sign(entry, &mut raw.verification_store, registry);
+                raw.entry_store.override_entry(entry.clone());
+                Some(Mutation::change(change.name, before, Some(entry)))
+            }
+            None => {
+                before.as_ref()?;
+                raw.entry_store.remove_domain(&change.name);
+                Some(Mutation::change(change.name, before, None))
+            }
+        }
+    }
+
+    /// Merges the entries and removals a peer replicated into the staging
+    /// database and returns how many of them were applied, see `gossip`.
+    ///
+    /// # Behaviour
+    ///
+    /// An entry or removal is only applied should its version win over the one
+    /// held for the name, see `Version::wins`. As with `apply_changes` the
+    /// entries are signed as the server and their verifications dropped. Every
+    /// change is recorded as `AuditAction::Replicated`.
+    ///
+    /// # Security
+    ///
+    /// Entries have to be signed by their owner, must be allowed by the access
+    /// list and may not expire later than a new entry would. An entry of another
+    /// owner than the held one is rejected, so a peer can not pass a name on to a
+    /// new owner. Removals have to be signed by the owner of the held entry, see
+    /// `registration::verify_removal`, and are dropped for names that are not
+    /// held. The policy is not applied, the peer already did so, which means that
+    /// all peers have to share the same policy and access list to converge.
+    pub fn merge_replicated(
+        &self,
+        requester: &Requester,
+        entries: Vec<DnsEntry>,
+        removals: Vec<(String, Version)>,
+    ) -> Result<usize, RNSDNSERRORS> {
+        let now = Utc::now();
+        let held = |store: &DnsEntryStore, name: &str| {
+            let removed = self
+                .tombstones
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(name)
+                .copied();
+            let entry = store.lookup(name).map(Version::of);
+            match (entry, removed) {
+                (Some(entry), Some(removed)) if removed.wins(&entry) => Some(removed),
+                (Some(entry), _) => Some(entry),
+                (None, removed) => removed,
+            }
+        };
+
+        // the checks that do not depend on what is held are made for the whole
+        // batch before anything is recorded
+        let entries: Vec<DnsEntry> = entries
+            .into_iter()
+            .filter(|entry| {
+                // the expiry is not covered by the owner signature
+                let latest_expiry = now + RECORD_EXPIRY + MAX_GOSSIP_AGE;
+                let owner = entry.owner_identity();
+                let valid = entry.verify_signature()
+                    && entry.expiry <= latest_expiry
+                    && self.check_access(Some(&owner), &entry.name).is_ok();
+                if !valid {
+                    let peer = requester.describe();
+                    log::warn!("rejected replicated entry {} of {peer}", entry.name);
+                }
+                valid
+            })
+            .collect();
+        let removals = removals.into_iter().filter(|(_, removal)| removal.removed);
+
+        let merge = |raw: &mut DnsDatabaseRaw, entry: DnsEntry| {
+            let before = raw.entry_store.lookup(&entry.name).cloned();
+            if before.as_ref().is_some_and(|held| held.verifying_key != entry.verifying_key) {
+                let peer = requester.describe();
+                log::warn!("rejected replicated entry {} of another owner of {peer}", entry.name);
+                return None;
+            }
+            let version = Version::of(&entry);
+            if held(&raw.entry_store, &entry.name).is_some_and(|held| !version.wins(&held)) {
+                return None;
+            }
+            if let Some(before) = &before {
+                raw.verification_store.remove_entry(before);
+            }
+            let entry = DnsEntry {
+                verifications: Vec::new(),
+                ..entry
+            };
+            let registry = &raw.verifier_registry;
+            let entry = self.server_sign(entry, &mut raw.verification_store, registry);
+            raw.entry_store.override_entry(entry.clone());
+            Some(Mutation::change(entry.name.clone(), before, Some(entry)))
+        };
+        let remove = |raw: &mut DnsDatabaseRaw, (name, removal): (String, Version)| {
+            // only the owner of the held entry can have removed it
+            let before = raw.entry_store.lookup(&name)?.clone();
+            if held(&raw.entry_store, &name).is_some_and(|held| !removal.wins(&held)) {
+                return None;
+            }
+            let (key, serial) = (&before.verifying_key, removal.serial);
+            if registration::verify_removal(key, &name, serial, &removal.signature).is_err() {
+                log::warn!("rejected replicated removal {name} of {}", requester.describe());
+                return None;
+            }
+            raw.entry_store.remove_domain(&name);
+            raw.verification_store.remove_entry(&before);
+            // the removal is kept as the peer made it, so that both hold the same
+            Some(Mutation {
+                removal: Some(removal),
+                ..Mutation::change(name, Some(before), None)
+            })
+        };
+
+        let mut staging = self.write_staging()?;
+        let action = AuditAction::Replicated;
+        let raw = staging.clone();
+        let merged = self.apply_batch(&mut staging, raw, action, requester, entries, merge)?;
+        let raw = staging.clone();
+        let removed = self.apply_batch(&mut staging, raw, action, requester, removals, remove)?;
+        Ok(merged + removed)
+    }
+
+    /// Removes a domain from the staging database. `signature` is the signature of
+    /// the owner over the removal of the stored entry, see `registration`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotFound` should there be no entry and
+    /// `RNSDNSERRORS::InvalidSignature` should the removal not be signed by the key
+    /// of the stored entry for its serial.
+    pub fn remove_domain(
+        &self,
+        requester: &Requester,
+        domain: &str,
+        signature: &Signature,
+    ) -> Result<(), RNSDNSERRORS> {
+        self.check_access(requester.identity.as_ref(), domain)?;
+        let mut staging = self.write_staging()?;
+
+        let before = staging
+            .entry_store
+            .lookup(domain)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        registration::verify_removal(&before.verifying_key, domain, before.serial, signature)?;
+        let requester = Requester {
+            signature: Some(*signature),
+            ..*requester
+        };
+        self.record(AuditAction::Remove, domain, &requester, Some(before), None)?;
+        self.remember_removal(domain, Version::removal(before, *signature));
+        self.notify(before.verifications.iter().map(|signing| VerifierNotification {
+            verifier: signing.destination,
+            name: domain.to_owned(),
+            entry: None,
+        }));
+        staging.entry_store.remove_domain(domain);
+        staging.verification_store.remove_domain(domain);
+        Ok(())
+    }
+
+    /// Applies `change` to a copy of the `VerificationStore`, rebuilds the
+    /// verifications of the entry from it and records the change.
+    fn change_verifications<T>(
+        &self,
+        requester: &Requester,
+        name: &str,
+        action: AuditAction,
+        change: impl FnOnce(
+            &DnsEntry,
+            &mut VerificationStore,
+            &VerifierRegistry,
+        ) -> Result<T, RNSDNSERRORS>,
+    ) -> Result<T, RNSDNSERRORS> {
+        let mut staging = self.write_staging()?;
+        let raw = &mut *staging;
+
+        let before = raw
+            .entry_store
+            .lookup(name)
+            .ok_or(RNSDNSERRORS::NotFound)?;
+        let mut verification_store = raw.verification_store.clone();
+        let result = change(before, &mut verification_store, &raw.verifier_registry)?;
+
+        let mut after = before.clone();
+        after.verifications = verification_store.collect_for_entry(&after, &raw.verifier_registry);
+
+        self.record(action, name, requester, Some(before), Some(&after))?;
+        raw.entry_store.override_entry(after);
+        raw.verification_store = verification_store;
+        Ok(result)
+    }
+
+    /// Adds the signing of a verifier to the entry at the given destination.
+    ///
+    /// # Behaviour
+    ///
+    /// The signing is stored in the `VerificationStore` after validation and the
+    /// verifications of the entry are rebuilt from the store.
+    pub fn add_verification(
+        &self,
+        requester: &Requester,
+        name: &str,
+        destination: AddressHash,
+        signing: VerifierSigning,
+    ) -> Result<(), RNSDNSERRORS> {
+        self.change_verifications(
+            requester,
+            name,
+            AuditAction::VerificationAdded,
+            |entry, store, registry| store.add_verification(entry, destination, signing, registry),
+        )
+    }
+
+    /// Removes the signing of a verifier from the entry at the given destination.
+    pub fn remove_verification(
+        &self,
+        requester: &Requester,
+        name: &str,
+        destination: &AddressHash,
+        verifier: &AddressHash,
+    ) -> Result<VerifierSigning, RNSDNSERRORS> {
+        self.change_verifications(
+            requester,
+            name,
+            AuditAction::VerificationRemoved,
+            |_, store, _| store.remove_verification(name, destination, verifier),
+        )
+    }
+
+    /// Adds the signing of a verifier to every destination of the entry.
+    ///
+    /// The signing covers the entry signature and therefore all destinations at
+    /// once, this is what a signing returned by a verifier is attached with.
+    pub fn attach_signing(
+        &self,
+        requester: &Requester,
+        name: &str,
+        signing: VerifierSigning,
+    ) -> Result<(), RNSDNSERRORS> {
+        self.change_verifications(
+            requester,
+            name,
+            AuditAction::VerificationAdded,
+            |entry, store, registry| {
+                for destination in &entry.destinations {
+                    store.add_verification(entry, *destination, signing.clone(), registry)?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Removes the signings of a verifier from every destination of the entry.
+    pub fn withdraw_signing(
+        &self,
+        requester: &Requester,
+        name: &str,
+        verifier: &AddressHash,
+    ) -> Result<(), RNSDNSERRORS> {
+        self.change_verifications(
+            requester,
+            name,
+            AuditAction::VerificationRemoved,
+            |entry, store, _| {
+                let removed = entry
+                    .destinations
+                    .iter()
+                    .filter(|d| store.remove_verification(name, d, verifier).is_ok())
+                    .count();
+                match removed {
+                    0 => Err(RNSDNSERRORS::NotFound),
+                    _ => Ok(()),
+                }
+            },
+        )
+    }
+
+    /// Extends the expiry of an entry by `RECORD_EXPIRY` from now.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotAuthorized` should the requester not have
+    /// identified itself as the owner of the entry or the operator.
+    pub fn renew_entry(&self, requester: &Requester, domain: &str) -> Result<(), RNSDNSERRORS> {
+        self.check_access(requester.identity.as_ref(), domain)?;
+        let mut staging = self.write_staging()?;
+
+        let owner = staging
+            .entry_store
+            .lookup(domain)
+            .ok_or(RNSDNSERRORS::NotFound)?
+            .owner_identity();
+        if requester.identity != Some(owner) && requester.identity != Some(self.operator) {
+            log::warn!("rejected renewal of {domain} by {}", requester.describe());
+            return Err(RNSDNSERRORS::NotAuthorized);
+        }
+
+        let mut entry_store = staging.entry_store.clone();
+        entry_store.renew_entry(domain, Utc::now())?;
+        self.record(
+            AuditAction::Renew,
+            domain,
+            requester,
+            staging.entry_store.lookup(domain),
+            entry_store.lookup(domain),
+        )?;
+        staging.entry_store = entry_store;
+        Ok(())
+    }
+
+    pub fn promote_staging(&self) -> Result<(), String> {
+        let staging_guard = self.staging.write()
+            .map_err(|e| format!("Staging lock poisoned: {}", e))?;
+
+        // clone and wrap
+        let new_active_raw = staging_guard.clone();
+        let new_active_arc = Arc::new(new_active_raw);
+
+        // ignore old value
+        self.active.swap(new_active_arc);
+
+        // every change of the staging database was logged while holding its lock,
+        // so the log matches the new snapshot exactly
+        self.sign_tree_head()?;
+        Ok(())
+    }
+
+    /// Signs the current head of the transparency log, should the server have a
+    /// signing identity. A head is only signed again once the log has grown.
+    fn sign_tree_head(&self) -> Result<(), String> {
+        let Some((server, private_id)) = self.server_signer.get() else {
+            return Ok(());
+        };
+        let transparency = self
+            .transparency
+            .lock()
+            .map_err(|e| format!("Transparency lock poisoned: {}", e))?;
+        let size = transparency.size();
+        if self
+            .tree_head
+            .load()
+            .as_ref()
+            .is_some_and(|head| head.tree_size == size)
+        {
+            return Ok(());
+        }
+        let head = SignedTreeHead::sign(size, transparency.root(size), *server, private_id);
+        self.tree_head.store(Some(Arc::new(head)));
+        Ok(())
+    }
+
+    /// Replaces the ephemeral transparency log, e.g. with one that is backed by a
+    /// file. This has to be done before any mutation is made.
+    pub fn with_transparency_log(mut self, log: TransparencyLog) -> Self {
+        self.transparency = Mutex::new(log);
+        self
+    }
+
+    /// Returns the signed head of the transparency log of the active snapshot.
+    pub fn tree_head(&self) -> Option<Arc<SignedTreeHead>> {
+        self.tree_head.load_full()
+    }
+
+    /// Returns the proof that the entry of the active snapshot is part of the
+    /// transparency log as of the current tree head.
+    pub fn inclusion_proof(&self, entry: &DnsEntry) -> Option<InclusionProof> {
+        let head = self.tree_head()?;
+        self.transparency.lock().ok()?.inclusion_proof(entry, &head)
+    }
+
+    /// Returns the proof that the transparency log of size `second` extends the
+    /// one of size `first`. Only sizes up to the current tree head are answered.
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Option<Vec<[u8; 32]>> {
+        if second > self.tree_head()?.tree_size {
+            return None;
+        }
+        self.transparency.lock().ok()?.consistency_proof(first, second)
+    }
+
+    /// Returns the currently active snapshot of the database.
+    pub fn active(&self) -> Arc<DnsDatabaseRaw> {
+        self.active.load_full()
+    }
+
+    /// Writes the active snapshot to disk.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.active.load().save(path)
+    }
+
+    /// Replaces both the active and the staging database with `raw`.
+    ///
+    /// Should the transparency log still be empty, e.g. because the database
+    /// predates it, every entry is logged once so that proofs can be made for it.
+    pub fn load(&self, raw: DnsDatabaseRaw) -> Result<(), RNSDNSERRORS> {
+        let mut staging = self.write_staging()?;
+        let mut transparency = self
+            .transparency
+            .lock()
+            .map_err(|_| RNSDNSERRORS::AuditFailed)?;
+        if transparency.size() == 0 {
+            let mut entries: Vec<&DnsEntry> = raw
+                .entry_store
+                .iter_forward_index()
+                .map(|(_, entry)| entry)
+                .collect();
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            for entry in entries {
+                transparency
+                    .append(&entry.name, Some(entry))
+                    .map_err(|_| RNSDNSERRORS::AuditFailed)?;
+            }
+        }
+        drop(transparency);
+
+        *staging = raw.clone();
+        self.active.store(Arc::new(raw));
+        Ok(())
+    }
+
+    pub fn get_entry_reader(&self, key: &str) {
+        let active_guard = self.active.load();
+        // TODO: Implement the logic that searches each indes
         // let entry_option = active_guard.entry_store.forward_index.get(key)
     }
 
     
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> DnsDatabase {
+        let signer = PrivateIdentity::new_from_name("server");
+        DnsDatabase::new(AddressHash::new([1u8; 16]), AuditLog::ephemeral(signer))
+    }
+
+    fn entry(name: &str, serial: u64, owner: &PrivateIdentity) -> DnsEntry {
+        DnsEntry::new_signed(name.to_owned(), vec![AddressHash::new([2u8; 16])], serial, owner)
+    }
+
+    /// A database that lets a name be updated right after it was registered.
+    fn open_database() -> DnsDatabase {
+        let signer = PrivateIdentity::new_from_name("server");
+        let policy = EntryPolicy::new(16, chrono::TimeDelta::zero(), 8);
+        let operator = AddressHash::new([1u8; 16]);
+        DnsDatabase::with_policy(operator, policy, AuditLog::ephemeral(signer))
+    }
+
+    fn owner_on_link(owner: &PrivateIdentity) -> Requester {
+        let identity = owner.as_identity().address_hash;
+        Requester::on_link(AddressHash::new([5u8; 16]), Some(identity))
+    }
+
+    fn register(
+        database: &DnsDatabase,
+        owner: &PrivateIdentity,
+        entry: DnsEntry,
+    ) -> Result<(), RNSDNSERRORS> {
+        let requester = owner_on_link(owner);
+        let challenge = database.issue_challenge(&requester.link.unwrap());
+        let proof = owner.sign(&registration::proof_bytes(&challenge, &entry));
+        database.register_entry(&requester, entry, &proof)
+    }
+
+    fn verifier(name: &str, byte: u8, trust_level: u32) -> (PrivateIdentity, Verifier) {
+        let identity = PrivateIdentity::new_from_name(name);
+        let key = identity.as_identity().verifying_key;
+        let destination = AddressHash::new([byte; 16]);
+        let verifier = Verifier::new(name.to_owned(), destination, trust_level, key);
+        (identity, verifier)
+    }
+
+    fn trust_level(database: &DnsDatabase, verifier: &AddressHash) -> Option<u32> {
+        let staging = database.staging.read().unwrap();
+        let verifier = staging.verifier_registry.get_verifier(verifier)?;
+        Some(verifier.trust_level)
+    }
+
+    fn names(database: &DnsDatabase) -> Vec<String> {
+        let mut names: Vec<String> = database
+            .active()
+            .entry_store()
+            .iter_forward_index()
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn transfer_adds_new_names() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let primary = Requester::new(AddressHash::new([3u8; 16]), None);
+        let entries = vec![entry("a.node", 1, &owner), entry("b.node", 1, &owner)];
+        database.replace_entries(&primary, entries).unwrap();
+        database.promote_staging().unwrap();
+        assert_eq!(names(&database), ["a.node", "b.node"]);
+    }
+
+    #[test]
+    fn transfer_drops_names_no_longer_in_the_zone() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let primary = Requester::new(AddressHash::new([3u8; 16]), None);
+        let entries = vec![entry("a.node", 1, &owner), entry("b.node", 1, &owner)];
+        database.replace_entries(&primary, entries).unwrap();
+        database.replace_entries(&primary, vec![entry("b.node", 2, &owner)]).unwrap();
+        database.promote_staging().unwrap();
+        assert_eq!(names(&database), ["b.node"]);
+        let active = database.active();
+        assert_eq!(active.entry_store().lookup("b.node").map(DnsEntry::serial), Some(2));
+    }
+
+    #[test]
+    fn changes_add_and_remove_names() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let primary = Requester::new(AddressHash::new([3u8; 16]), None);
+        database.replace_entries(&primary, vec![entry("a.node", 1, &owner)]).unwrap();
+        let changes = vec![
+            Change {
+                name: "b.node".to_owned(),
+                entry: Some(entry("b.node", 1, &owner)),
+            },
+            Change {
+                name: "a.node".to_owned(),
+                entry: None,
+            },
+            Change {
+                name: "c.node".to_owned(),
+                entry: None,
+            },
+        ];
+        database.apply_changes(&primary, changes).unwrap();
+        database.promote_staging().unwrap();
+        assert_eq!(names(&database), ["b.node"]);
+    }
+
+    #[test]
+    fn batch_that_fails_to_record_is_not_applied() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let primary = Requester::new(AddressHash::new([3u8; 16]), None);
+        database.replace_entries(&primary, vec![entry("a.node", 1, &owner)]).unwrap();
+        let _ = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _audit_log = database.audit_log.lock();
+                    panic!("poisons the audit log");
+                })
+                .join()
+        });
+        let entries = vec![entry("b.node", 1, &owner), entry("c.node", 1, &owner)];
+        let result = database.replace_entries(&primary, entries);
+        assert!(matches!(result, Err(RNSDNSERRORS::AuditFailed)));
+        database.promote_staging().unwrap();
+        assert_eq!(names(&database), ["a.node"]);
+    }
+
+    fn removal(entry: &DnsEntry, owner: &PrivateIdentity) -> (String, Version) {
+        let signature = owner.sign(&registration::removal_bytes(&entry.name, entry.serial));
+        (entry.name.clone(), Version::removal(entry, signature))
+    }
+
+    #[test]
+    fn replicated_entries_are_merged() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let peer = Requester::new(AddressHash::new([4u8; 16]), None);
+        let entries = vec![entry("a.node", 1, &owner), entry("b.node", 1, &owner)];
+        assert_eq!(database.merge_replicated(&peer, entries, Vec::new()), Ok(2));
+        database.promote_staging().unwrap();
+        assert_eq!(names(&database), ["a.node", "b.node"]);
+    }
+
+    #[test]
+    fn replicated_entry_of_another_owner_is_rejected() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let other = PrivateIdentity::new_from_name("other");
+        let peer = Requester::new(AddressHash::new([4u8; 16]), None);
+        let entries = vec![entry("a.node", 1, &owner)];
+        database.merge_replicated(&peer, entries, Vec::new()).unwrap();
+        let entries = vec![entry("a.node", 2, &other)];
+        assert_eq!(database.merge_replicated(&peer, entries, Vec::new()), Ok(0));
+        database.promote_staging().unwrap();
+        let active = database.active();
+        let held = active.entry_store().lookup("a.node").unwrap();
+        assert_eq!(held.verifying_key(), &owner.as_identity().verifying_key);
+    }
+
+    #[test]
+    fn replicated_removal_signed_by_the_owner_is_merged() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let peer = Requester::new(AddressHash::new([4u8; 16]), None);
+        let held = entry("a.node", 1, &owner);
+        database.merge_replicated(&peer, vec![held.clone()], Vec::new()).unwrap();
+        let removals = vec![removal(&held, &owner)];
+        assert_eq!(database.merge_replicated(&peer, Vec::new(), removals), Ok(1));
+        database.promote_staging().unwrap();
+        assert!(names(&database).is_empty());
+        let versions = database.gossip_versions();
+        assert_eq!(versions, [removal(&held, &owner)]);
+    }
+
+    #[test]
+    fn replicated_removal_of_another_key_is_rejected() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let other = PrivateIdentity::new_from_name("other");
+        let peer = Requester::new(AddressHash::new([4u8; 16]), None);
+        let held = entry("a.node", 1, &owner);
+        database.merge_replicated(&peer, vec![held.clone()], Vec::new()).unwrap();
+        let unsigned = (held.name.clone(), Version::removal(&held, held.signature));
+        let removals = vec![removal(&held, &other), unsigned];
+        assert_eq!(database.merge_replicated(&peer, Vec::new(), removals), Ok(0));
+        database.promote_staging().unwrap();
+        assert_eq!(names(&database), ["a.node"]);
+    }
+
+    #[test]
+    fn replicated_removal_of_unheld_name_is_dropped() {
+        let database = database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let peer = Requester::new(AddressHash::new([4u8; 16]), None);
+        let removals = vec![removal(&entry("a.node", 1, &owner), &owner)];
+        assert_eq!(database.merge_replicated(&peer, Vec::new(), removals), Ok(0));
+        assert!(database.gossip_versions().is_empty());
+    }
+
+    #[test]
+    fn reverse_index_follows_registration_update_and_removal() {
+        let database = open_database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let requester = owner_on_link(&owner);
+        let (first, second) = (AddressHash::new([2u8; 16]), AddressHash::new([3u8; 16]));
+        let reverse = |destination: &AddressHash| {
+            let staging = database.staging.read().unwrap();
+            staging.entry_store.reverse_lookup(destination).cloned()
+        };
+
+        register(&database, &owner, entry("a.node", 1, &owner)).unwrap();
+        assert_eq!(reverse(&first), Some(vec!["a.node".to_owned()]));
+
+        let update = DnsEntry::new_signed("a.node".to_owned(), vec![second], 2, &owner);
+        database.override_entry(&requester, update).unwrap();
+        assert_eq!(reverse(&first), None);
+        assert_eq!(reverse(&second), Some(vec!["a.node".to_owned()]));
+
+        let signature = owner.sign(&registration::removal_bytes("a.node", 2));
+        database.remove_domain(&requester, "a.node", &signature).unwrap();
+        assert_eq!(reverse(&second), None);
+        database.promote_staging().unwrap();
+        assert_eq!(database.active().entry_store().iter_reverse_index().count(), 0);
+    }
+
+    #[test]
+    fn integrity_is_only_checked_for_the_operator() {
+        let database = database();
+        let stranger = Requester::new(AddressHash::new([9u8; 16]), None);
+        let operator = Requester::new(*database.operator(), None);
+
+        assert!(matches!(
+            database.check_integrity(&stranger),
+            Err(RNSDNSERRORS::NotAuthorized)
+        ));
+        assert!(database.check_integrity(&operator).unwrap().is_ok());
+    }
+
+    #[test]
+    fn verifier_registry_is_only_changed_by_the_operator() {
+        let database = database();
+        let stranger = Requester::new(AddressHash::new([9u8; 16]), None);
+        let operator = Requester::new(*database.operator(), None);
+        let (_, added) = verifier("verifier", 7, 1);
+        let destination = added.destination;
+
+        let result = database.add_verifier(&stranger, added.clone());
+        assert_eq!(result, Err(RNSDNSERRORS::NotAuthorized));
+        assert_eq!(trust_level(&database, &destination), None);
+        assert_eq!(database.add_verifier(&operator, added), Ok(()));
+        assert_eq!(trust_level(&database, &destination), Some(1));
+
+        let (_, lowered) = verifier("verifier", 7, 2);
+        let result = database.update_verifier(&stranger, lowered.clone());
+        assert_eq!(result, Err(RNSDNSERRORS::NotAuthorized));
+        assert_eq!(trust_level(&database, &destination), Some(1));
+        assert_eq!(database.update_verifier(&operator, lowered), Ok(()));
+        assert_eq!(trust_level(&database, &destination), Some(2));
+
+        let removed = database.remove_verifier(&stranger, &destination);
+        assert_eq!(removed.err(), Some(RNSDNSERRORS::NotAuthorized));
+        let revoked = database.revoke_verifier(&stranger, &destination);
+        assert_eq!(revoked.err(), Some(RNSDNSERRORS::NotAuthorized));
+        assert_eq!(trust_level(&database, &destination), Some(2));
+        assert!(database.remove_verifier(&operator, &destination).is_ok());
+        assert_eq!(trust_level(&database, &destination), None);
+    }
+
+    #[test]
+    fn verification_store_rejects_invalid_signings() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let (identity, verifier) = verifier("verifier", 7, 1);
+        let destination = AddressHash::new([2u8; 16]);
+        let mut registry = VerifierRegistry::default();
+        let mut store = VerificationStore::default();
+        let current = entry("a.node", 2, &owner);
+        let add = |store: &mut VerificationStore, registry: &VerifierRegistry, signing| {
+            store.add_verification(&current, destination, signing, registry)
+        };
+
+        let signing = VerifierSigning::sign(&current, verifier.destination, &identity);
+        let result = add(&mut store, &registry, signing.clone());
+        assert_eq!(result, Err(RNSDNSERRORS::UnknownVerifier));
+        registry.add_verifier(verifier.clone()).unwrap();
+
+        let forger = PrivateIdentity::new_from_name("forger");
+        let forged = VerifierSigning::sign(&current, verifier.destination, &forger);
+        let result = add(&mut store, &registry, forged);
+        assert_eq!(result, Err(RNSDNSERRORS::InvalidSignature));
+
+        let previous = entry("a.node", 1, &owner);
+        let outdated = VerifierSigning::sign(&previous, verifier.destination, &identity);
+        let result = add(&mut store, &registry, outdated);
+        assert_eq!(result, Err(RNSDNSERRORS::InvalidSignature));
+
+        assert_eq!(store.count_verifications("a.node", &destination), 0);
+        assert_eq!(add(&mut store, &registry, signing), Ok(()));
+        assert_eq!(store.count_verifications("a.node", &destination), 1);
+    }
+
+    #[test]
+    fn registry_changes_reach_the_signings_of_existing_entries() {
+        let database = open_database();
+        let operator = Requester::new(*database.operator(), None);
+        let owner = PrivateIdentity::new_from_name("owner");
+        let destination = AddressHash::new([2u8; 16]);
+        register(&database, &owner, entry("a.node", 1, &owner)).unwrap();
+
+        let (first_id, first) = verifier("first", 7, 1);
+        let (second_id, second) = verifier("second", 8, 2);
+        for (identity, verifier) in [(&first_id, &first), (&second_id, &second)] {
+            database.add_verifier(&operator, verifier.clone()).unwrap();
+            let stored = database.staging.read().unwrap().entry_store.lookup("a.node").cloned();
+            let signing = VerifierSigning::sign(&stored.unwrap(), verifier.destination, identity);
+            database
+                .add_verification(&operator, "a.node", destination, signing)
+                .unwrap();
+        }
+        let signers = || -> Vec<AddressHash> {
+            let staging = database.staging.read().unwrap();
+            let entry = staging.entry_store.lookup("a.node").unwrap();
+            entry.verifications().iter().map(|s| *s.destination()).collect()
+        };
+        assert_eq!(signers(), vec![first.destination, second.destination]);
+
+        let (_, lowered) = verifier("first", 7, 3);
+        database.update_verifier(&operator, lowered).unwrap();
+        assert_eq!(signers(), vec![second.destination, first.destination]);
+
+        assert!(database.revoke_verifier(&operator, &second.destination).is_ok());
+        assert_eq!(signers(), vec![first.destination]);
+        let staging = database.staging.read().unwrap();
+        assert_eq!(staging.verification_store.count_verifications("a.node", &destination), 1);
+    }
+
+    #[test]
+    fn update_without_a_higher_serial_is_stale() {
+        let database = open_database();
+        let owner = PrivateIdentity::new_from_name("owner");
+        let requester = owner_on_link(&owner);
+        register(&database, &owner, entry("a.node", 2, &owner)).unwrap();
+
+        for serial in [2, 1] {
+            let result = database.override_entry(&requester, entry("a.node", serial, &owner));
+            assert_eq!(result, Err(RNSDNSERRORS::StaleSerial { current: 2 }));
+        }
+        assert_eq!(database.override_entry(&requester, entry("a.node", 3, &owner)), Ok(()));
+        let result = database.override_entry(&requester, entry("a.node", 3, &owner));
+        assert_eq!(result, Err(RNSDNSERRORS::StaleSerial { current: 3 }));
+    }
+}
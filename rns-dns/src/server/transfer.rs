@@ -0,0 +1,553 @@
+//! Transfer of the complete contents of a zone from a primary to a secondary
+//! server, in the spirit of AXFR.
+//!
+//! The secondary asks for one page after the other with
+//! `TRANSFER <zone> [<after>]`, where `<after>` is the last name of the previous
+//! page, and the primary answers each with `XFR <page>`. Every page is signed by
+//! the zone and names the size of the transparency log of the snapshot it was
+//! taken from. Should the size change during a transfer the zone changed and
+//! the secondary starts over, so it never loads a mix of two snapshots.
+//!
+//! The size of the transparency log under the signed tree head is the serial of
+//! the zone. Once a secondary holds a snapshot it only asks for what changed
+//! since, in the spirit of IXFR, with `CHANGES <zone> <serial>`, which the primary
+//! answers with `IXFR <page>`. Should the primary no longer remember the changes
+//! since that serial it answers with `ERR 23` and the secondary falls back to a
+//! full transfer.
+//!
+//! A zone is only handed out to the configured servers. A server identifies
+//! itself on every link it opens to another server with `IDENTIFY`, and the
+//! identities of the configured servers are learned from their announces.
+
+use std::collections::HashSet;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+
+use crate::server::delegation::is_within;
+use crate::server::server::{DnsEntry, RNSDNSERRORS, Requester};
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+/// The most entries sent in a single page.
+pub const TRANSFER_PAGE_SIZE: usize = 8;
+
+/// The most changes sent in a single page of an incremental transfer.
+pub const CHANGE_PAGE_SIZE: usize = 8;
+
+/// The number of recent changes a database remembers for incremental transfers.
+pub const MAX_HISTORY: usize = 1024;
+
+/// The most entries a secondary accepts in a single transfer.
+const MAX_TRANSFER_ENTRIES: usize = 1 << 20;
+
+/// The identities of the configured servers, learned from their announces.
+///
+/// # Fields
+/// `secondaries` - The secondaries, which may transfer every zone.
+/// `peers` - The peers, which may ask for the records of every zone.
+/// `primary` - The primary of a secondary, which may notify it of changes.
+///
+/// # Security
+///
+/// A transfer hands out every name of a zone at once, which makes it cheap to
+/// copy or enumerate the zone and costs the server far more than a lookup. Only
+/// the servers the operator configured may therefore ask for one, once they
+/// proved their identity on the link. Likewise only the primary may make a
+/// secondary ask for the changes early.
+#[derive(Default)]
+pub struct Servers {
+    pub secondaries: HashSet<AddressHash>,
+    pub peers: HashSet<AddressHash>,
+    pub primary: Option<AddressHash>,
+}
+
+impl Servers {
+    /// Checks that the requester identified itself as a configured secondary.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotAuthorized` otherwise.
+    pub fn require_secondary(&self, requester: &Requester) -> Result<(), RNSDNSERRORS> {
+        Self::require(|identity| self.secondaries.contains(identity), requester, "secondary")
+    }
+
+    /// Checks that the requester identified itself as a configured peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotAuthorized` otherwise.
+    pub fn require_peer(&self, requester: &Requester) -> Result<(), RNSDNSERRORS> {
+        Self::require(|identity| self.peers.contains(identity), requester, "peer")
+    }
+
+    /// Checks that the requester identified itself as the configured primary.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RNSDNSERRORS::NotAuthorized` otherwise.
+    pub fn require_primary(&self, requester: &Requester) -> Result<(), RNSDNSERRORS> {
+        Self::require(|identity| self.primary == Some(*identity), requester, "primary")
+    }
+
+    fn require(
+        allowed: impl Fn(&AddressHash) -> bool,
+        requester: &Requester,
+        role: &str,
+    ) -> Result<(), RNSDNSERRORS> {
+        if !requester.identity.is_some_and(|identity| allowed(&identity)) {
+            log::warn!("refused {} that is no {role}", requester.describe());
+            return Err(RNSDNSERRORS::NotAuthorized);
+        }
+        Ok(())
+    }
+}
+
+/// The name of the zone in a `TRANSFER` request, `.` for the primary zone whose
+/// suffix is empty.
+pub fn zone_argument(zone: &str) -> &str {
+    match zone.is_empty() {
+        true => ".",
+        false => zone,
+    }
+}
+
+/// One page of a zone transfer.
+///
+/// # Fields
+/// `zone` - The suffix of the zone, empty for the primary zone.
+/// `tree_size` - The size of the transparency log of the snapshot the page was
+/// taken from.
+/// `after` - The name the page continues after, `None` for the first page.
+/// `entries` - The entries following `after` in sorted order.
+/// `last` - Whether this is the last page.
+/// `timestamp` - The time at which the page was signed.
+/// `server` - The destination the zone signs as.
+/// `signature` - The signature of the zone over all of the above.
+#[derive(Clone)]
+pub struct TransferPage {
+    pub zone: String,
+    pub tree_size: u64,
+    pub after: Option<String>,
+    pub entries: Vec<DnsEntry>,
+    pub last: bool,
+    pub timestamp: DateTime<Utc>,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
+
+impl TransferPage {
+    pub fn sign(
+        zone: String,
+        tree_size: u64,
+        after: Option<String>,
+        entries: Vec<DnsEntry>,
+        last: bool,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut page = Self {
+            zone,
+            tree_size,
+            after,
+            entries,
+            last,
+            timestamp: Utc::now(),
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        page.signature = private_id.sign(&page.signable_bytes());
+        page
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer.put_str(&self.zone).put_u64(self.tree_size);
+        writer.put_bool(self.after.is_some());
+        if let Some(after) = &self.after {
+            writer.put_str(after);
+        }
+        writer.put_u32(self.entries.len() as u32);
+        for entry in &self.entries {
+            writer.put_bytes(&entry.encode());
+        }
+        writer
+            .put_bool(self.last)
+            .put_time(&self.timestamp)
+            .put_address(&self.server);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    pub fn verify(&self, zone_key: &VerifyingKey) -> bool {
+        zone_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let zone = reader.get_str()?;
+        let tree_size = reader.get_u64()?;
+        let after = match reader.get_bool()? {
+            true => Some(reader.get_str()?),
+            false => None,
+        };
+        let entries = (0..reader.get_u32()?)
+            .map(|_| DnsEntry::decode(&mut Reader::new(reader.get_bytes()?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            zone,
+            tree_size,
+            after,
+            entries,
+            last: reader.get_bool()?,
+            timestamp: reader.get_time()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+/// Decodes the payload of a `XFR` answer.
+pub fn decode_page(payload: &str) -> Option<TransferPage> {
+    let bytes = URL_SAFE_NO_PAD.decode(payload.trim()).ok()?;
+    TransferPage::decode(&mut Reader::new(&bytes)).ok()
+}
+
+/// A change of a single name.
+///
+/// # Fields
+/// `name` - The name that changed.
+/// `entry` - The entry after the change, `None` should the name have been removed.
+#[derive(Clone)]
+pub struct Change {
+    pub name: String,
+    pub entry: Option<DnsEntry>,
+}
+
+/// One page of an incremental transfer.
+///
+/// # Fields
+/// `zone` - The suffix of the zone, empty for the primary zone.
+/// `from` - The serial the changes apply on top of.
+/// `to` - The serial of the zone once the changes are applied, every change
+/// increases the serial by one.
+/// `changes` - The changes in the order they were made.
+/// `last` - Whether `to` is the serial of the current snapshot.
+/// `timestamp` - The time at which the page was signed.
+/// `server` - The destination the zone signs as.
+/// `signature` - The signature of the zone over all of the above.
+#[derive(Clone)]
+pub struct ChangePage {
+    pub zone: String,
+    pub from: u64,
+    pub to: u64,
+    pub changes: Vec<Change>,
+    pub last: bool,
+    pub timestamp: DateTime<Utc>,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
+
+impl ChangePage {
+    pub fn sign(
+        zone: String,
+        from: u64,
+        changes: Vec<Change>,
+        last: bool,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut page = Self {
+            zone,
+            from,
+            to: from + changes.len() as u64,
+            changes,
+            last,
+            timestamp: Utc::now(),
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        page.signature = private_id.sign(&page.signable_bytes());
+        page
+    }
+
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer
+            .put_str(&self.zone)
+            .put_u64(self.from)
+            .put_u64(self.to)
+            .put_u32(self.changes.len() as u32);
+        for change in &self.changes {
+            writer
+                .put_str(&change.name)
+                .put_bool(change.entry.is_some());
+            if let Some(entry) = &change.entry {
+                writer.put_bytes(&entry.encode());
+            }
+        }
+        writer
+            .put_bool(self.last)
+            .put_time(&self.timestamp)
+            .put_address(&self.server);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    pub fn verify(&self, zone_key: &VerifyingKey) -> bool {
+        zone_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    /// Checks that the page is signed by the zone, continues at `serial` and only
+    /// holds changes of names within the zone that are signed by their owners.
+    ///
+    /// # Security
+    ///
+    /// As with a full transfer the primary can hold back changes but it can not
+    /// forge them. Removals carry no signature of the owner, a primary is trusted
+    /// to only pass on removals it made itself.
+    pub fn check(
+        &self,
+        zone: &str,
+        zone_key: &VerifyingKey,
+        serial: u64,
+    ) -> Result<(), TransferError> {
+        if !self.verify(zone_key) {
+            return Err(TransferError::InvalidSignature);
+        }
+        let consecutive = self.to.checked_sub(self.from) == Some(self.changes.len() as u64);
+        if self.zone != zone || self.from != serial || !consecutive {
+            return Err(TransferError::Unexpected);
+        }
+        for change in &self.changes {
+            let valid = change
+                .entry
+                .as_ref()
+                .is_none_or(|entry| entry.name() == change.name && entry.verify_signature());
+            if !valid || !is_within(&change.name, zone) {
+                return Err(TransferError::InvalidEntry(change.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.put_signature(&self.signature);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let zone = reader.get_str()?;
+        let from = reader.get_u64()?;
+        let to = reader.get_u64()?;
+        let changes = (0..reader.get_u32()?)
+            .map(|_| {
+                let name = reader.get_str()?;
+                let entry = match reader.get_bool()? {
+                    true => Some(DnsEntry::decode(&mut Reader::new(reader.get_bytes()?))?),
+                    false => None,
+                };
+                Ok(Change { name, entry })
+            })
+            .collect::<Result<Vec<_>, CodecError>>()?;
+        Ok(Self {
+            zone,
+            from,
+            to,
+            changes,
+            last: reader.get_bool()?,
+            timestamp: reader.get_time()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+/// Decodes the payload of an `IXFR` answer.
+pub fn decode_change_page(payload: &str) -> Option<ChangePage> {
+    let bytes = URL_SAFE_NO_PAD.decode(payload.trim()).ok()?;
+    ChangePage::decode(&mut Reader::new(&bytes)).ok()
+}
+
+/// The request for the changes of `zone` since `serial`.
+pub fn changes_request(zone: &str, serial: u64) -> String {
+    format!("CHANGES {} {serial}", zone_argument(zone))
+}
+
+/// Why a page of a transfer was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    /// The page is not signed by the zone.
+    InvalidSignature,
+    /// The page belongs to another zone or does not follow the previous page.
+    Unexpected,
+    /// The zone changed since the transfer started, it has to start over.
+    Changed,
+    /// An entry is not signed by its owner, lies outside of the zone or is out
+    /// of order.
+    InvalidEntry(String),
+    /// The zone holds more entries than a secondary accepts.
+    TooLarge,
+}
+
+/// A transfer of a zone in progress, kept by the secondary.
+///
+/// # Fields
+/// `zone` - The suffix of the zone.
+/// `zone_key` - The key the primary signs the zone with.
+/// `tree_size` - The size of the log of the snapshot being transferred, known
+/// once the first page arrived.
+/// `entries` - The entries received so far, in sorted order.
+///
+/// # Security
+///
+/// Every entry has to be signed by its owner, so a primary can leave names out
+/// or serve an old snapshot but it can not forge names. The signature of the
+/// zone over every page ties the pages to one snapshot of one zone.
+pub struct ZoneTransfer {
+    zone: String,
+    zone_key: VerifyingKey,
+    tree_size: Option<u64>,
+    entries: Vec<DnsEntry>,
+}
+
+impl ZoneTransfer {
+    pub fn new(zone: String, zone_key: VerifyingKey) -> Self {
+        Self {
+            zone,
+            zone_key,
+            tree_size: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The serial of the snapshot being transferred, known once the first page
+    /// arrived.
+    pub fn tree_size(&self) -> Option<u64> {
+        self.tree_size
+    }
+
+    /// The request for the next page.
+    pub fn request(&self) -> String {
+        let zone = zone_argument(&self.zone);
+        match self.entries.last() {
+            Some(last) => format!("TRANSFER {zone} {}", last.name()),
+            None => format!("TRANSFER {zone}"),
+        }
+    }
+
+    /// Checks a page and adds its entries.
+    ///
+    /// Returns all entries of the zone once the last page arrived and `None`
+    /// while pages are missing.
+    ///
+    /// # Errors
+    ///
+    /// Rejects pages that fail any of the checks. On `TransferError::Changed`
+    /// the transfer is reset and can be continued with `request`.
+    pub fn accept(&mut self, page: TransferPage) -> Result<Option<Vec<DnsEntry>>, TransferError> {
+        if !page.verify(&self.zone_key) {
+            return Err(TransferError::InvalidSignature);
+        }
+        let after = self.entries.last().map(|entry| entry.name());
+        if page.zone != self.zone || page.after.as_deref() != after {
+            return Err(TransferError::Unexpected);
+        }
+        if self.tree_size.is_some_and(|size| size != page.tree_size) {
+            self.tree_size = None;
+            self.entries.clear();
+            return Err(TransferError::Changed);
+        }
+        if self.entries.len() + page.entries.len() > MAX_TRANSFER_ENTRIES {
+            return Err(TransferError::TooLarge);
+        }
+
+        let mut previous = page.after.clone();
+        for entry in &page.entries {
+            let in_order = previous.as_deref().is_none_or(|name| name < entry.name());
+            if !in_order || !is_within(entry.name(), &self.zone) || !entry.verify_signature() {
+                return Err(TransferError::InvalidEntry(entry.name().to_owned()));
+            }
+            previous = Some(entry.name().to_owned());
+        }
+
+        self.tree_size = Some(page.tree_size);
+        self.entries.extend(page.entries);
+        match page.last {
+            true => Ok(Some(std::mem::take(&mut self.entries))),
+            false => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> Servers {
+        Servers {
+            secondaries: HashSet::from([AddressHash::new([1u8; 16])]),
+            peers: HashSet::from([AddressHash::new([2u8; 16])]),
+            primary: Some(AddressHash::new([3u8; 16])),
+        }
+    }
+
+    fn identified(identity: u8) -> Requester {
+        Requester::on_link(AddressHash::new([9u8; 16]), Some(AddressHash::new([identity; 16])))
+    }
+
+    #[test]
+    fn configured_servers_are_allowed() {
+        let servers = servers();
+        assert!(servers.require_secondary(&identified(1)).is_ok());
+        assert!(servers.require_peer(&identified(2)).is_ok());
+        assert!(servers.require_primary(&identified(3)).is_ok());
+    }
+
+    #[test]
+    fn servers_are_only_allowed_their_role() {
+        let servers = servers();
+        assert!(matches!(
+            servers.require_secondary(&identified(2)),
+            Err(RNSDNSERRORS::NotAuthorized)
+        ));
+        assert!(servers.require_peer(&identified(3)).is_err());
+        assert!(servers.require_primary(&identified(1)).is_err());
+    }
+
+    #[test]
+    fn unidentified_requester_is_refused() {
+        let servers = servers();
+        let requester = Requester::on_link(AddressHash::new([1u8; 16]), None);
+        assert!(servers.require_secondary(&requester).is_err());
+        assert!(servers.require_peer(&requester).is_err());
+        assert!(servers.require_primary(&requester).is_err());
+    }
+
+    #[test]
+    fn secondary_without_primary_refuses_notices() {
+        let servers = Servers::default();
+        assert!(servers.require_primary(&identified(3)).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+use sha2::{Digest, Sha256};
+
+use crate::server::server::DnsEntry;
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::CodecError;
+
+pub type Hash = [u8; 32];
+
+// The tree follows RFC 6962. Leaves and inner nodes are hashed with different
+// prefixes so that a leaf can never be passed off as an inner node.
+
+fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Returns the largest power of two smaller than `n`, `n` has to be at least 2.
+fn split(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest(b"").into(),
+        1 => leaves[0],
+        n => {
+            let k = split(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+fn inclusion_path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+    let k = split(leaves.len());
+    let (mut path, sibling) = match index < k {
+        true => (inclusion_path(index, &leaves[..k]), root(&leaves[k..])),
+        false => (inclusion_path(index - k, &leaves[k..]), root(&leaves[..k])),
+    };
+    path.push(sibling);
+    path
+}
+
+fn consistency_path(first: usize, leaves: &[Hash], complete: bool) -> Vec<Hash> {
+    let n = leaves.len();
+    if first == n {
+        return match complete {
+            true => Vec::new(),
+            false => vec![root(leaves)],
+        };
+    }
+    let k = split(n);
+    let (mut path, sibling) = match first <= k {
+        true => (consistency_path(first, &leaves[..k], complete), root(&leaves[k..])),
+        false => (consistency_path(first - k, &leaves[k..], false), root(&leaves[..k])),
+    };
+    path.push(sibling);
+    path
+}
+
+/// Checks that the leaf is part of the tree with the given size and root.
+pub fn verify_inclusion(
+    leaf: &Hash,
+    index: u64,
+    size: u64,
+    path: &[Hash],
+    expected: &Hash,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut f, mut s) = (index, size - 1);
+    let mut r = *leaf;
+    for p in path {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(p, &r);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && r == *expected
+}
+
+/// Checks that the tree `second` is an extension of the tree `first`, i.e. that no
+/// leaf of the first tree was changed or removed.
+pub fn verify_consistency(first: (u64, &Hash), second: (u64, &Hash), path: &[Hash]) -> bool {
+    let ((size1, root1), (size2, root2)) = (first, second);
+    if size1 > size2 {
+        return false;
+    }
+    if size1 == size2 {
+        return path.is_empty() && root1 == root2;
+    }
+    if size1 == 0 {
+        return path.is_empty();
+    }
+
+    let mut path = path.to_vec();
+    if size1.is_power_of_two() {
+        path.insert(0, *root1);
+    }
+    let Some((start, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut f, mut s) = (size1 - 1, size2 - 1);
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+    let (mut fr, mut sr) = (*start, *start);
+    for c in rest {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && fr == *root1 && sr == *root2
+}
+
+/// Returns the leaf that records a change of `name`.
+///
+/// `entry` is the entry after the change or `None` should the name have been
+/// removed at `now`. Only the record digest of the entry is used, changes to the
+/// verifications alone do not produce a new leaf.
+pub fn change_leaf(name: &str, entry: Option<&DnsEntry>, now: DateTime<Utc>) -> Hash {
+    let mut writer = Writer::new();
+    writer.put_str(name).put_bool(entry.is_some());
+    match entry {
+        Some(entry) => writer.put_fixed(&entry.record_digest()),
+        None => writer.put_time(&now),
+    };
+    leaf_hash(&writer.finish())
+}
+
+/// A tree head signed by the dns server.
+///
+/// # Fields
+/// `tree_size` - The amount of leaves in the tree.
+/// `root` - The root hash of the tree.
+/// `timestamp` - The time at which the head was signed.
+/// `server` - The destination of the server.
+/// `signature` - The signature of the server over all of the above.
+///
+/// # Security
+///
+/// A server that shows different records to different clients has to sign two
+/// heads that are not consistent with each other. Clients that compare the heads
+/// they have seen therefore detect the equivocation, and the two signed heads
+/// prove it to anyone else.
+#[derive(Clone)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    pub root: Hash,
+    pub timestamp: DateTime<Utc>,
+    pub server: AddressHash,
+    pub signature: Signature,
+}
+
+impl SignedTreeHead {
+    fn encode_unsigned(&self, writer: &mut Writer) {
+        writer
+            .put_u64(self.tree_size)
+            .put_fixed(&self.root)
+            .put_time(&self.timestamp)
+            .put_address(&self.server);
+    }
+
+    fn signable_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode_unsigned(&mut writer);
+        writer.finish()
+    }
+
+    pub fn sign(
+        tree_size: u64,
+        root: Hash,
+        server: AddressHash,
+        private_id: &PrivateIdentity,
+    ) -> Self {
+        let mut head = Self {
+            tree_size,
+            root,
+            timestamp: Utc::now(),
+            server,
+            signature: Signature::from_bytes(&[0u8; 64]),
+        };
+        head.signature = private_id.sign(&head.signable_bytes());
+        head
+    }
+
+    pub fn verify(&self, server_key: &VerifyingKey) -> bool {
+        server_key
+            .verify_strict(&self.signable_bytes(), &self.signature)
+            .is_ok()
+    }
+
+    pub fn encode_into(&self, writer: &mut Writer) {
+        self.encode_unsigned(writer);
+        writer.put_signature(&self.signature);
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            tree_size: reader.get_u64()?,
+            root: reader.get_array()?,
+            timestamp: reader.get_time()?,
+            server: reader.get_address()?,
+            signature: reader.get_signature()?,
+        })
+    }
+}
+
+/// The proof that the current version of an entry is part of the log.
+#[derive(Clone)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub path: Vec<Hash>,
+    pub head: SignedTreeHead,
+}
+
+impl InclusionProof {
+    /// Checks the signature of the tree head and that the entry is a leaf of it.
+    pub fn verify(&self, entry: &DnsEntry, server_key: &VerifyingKey) -> bool {
+        let leaf = change_leaf(entry.name(), Some(entry), Utc::now());
+        self.head.verify(server_key)
+            && verify_inclusion(
+                &leaf,
+                self.leaf_index,
+                self.head.tree_size,
+                &self.path,
+                &self.head.root,
+            )
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.put_u64(self.leaf_index);
+        encode_hashes(&mut writer, &self.path);
+        self.head.encode_into(&mut writer);
+        writer.finish()
+    }
+
+    pub fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Self {
+            leaf_index: reader.get_u64()?,
+            path: decode_hashes(reader)?,
+            head: SignedTreeHead::decode(reader)?,
+        })
+    }
+}
+
+pub fn encode_hashes(writer: &mut Writer, hashes: &[Hash]) {
+    writer.put_u8(hashes.len() as u8);
+    for hash in hashes {
+        writer.put_fixed(hash);
+    }
+}
+
+pub fn decode_hashes(reader: &mut Reader) -> Result<Vec<Hash>, CodecError> {
+    (0..reader.get_u8()?).map(|_| reader.get_array()).collect()
+}
+
+/// This is the transparency log of the `DnsDatabase`, a Merkle tree with one leaf
+/// per change of a record.
+///
+/// # Fields
+/// `leaves` - The leaf hashes in the order the changes were made.
+/// `first_index` - The position at which each leaf first appeared.
+/// `file` - The file the leaves are appended to. `None` if the log is ephemeral.
+///
+/// # Reasoning
+///
+/// The audit log answers who changed a record, this log lets clients check that
+/// everyone is shown the same records. The file only holds the leaf hashes, one
+/// after another, since everything else can be recomputed from them.
+pub struct TransparencyLog {
+    leaves: Vec<Hash>,
+    first_index: HashMap<Hash, u64>,
+    file: Option<File>,
+}
+
+impl TransparencyLog {
+    pub fn ephemeral() -> Self {
+        Self {
+            leaves: Vec::new(),
+            first_index: HashMap::new(),
+            file: None,
+        }
+    }
+
+    /// Opens the log at `path`, continuing an existing log.
+    ///
+    /// # Errors
+    ///
+    /// Fails should the file be unreadable or not consist of whole leaves.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut log = Self::ephemeral();
+        if path.exists() {
+            let bytes = fs::read(path)?;
+            if bytes.len() % 32 != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "transparency log is truncated",
+                ));
+            }
+            for chunk in bytes.chunks_exact(32) {
+                log.push(chunk.try_into().expect("chunks are 32 bytes long"));
+            }
+        }
+        log.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(log)
+    }
+
+    fn push(&mut self, leaf: Hash) {
+        self.first_index.entry(leaf).or_insert(self.leaves.len() as u64);
+        self.leaves.push(leaf);
+    }
+
+    pub fn size(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    /// Appends the change of `name` to the log.
+    ///
+    /// A change that does not alter the record itself, such as a new verification,
+    /// is not appended again.
+    pub fn append(&mut self, name: &str, entry: Option<&DnsEntry>) -> io::Result<()> {
+        let leaf = change_leaf(name, entry, Utc::now());
+        if self.first_index.contains_key(&leaf) {
+            return Ok(());
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&leaf)?;
+            file.flush()?;
+        }
+        self.push(leaf);
+        Ok(())
+    }
+
+    /// Returns the root of the tree made of the first `size` leaves.
+    pub fn root(&self, size: u64) -> Hash {
+        root(&self.leaves[..size as usize])
+    }
+
+    /// Returns the proof that the entry is part of the tree of the given size.
+    pub fn inclusion_proof(
+        &self,
+        entry: &DnsEntry,
+        head: &SignedTreeHead,
+    ) -> Option<InclusionProof> {
+        let leaf = change_leaf(entry.name(), Some(entry), Utc::now());
+        let index = *self.first_index.get(&leaf)?;
+        if index >= head.tree_size || head.tree_size > self.size() {
+            return None;
+        }
+        Some(InclusionProof {
+            leaf_index: index,
+            path: inclusion_path(index as usize, &self.leaves[..head.tree_size as usize]),
+            head: head.clone(),
+        })
+    }
+
+    /// Returns the proof that the tree of size `second` extends the tree of size
+    /// `first`.
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Option<Vec<Hash>> {
+        if first > second || second > self.size() {
+            return None;
+        }
+        if first == 0 {
+            return Some(Vec::new());
+        }
+        Some(consistency_path(
+            first as usize,
+            &self.leaves[..second as usize],
+            true,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(size: usize) -> Vec<Hash> {
+        (0..size).map(|i| leaf_hash(&i.to_be_bytes())).collect()
+    }
+
+    #[test]
+    fn every_leaf_is_included() {
+        for size in 1..=17 {
+            let leaves = leaves(size);
+            let root = root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let path = inclusion_path(index, &leaves);
+                assert!(verify_inclusion(leaf, index as u64, size as u64, &path, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn inclusion_fails_for_another_leaf_or_index() {
+        let leaves = leaves(7);
+        let root = root(&leaves);
+        let path = inclusion_path(3, &leaves);
+        assert!(!verify_inclusion(&leaves[4], 3, 7, &path, &root));
+        assert!(!verify_inclusion(&leaves[3], 4, 7, &path, &root));
+        assert!(!verify_inclusion(&leaves[3], 3, 4, &path, &root));
+        assert!(!verify_inclusion(&leaves[3], 7, 7, &path, &root));
+    }
+
+    #[test]
+    fn inclusion_fails_for_tampered_path() {
+        let leaves = leaves(7);
+        let root = root(&leaves);
+        let mut path = inclusion_path(3, &leaves);
+        path[1][0] ^= 1;
+        assert!(!verify_inclusion(&leaves[3], 3, 7, &path, &root));
+        path.pop();
+        assert!(!verify_inclusion(&leaves[3], 3, 7, &path, &root));
+    }
+
+    #[test]
+    fn every_tree_is_consistent_with_its_prefixes() {
+        let leaves = leaves(17);
+        for second in 1..=leaves.len() {
+            let root2 = root(&leaves[..second]);
+            for first in 1..=second {
+                let root1 = root(&leaves[..first]);
+                let path = consistency_path(first, &leaves[..second], true);
+                let (first, second) = ((first as u64, &root1), (second as u64, &root2));
+                assert!(verify_consistency(first, second, &path));
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_fails_for_changed_leaf() {
+        let leaves = leaves(11);
+        let mut changed = leaves.clone();
+        changed[2] = leaf_hash(b"changed");
+        let path = consistency_path(5, &changed, true);
+        let first = (5, &root(&leaves[..5]));
+        assert!(!verify_consistency(first, (11, &root(&changed)), &path));
+    }
+
+    #[test]
+    fn consistency_fails_for_shrunk_tree() {
+        let leaves = leaves(8);
+        let first = (8, &root(&leaves));
+        let second = (5, &root(&leaves[..5]));
+        assert!(!verify_consistency(first, second, &[]));
+    }
+
+    fn entry(name: &str, serial: u64, owner: &PrivateIdentity) -> DnsEntry {
+        DnsEntry::new_signed(name.to_owned(), vec![AddressHash::new([2u8; 16])], serial, owner)
+    }
+
+    #[test]
+    fn log_proves_its_entries() {
+        let server = PrivateIdentity::new_from_name("server");
+        let owner = PrivateIdentity::new_from_name("owner");
+        let destination = AddressHash::new([1u8; 16]);
+        let mut log = TransparencyLog::ephemeral();
+        let entries: Vec<DnsEntry> = (1..=5)
+            .map(|serial| entry("a.node", serial, &owner))
+            .collect();
+        for entry in &entries {
+            log.append(entry.name(), Some(entry)).unwrap();
+        }
+        let head = SignedTreeHead::sign(log.size(), log.root(log.size()), destination, &server);
+        let key = server.as_identity().verifying_key;
+        for entry in &entries {
+            let proof = log.inclusion_proof(entry, &head).unwrap();
+            assert!(proof.verify(entry, &key));
+            let decoded = InclusionProof::decode(&mut Reader::new(&proof.encode())).unwrap();
+            assert!(decoded.verify(entry, &key));
+        }
+        let other = PrivateIdentity::new_from_name("other");
+        let proof = log.inclusion_proof(&entries[0], &head).unwrap();
+        assert!(!proof.verify(&entries[0], &other.as_identity().verifying_key));
+        assert!(!proof.verify(&entry("b.node", 1, &owner), &key));
+    }
+
+    #[test]
+    fn log_only_proves_entries_within_the_head() {
+        let server = PrivateIdentity::new_from_name("server");
+        let owner = PrivateIdentity::new_from_name("owner");
+        let mut log = TransparencyLog::ephemeral();
+        let (first, second) = (entry("a.node", 1, &owner), entry("b.node", 1, &owner));
+        log.append(first.name(), Some(&first)).unwrap();
+        let head = SignedTreeHead::sign(1, log.root(1), AddressHash::new([1u8; 16]), &server);
+        log.append(second.name(), Some(&second)).unwrap();
+        assert!(log.inclusion_proof(&first, &head).is_some());
+        assert!(log.inclusion_proof(&second, &head).is_none());
+    }
+
+    #[test]
+    fn log_proves_its_growth() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let mut log = TransparencyLog::ephemeral();
+        for serial in 1..=9 {
+            let entry = entry("a.node", serial, &owner);
+            log.append(entry.name(), Some(&entry)).unwrap();
+        }
+        for first in 0..=9 {
+            let path = log.consistency_proof(first, 9).unwrap();
+            let (root1, root2) = (log.root(first), log.root(9));
+            assert!(verify_consistency((first, &root1), (9, &root2), &path));
+        }
+        assert!(log.consistency_proof(5, 10).is_none());
+        assert!(log.consistency_proof(6, 5).is_none());
+    }
+
+    #[test]
+    fn unchanged_record_is_not_appended_again() {
+        let owner = PrivateIdentity::new_from_name("owner");
+        let mut log = TransparencyLog::ephemeral();
+        let entry = entry("a.node", 1, &owner);
+        log.append(entry.name(), Some(&entry)).unwrap();
+        log.append(entry.name(), Some(&entry)).unwrap();
+        assert_eq!(log.size(), 1);
+    }
+}
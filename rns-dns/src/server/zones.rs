@@ -0,0 +1,265 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::TimeDelta;
+use reticulum::hash::AddressHash;
+
+use crate::server::delegation::is_within;
+use crate::server::policy::EntryPolicy;
+use crate::server::server::DnsDatabase;
+use crate::types::{self, ZoneConfig};
+
+/// Parses a line of the zones file,
+/// `<suffix> <identity> <database> [chain=<file>] [acl=<file>] [max-names=<n>]
+/// [min-update-minutes=<n>] [max-destinations=<n>]`.
+///
+/// The identity is the hex encoded private identity the zone signs with. Settings
+/// that are left out take the defaults of the server.
+pub fn parse_zone(line: &str) -> Result<ZoneConfig, String> {
+    let mut parts = line.split_whitespace();
+    let suffix = parts.next().ok_or("missing suffix")?;
+    let identity = parts.next().ok_or("missing identity")?;
+    let database = parts.next().ok_or("missing database")?;
+    if suffix.is_empty() || suffix.starts_with('.') || suffix.ends_with('.') {
+        return Err(format!("invalid suffix {suffix}"));
+    }
+
+    let mut zone = ZoneConfig {
+        suffix: suffix.to_owned(),
+        identity: Some(types::PrivateIdentity::FromHexString(identity.to_owned())),
+        database_path: Some(PathBuf::from(database)),
+        policy: EntryPolicy::default(),
+        chain_path: None,
+        acl_path: None,
+    };
+    for option in parts {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or(format!("invalid option {option}"))?;
+        let number = || {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("invalid value of {key}"))
+        };
+        match key {
+            "chain" => zone.chain_path = Some(PathBuf::from(value)),
+            "acl" => zone.acl_path = Some(PathBuf::from(value)),
+            "max-names" => zone.policy.max_names_per_key = number()? as usize,
+            "min-update-minutes" => {
+                zone.policy.min_update_interval = TimeDelta::minutes(number()? as i64)
+            }
+            "max-destinations" => zone.policy.max_destinations = number()? as usize,
+            _ => return Err(format!("unknown option {key}")),
+        }
+    }
+    Ok(zone)
+}
+
+/// Reads the zones from a file with one zone per line. Empty lines and lines
+/// starting with `#` are ignored.
+pub fn load_zones(path: &Path) -> io::Result<Vec<ZoneConfig>> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| {
+            parse_zone(line).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", n + 1))
+            })
+        })
+        .collect()
+}
+
+/// A suffix the server is authoritative for.
+///
+/// # Fields
+/// `suffix` - The names the zone holds, unless a zone with a longer suffix holds
+/// them.
+/// `server` - The destination the zone signs its records as.
+/// `database` - The partition of the database holding the names of the zone.
+/// `database_path` - The file the partition is persisted to.
+pub struct Zone {
+    pub suffix: String,
+    pub server: AddressHash,
+    pub database: DnsDatabase,
+    pub database_path: Option<PathBuf>,
+}
+
+/// The zones of a server.
+///
+/// # Reasoning
+///
+/// A name belongs to the zone with the longest suffix it lies within, so that a
+/// zone can be hosted inside another one. The primary zone has the empty suffix
+/// and therefore holds every name no other zone holds, which is how the server
+/// behaved before it had zones.
+///
+/// # Security
+///
+/// Every zone has its own signing identity, policy and access list. A change
+/// made in one zone can never touch the names of another since every request is
+/// handled by exactly one partition.
+pub struct Zones {
+    zones: Vec<Zone>,
+}
+
+impl Zones {
+    pub fn new(primary: Zone) -> Self {
+        Self {
+            zones: vec![primary],
+        }
+    }
+
+    /// Adds a zone.
+    ///
+    /// # Errors
+    ///
+    /// Fails should another zone already have the suffix.
+    pub fn add(&mut self, zone: Zone) -> Result<(), String> {
+        if self.zones.iter().any(|other| other.suffix == zone.suffix) {
+            return Err(format!("zone {} is configured twice", zone.suffix));
+        }
+        self.zones.push(zone);
+        Ok(())
+    }
+
+    /// The zone without a suffix, which signs with the identity of the server.
+    pub fn primary(&self) -> &Zone {
+        &self.zones[0]
+    }
+
+    /// Returns the zone with exactly this suffix.
+    pub fn get(&self, suffix: &str) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.suffix == suffix)
+    }
+
+    /// Returns the zone `name` belongs to.
+    pub fn route(&self, name: &str) -> &Zone {
+        self.zones
+            .iter()
+            .filter(|zone| is_within(name, &zone.suffix))
+            .max_by_key(|zone| zone.suffix.len())
+            .unwrap_or(self.primary())
+    }
+
+    /// Whether the server answers a lookup of `name` itself rather than
+    /// forwarding it in resolver mode.
+    ///
+    /// A server with a chain is authoritative for the suffixes delegated to it.
+    /// Without one it can not prove to be authoritative for any suffix, so it only
+    /// answers for the names it holds.
+    pub fn is_authoritative(&self, name: &str) -> bool {
+        let suffixes = self.suffixes();
+        match suffixes.is_empty() {
+            true => {
+                let active = self.route(name).database.active();
+                active.entry_store().lookup(name).is_some()
+            }
+            false => suffixes.iter().any(|suffix| is_within(name, suffix)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Zone> {
+        self.zones.iter()
+    }
+
+    /// The suffixes the server is authoritative for, as announced.
+    ///
+    /// Without a chain that delegates a suffix to it the primary zone holds every
+    /// name, which is announced as no suffixes at all.
+    pub fn suffixes(&self) -> Vec<String> {
+        let Some(primary) = self
+            .primary()
+            .database
+            .trust_chain()
+            .and_then(|chain| chain.delegations().last())
+        else {
+            return Vec::new();
+        };
+        std::iter::once(primary.suffix().to_owned())
+            .chain(self.zones[1..].iter().map(|zone| zone.suffix.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reticulum::identity::PrivateIdentity;
+
+    use crate::server::audit::AuditLog;
+
+    use super::*;
+
+    fn zone(suffix: &str) -> Zone {
+        let signer = PrivateIdentity::new_from_name(suffix);
+        let operator = signer.as_identity().address_hash;
+        Zone {
+            suffix: suffix.to_owned(),
+            server: operator,
+            database: DnsDatabase::new(operator, AuditLog::ephemeral(signer)),
+            database_path: None,
+        }
+    }
+
+    fn zones(suffixes: &[&str]) -> Zones {
+        let mut zones = Zones::new(zone(""));
+        for suffix in suffixes {
+            zones.add(zone(suffix)).unwrap();
+        }
+        zones
+    }
+
+    #[test]
+    fn name_is_routed_to_the_longest_suffix() {
+        let zones = zones(&["node", "lab.node"]);
+        assert_eq!(zones.route("a.lab.node").suffix, "lab.node");
+        assert_eq!(zones.route("a.node").suffix, "node");
+    }
+
+    #[test]
+    fn suffix_itself_belongs_to_its_zone() {
+        let zones = zones(&["node", "lab.node"]);
+        assert_eq!(zones.route("lab.node").suffix, "lab.node");
+        assert_eq!(zones.route("node").suffix, "node");
+    }
+
+    #[test]
+    fn other_names_belong_to_the_primary_zone() {
+        let zones = zones(&["node"]);
+        assert_eq!(zones.route("a.other").suffix, "");
+        assert_eq!(zones.route("othernode").suffix, "");
+    }
+
+    #[test]
+    fn suffix_only_matches_at_a_label_boundary() {
+        let zones = zones(&["lab.node"]);
+        assert_eq!(zones.route("alab.node").suffix, "");
+        assert_eq!(zones.route("a.alab.node").suffix, "");
+    }
+
+    #[test]
+    fn order_of_the_zones_does_not_matter() {
+        let zones = zones(&["lab.node", "node"]);
+        assert_eq!(zones.route("a.lab.node").suffix, "lab.node");
+        assert_eq!(zones.route("a.node").suffix, "node");
+    }
+
+    #[test]
+    fn suffix_can_only_be_configured_once() {
+        let mut zones = zones(&["node"]);
+        assert!(zones.add(zone("node")).is_err());
+        assert!(zones.get("node").is_some());
+    }
+
+    #[test]
+    fn zone_lines_are_parsed() {
+        let zone = parse_zone("lab.node 00ff lab.db acl=lab.acl max-names=3").unwrap();
+        assert_eq!(zone.suffix, "lab.node");
+        assert_eq!(zone.acl_path, Some(PathBuf::from("lab.acl")));
+        assert_eq!(zone.policy.max_names_per_key, 3);
+        assert!(parse_zone(".node 00ff lab.db").is_err());
+        assert!(parse_zone("node 00ff lab.db colour=red").is_err());
+    }
+}
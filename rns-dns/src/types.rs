@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 use std::default;
+use std::path::PathBuf;
 
 use chrono::DateTime;
 use chrono::Utc;
 
-use ed25519_dalek::Signature;
+use ed25519_dalek::{Signature, VerifyingKey};
 use rand_core::OsRng;
 use reticulum::destination::Destination;
 use reticulum::hash::AddressHash;
@@ -13,6 +14,11 @@ use x25519_dalek::PublicKey;
 
 use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 
+use crate::server::announce::AnnounceConfig;
+use crate::server::delegation::TrustAnchor;
+use crate::server::policy::EntryPolicy;
+use crate::server::ratelimit::RateLimitConfig;
+
 pub const RECORD_EXPIRY: chrono::TimeDelta = chrono::Duration::days(365);
 
 #[derive(Debug, Clone)]
@@ -141,6 +147,181 @@ impl NodeSettings {
     }
 }
 
+/// The settings that are specific to the dns server.
+///
+/// `database_path` - The file the database is loaded from and persisted to. The
+/// audit log is kept next to it. Should this be `None` then nothing is persisted.
+/// `policy` - The policy applied to every mutation of the database.
+/// `rate_limit` - The limits applied to incoming queries.
+/// `announce` - How often the server announces itself.
+/// `zones_path` - The file listing the zones hosted next to the primary one.
+/// `secondary` - The zone this server transfers from a primary server, should it
+/// be a secondary.
+/// `secondaries` - The secondary servers that may transfer the zones and are told
+/// right away when a zone changed.
+/// `peers` - The servers that accept registrations for the same zones and whose
+/// entries are replicated by gossip, see `server::gossip`.
+/// `resolver` - Where lookups the server is not authoritative for are forwarded
+/// to, `None` should the server only answer from its own zones.
+///
+/// The database, policy, chain and access list are those of the primary zone,
+/// which holds every name no other zone is authoritative for.
+pub struct ServerConfig {
+    pub database_path: Option<PathBuf>,
+    pub policy: EntryPolicy,
+    /// The file holding the delegations from a root down to this server.
+    pub chain_path: Option<PathBuf>,
+    pub rate_limit: RateLimitConfig,
+    /// The file holding the rules which identities may change which names.
+    pub acl_path: Option<PathBuf>,
+    pub announce: AnnounceConfig,
+    pub zones_path: Option<PathBuf>,
+    pub secondary: Option<SecondaryConfig>,
+    pub secondaries: Vec<AddressHash>,
+    pub peers: Vec<AddressHash>,
+    pub resolver: Option<ResolverConfig>,
+}
+
+impl ServerConfig {
+    pub fn new(database_path: Option<PathBuf>) -> Self {
+        Self {
+            database_path,
+            policy: EntryPolicy::default(),
+            chain_path: None,
+            rate_limit: RateLimitConfig::default(),
+            acl_path: None,
+            announce: AnnounceConfig::default(),
+            zones_path: None,
+            secondary: None,
+            secondaries: Vec::new(),
+            peers: Vec::new(),
+            resolver: None,
+        }
+    }
+
+    pub fn with_resolver(mut self, resolver: Option<ResolverConfig>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    pub fn with_peers(mut self, peers: Vec<AddressHash>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    pub fn with_secondaries(mut self, secondaries: Vec<AddressHash>) -> Self {
+        self.secondaries = secondaries;
+        self
+    }
+
+    pub fn with_secondary(mut self, secondary: Option<SecondaryConfig>) -> Self {
+        self.secondary = secondary;
+        self
+    }
+
+    pub fn with_zones(mut self, zones_path: Option<PathBuf>) -> Self {
+        self.zones_path = zones_path;
+        self
+    }
+
+    pub fn with_announce(mut self, announce: AnnounceConfig) -> Self {
+        self.announce = announce;
+        self
+    }
+
+    pub fn with_acl(mut self, acl_path: Option<PathBuf>) -> Self {
+        self.acl_path = acl_path;
+        self
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: EntryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_chain(mut self, chain_path: Option<PathBuf>) -> Self {
+        self.chain_path = chain_path;
+        self
+    }
+
+    /// The primary zone, which signs with the identity of the server itself.
+    pub fn primary_zone(&self) -> ZoneConfig {
+        ZoneConfig {
+            suffix: String::new(),
+            identity: None,
+            database_path: self.database_path.clone(),
+            policy: self.policy.clone(),
+            chain_path: self.chain_path.clone(),
+            acl_path: self.acl_path.clone(),
+        }
+    }
+}
+
+/// The zone a secondary server transfers from its primary server.
+///
+/// `primary` - The destination of the primary server.
+/// `zone_key` - The key the primary signs the zone with.
+/// `zone` - The suffix of the zone, empty for the primary zone. The secondary
+/// loads it into its own zone with the same suffix.
+pub struct SecondaryConfig {
+    pub primary: AddressHash,
+    pub zone_key: VerifyingKey,
+    pub zone: String,
+}
+
+/// The resolver mode of a server.
+///
+/// `upstreams` - The servers lookups are forwarded to should no server that
+/// announced the suffix of the name be known.
+/// `anchors` - The root keys the answers of the upstream servers are validated
+/// against. Without anchors only the signatures of the zones are checked.
+pub struct ResolverConfig {
+    pub upstreams: Vec<AddressHash>,
+    pub anchors: Vec<TrustAnchor>,
+}
+
+/// A suffix the server is authoritative for, with its own partition of the
+/// database.
+///
+/// `suffix` - The names the zone holds, unless a zone with a longer suffix holds
+/// them.
+/// `identity` - The identity the zone signs with. `None` for the identity of the
+/// server itself.
+/// `database_path` - The file the zone is loaded from and persisted to. Its audit
+/// and transparency logs are kept next to it.
+/// `policy` - The policy applied to every mutation of the zone.
+/// `chain_path` - The file holding the delegations from a root down to the zone.
+/// `acl_path` - The file holding the access list of the zone.
+pub struct ZoneConfig {
+    pub suffix: String,
+    pub identity: Option<PrivateIdentity>,
+    pub database_path: Option<PathBuf>,
+    pub policy: EntryPolicy,
+    pub chain_path: Option<PathBuf>,
+    pub acl_path: Option<PathBuf>,
+}
+
+impl ZoneConfig {
+    /// The audit log lives next to the database file.
+    pub fn audit_log_path(&self) -> Option<PathBuf> {
+        self.database_path
+            .as_ref()
+            .map(|path| path.with_extension("audit"))
+    }
+
+    /// The transparency log lives next to the database file.
+    pub fn transparency_log_path(&self) -> Option<PathBuf> {
+        self.database_path
+            .as_ref()
+            .map(|path| path.with_extension("merkle"))
+    }
+}
+
 pub fn generate_node_url(
     version: &u16,
     address_hash: &Vec<AddressHash>,
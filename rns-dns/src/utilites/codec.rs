@@ -0,0 +1,279 @@
+//! A minimal binary codec used for signing, digests, persistence and the wire
+//! format. Every variable length field is prefixed with its length so that two
+//! different values can never encode to the same bytes.
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::Signature;
+use reticulum::hash::{ADDRESS_HASH_SIZE, AddressHash};
+
+use crate::utilites::error::CodecError;
+
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    pub fn put_u16(&mut self, value: u16) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn put_u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn put_u64(&mut self, value: u64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn put_i64(&mut self, value: i64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// Writes the slice as is without a length prefix.
+    pub fn put_fixed(&mut self, value: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    /// Writes the slice with a `u32` length prefix.
+    pub fn put_bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.put_u32(value.len() as u32);
+        self.put_fixed(value)
+    }
+
+    pub fn put_str(&mut self, value: &str) -> &mut Self {
+        self.put_bytes(value.as_bytes())
+    }
+
+    pub fn put_bool(&mut self, value: bool) -> &mut Self {
+        self.put_u8(value as u8)
+    }
+
+    /// Timestamps are stored as milliseconds since the unix epoch.
+    pub fn put_time(&mut self, value: &DateTime<Utc>) -> &mut Self {
+        self.put_i64(value.timestamp_millis())
+    }
+
+    pub fn put_address(&mut self, value: &AddressHash) -> &mut Self {
+        self.put_fixed(value.as_slice())
+    }
+
+    pub fn put_signature(&mut self, value: &Signature) -> &mut Self {
+        self.put_fixed(&value.to_bytes())
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn get_fixed(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+        if self.buf.len() < len {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn get_array<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.get_fixed(N)?);
+        Ok(out)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.get_array::<1>()?[0])
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, CodecError> {
+        Ok(u16::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, CodecError> {
+        Ok(u32::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, CodecError> {
+        Ok(u64::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_i64(&mut self) -> Result<i64, CodecError> {
+        Ok(i64::from_be_bytes(self.get_array()?))
+    }
+
+    pub fn get_bytes(&mut self) -> Result<&'a [u8], CodecError> {
+        let len = self.get_u32()? as usize;
+        self.get_fixed(len)
+    }
+
+    pub fn get_str(&mut self) -> Result<String, CodecError> {
+        let bytes = self.get_bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CodecError::InvalidData)
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, CodecError> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(CodecError::InvalidData),
+        }
+    }
+
+    pub fn get_time(&mut self) -> Result<DateTime<Utc>, CodecError> {
+        DateTime::from_timestamp_millis(self.get_i64()?).ok_or(CodecError::InvalidData)
+    }
+
+    pub fn get_address(&mut self) -> Result<AddressHash, CodecError> {
+        Ok(AddressHash::new(self.get_array::<ADDRESS_HASH_SIZE>()?))
+    }
+
+    pub fn get_signature(&mut self) -> Result<Signature, CodecError> {
+        Ok(Signature::from_bytes(&self.get_array()?))
+    }
+}
+
+/// Returns the lower case hex representation of the bytes.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parses a hex string, returning `None` should it contain anything but hex
+/// digit pairs.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses the hex representation of an address hash. Surrounding slashes as
+/// printed by reticulum are accepted.
+pub fn parse_address(hex: &str) -> Option<AddressHash> {
+    let bytes = from_hex(hex.trim_matches('/'))?;
+    Some(AddressHash::new(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_survive_encoding() {
+        let time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let address = AddressHash::new([7u8; ADDRESS_HASH_SIZE]);
+        let signature = Signature::from_bytes(&[9u8; 64]);
+        let mut writer = Writer::new();
+        writer
+            .put_u8(1)
+            .put_u16(2)
+            .put_u32(3)
+            .put_u64(u64::MAX)
+            .put_i64(-5)
+            .put_bytes(&[1, 2, 3])
+            .put_str("a.node")
+            .put_bool(true)
+            .put_time(&time)
+            .put_address(&address)
+            .put_signature(&signature);
+        let bytes = writer.finish();
+
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(reader.get_u8(), Ok(1));
+        assert_eq!(reader.get_u16(), Ok(2));
+        assert_eq!(reader.get_u32(), Ok(3));
+        assert_eq!(reader.get_u64(), Ok(u64::MAX));
+        assert_eq!(reader.get_i64(), Ok(-5));
+        assert_eq!(reader.get_bytes(), Ok(&[1u8, 2, 3][..]));
+        assert_eq!(reader.get_str(), Ok("a.node".to_owned()));
+        assert_eq!(reader.get_bool(), Ok(true));
+        assert_eq!(reader.get_time(), Ok(time));
+        assert_eq!(reader.get_address(), Ok(address));
+        assert_eq!(reader.get_signature(), Ok(signature));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let mut writer = Writer::new();
+        writer.put_str("a.node");
+        let bytes = writer.finish();
+        let mut reader = Reader::new(&bytes[..bytes.len() - 1]);
+        assert_eq!(reader.get_str(), Err(CodecError::UnexpectedEnd));
+        assert_eq!(Reader::new(&[0, 0]).get_u32(), Err(CodecError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert_eq!(Reader::new(&[2]).get_bool(), Err(CodecError::InvalidData));
+        let mut writer = Writer::new();
+        writer.put_bytes(&[0xff, 0xfe]);
+        assert_eq!(Reader::new(&writer.finish()).get_str(), Err(CodecError::InvalidData));
+    }
+
+    #[test]
+    fn length_prefix_keeps_values_apart() {
+        let encode = |parts: &[&str]| {
+            let mut writer = Writer::new();
+            for part in parts {
+                writer.put_str(part);
+            }
+            writer.finish()
+        };
+        assert_ne!(encode(&["ab", "c"]), encode(&["a", "bc"]));
+    }
+
+    #[test]
+    fn hex_survives_encoding() {
+        let bytes = [0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(to_hex(&bytes), "007fabff");
+        assert_eq!(from_hex("007fabff"), Some(bytes.to_vec()));
+        assert_eq!(from_hex("007FABFF"), Some(bytes.to_vec()));
+    }
+
+    #[test]
+    fn invalid_hex_is_rejected() {
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("é1"), None);
+    }
+
+    #[test]
+    fn address_is_parsed_with_slashes() {
+        let address = AddressHash::new([0xab; ADDRESS_HASH_SIZE]);
+        let hex = to_hex(address.as_slice());
+        assert_eq!(parse_address(&hex), Some(address));
+        assert_eq!(parse_address(&format!("/{hex}/")), Some(address));
+        assert_eq!(parse_address(&hex[2..]), None);
+    }
+}
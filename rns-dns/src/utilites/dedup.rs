@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// How long a request is remembered to recognise copies of it.
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+/// The most keys remembered at once.
+const MAX_KEYS: usize = 4096;
+
+/// Remembers the keys seen within a short window.
+///
+/// # Reasoning
+///
+/// With several interfaces or paths between two nodes every packet can arrive
+/// more than once. Answering each copy multiplies the traffic on every hop and
+/// quickly floods the network, so only the first copy is acted upon.
+pub struct DuplicateFilter<K> {
+    seen: HashMap<K, Instant>,
+    window: Duration,
+}
+
+impl<K: Eq + Hash> Default for DuplicateFilter<K> {
+    fn default() -> Self {
+        Self {
+            seen: HashMap::new(),
+            window: DEDUP_WINDOW,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> DuplicateFilter<K> {
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Whether the key was seen within the window, without recording it.
+    pub fn contains(&self, key: &K, now: Instant) -> bool {
+        self.seen
+            .get(key)
+            .is_some_and(|seen_at| now.saturating_duration_since(*seen_at) < self.window)
+    }
+
+    /// Records the key and returns whether it is the first time it was seen
+    /// within the window.
+    ///
+    /// # Behaviour
+    ///
+    /// At most `MAX_KEYS` keys are remembered. Once that many are held the
+    /// expired keys are dropped, and should all of them still be within the
+    /// window the oldest one is evicted to make room for the new key.
+    pub fn first_seen(&mut self, key: K, now: Instant) -> bool {
+        if self.contains(&key, now) {
+            return false;
+        }
+        if !self.seen.contains_key(&key) && self.seen.len() >= MAX_KEYS {
+            self.make_room(now);
+        }
+        self.seen.insert(key, now);
+        true
+    }
+
+    fn make_room(&mut self, now: Instant) {
+        let window = self.window;
+        self.seen
+            .retain(|_, seen_at| now.saturating_duration_since(*seen_at) < window);
+        if self.seen.len() < MAX_KEYS {
+            return;
+        }
+        let oldest = self
+            .seen
+            .iter()
+            .min_by_key(|(_, seen_at)| **seen_at)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            self.seen.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_within_the_window_is_a_duplicate() {
+        let mut filter = DuplicateFilter::default();
+        let now = Instant::now();
+        assert!(filter.first_seen(1, now));
+        assert!(!filter.first_seen(1, now + Duration::from_secs(9)));
+    }
+
+    #[test]
+    fn other_keys_are_not_duplicates() {
+        let mut filter = DuplicateFilter::default();
+        let now = Instant::now();
+        assert!(filter.first_seen(1, now));
+        assert!(filter.first_seen(2, now));
+    }
+
+    #[test]
+    fn key_is_forgotten_after_the_window() {
+        let mut filter = DuplicateFilter::default().with_window(Duration::from_secs(2));
+        let now = Instant::now();
+        assert!(filter.first_seen(1, now));
+        assert!(filter.first_seen(1, now + Duration::from_secs(2)));
+        assert!(!filter.first_seen(1, now + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn duplicate_does_not_extend_the_window() {
+        let mut filter = DuplicateFilter::default().with_window(Duration::from_secs(2));
+        let now = Instant::now();
+        assert!(filter.first_seen(1, now));
+        assert!(!filter.first_seen(1, now + Duration::from_secs(1)));
+        assert!(filter.first_seen(1, now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn expired_keys_are_dropped_once_full() {
+        let mut filter = DuplicateFilter::default().with_window(Duration::from_secs(1));
+        let now = Instant::now();
+        for key in 0..MAX_KEYS {
+            filter.first_seen(key, now);
+        }
+        let later = now + Duration::from_secs(1);
+        assert!(filter.first_seen(MAX_KEYS, later));
+        assert_eq!(filter.seen.len(), 1);
+    }
+
+    #[test]
+    fn oldest_key_is_evicted_once_full() {
+        let mut filter = DuplicateFilter::default();
+        let now = Instant::now();
+        let at = |key: usize| now + Duration::from_millis(key as u64);
+        for key in 0..MAX_KEYS {
+            assert!(filter.first_seen(key, at(key)));
+        }
+        assert!(filter.first_seen(MAX_KEYS, at(MAX_KEYS)));
+        assert_eq!(filter.seen.len(), MAX_KEYS);
+        assert!(!filter.seen.contains_key(&0));
+        assert!(!filter.first_seen(1, at(MAX_KEYS)));
+        assert!(!filter.first_seen(MAX_KEYS, at(MAX_KEYS)));
+    }
+}
@@ -1,6 +1,44 @@
+use crate::server::server::RNSDNSERRORS;
+
 pub enum RequestError {
     FailedToParse,
     UnknownCommand,
+    /// The requester sent more queries than the rate limit allows.
+    RateLimited,
+    /// The query was already forwarded by `MAX_HOPS` resolvers.
+    TooManyHops,
+    /// No upstream server is known that the query could be forwarded to.
+    NoUpstream,
+    /// The answer of the upstream server did not validate.
+    InvalidUpstreamAnswer,
+    /// The request was understood but rejected by the database.
+    Rejected(RNSDNSERRORS),
+}
+
+impl RequestError {
+    /// Returns the error code which is sent back to the requester.
+    pub fn code(&self) -> u8 {
+        match self {
+            RequestError::FailedToParse => 1,
+            RequestError::UnknownCommand => 2,
+            RequestError::RateLimited => 3,
+            RequestError::TooManyHops => 4,
+            RequestError::NoUpstream => 5,
+            RequestError::InvalidUpstreamAnswer => 6,
+            RequestError::Rejected(e) => e.code(),
+        }
+    }
+
+    /// Returns the `ERR` response for this error. A stale serial also carries the
+    /// current serial of the entry.
+    pub fn response(&self) -> String {
+        match self {
+            RequestError::Rejected(RNSDNSERRORS::StaleSerial { current }) => {
+                format!("ERR {} {current}", self.code())
+            }
+            _ => format!("ERR {}", self.code()),
+        }
+    }
 }
 
 impl From<Option<&str>> for RequestError {
@@ -8,3 +46,17 @@ impl From<Option<&str>> for RequestError {
         RequestError::FailedToParse
     }
 }
+
+impl From<RNSDNSERRORS> for RequestError {
+    fn from(value: RNSDNSERRORS) -> Self {
+        RequestError::Rejected(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecError {
+    /// The input ended before the value was complete.
+    UnexpectedEnd,
+    /// The input contained a value that could not be decoded.
+    InvalidData,
+}
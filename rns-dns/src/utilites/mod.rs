@@ -1,2 +1,5 @@
+pub mod codec;
+pub mod dedup;
 pub mod error;
+pub mod shutdown;
 pub mod url_parsing;
@@ -0,0 +1,159 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time;
+
+/// How long in-flight work may take once a shutdown was requested.
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+/// The signal that requested the shutdown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+/// Waits for SIGINT or SIGTERM. Should the handlers not be installable only
+/// Ctrl-C is waited for.
+pub async fn signal() -> Signal {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = tokio::signal::ctrl_c() => Signal::Interrupt,
+                _ = terminate.recv() => Signal::Terminate,
+            };
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    Signal::Interrupt
+}
+
+/// How a node ended, determines the exit code of the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// A signal was received and all state was flushed.
+    Clean,
+    /// The state could not be persisted.
+    FlushFailed,
+    /// In-flight work was abandoned because it did not finish before the
+    /// deadline. The state was still flushed.
+    DeadlineExceeded,
+    /// The node stopped without a signal, e.g. because it failed to start or
+    /// the transport went away.
+    Unexpected,
+}
+
+impl ExitStatus {
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Clean => 0,
+            ExitStatus::FlushFailed => 1,
+            ExitStatus::DeadlineExceeded => 2,
+            ExitStatus::Unexpected => 3,
+        }
+    }
+
+    /// This status unless it is clean, then the other one.
+    pub fn worse(self, other: ExitStatus) -> ExitStatus {
+        match self {
+            ExitStatus::Clean => other,
+            _ => self,
+        }
+    }
+}
+
+/// Coordinates the shutdown of a node.
+///
+/// # Behaviour
+///
+/// Work is registered with `begin` and counts as in-flight until the returned
+/// guard is dropped. Once a signal arrived `begin` refuses new work, and `drain`
+/// waits for the work that is still in flight.
+#[derive(Default)]
+pub struct Shutdown {
+    stopping: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+impl Shutdown {
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Waits for a signal and stops accepting new work.
+    pub async fn requested(&self) -> Signal {
+        let signal = signal().await;
+        self.stopping.store(true, Ordering::SeqCst);
+        signal
+    }
+
+    /// Registers a piece of work, `None` once the node is shutting down.
+    pub fn begin(&self) -> Option<InFlight<'_>> {
+        // counted first so that `drain` can not miss work that starts while the
+        // signal arrives
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlight { shutdown: self };
+        match self.is_stopping() {
+            true => None,
+            false => Some(guard),
+        }
+    }
+
+    /// Waits until no work is in flight anymore and returns whether that
+    /// happened before the deadline.
+    pub async fn drain(&self, deadline: Duration) -> bool {
+        let drained = time::timeout(deadline, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.in_flight.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok();
+        if !drained {
+            let abandoned = self.in_flight.load(Ordering::SeqCst);
+            log::warn!("abandoned {abandoned} requests at the shutdown deadline");
+        }
+        drained
+    }
+}
+
+/// Marks a piece of work as in flight until it is dropped.
+pub struct InFlight<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown.idle.notify_waiters();
+        }
+    }
+}
+
+/// Waits until `done` holds, checking it every `interval`, and returns whether
+/// it did before the deadline.
+pub async fn poll_until<F>(
+    deadline: Duration,
+    interval: Duration,
+    mut done: impl FnMut() -> F,
+) -> bool
+where
+    F: Future<Output = bool>,
+{
+    time::timeout(deadline, async {
+        while !done().await {
+            time::sleep(interval).await;
+        }
+    })
+    .await
+    .is_ok()
+}
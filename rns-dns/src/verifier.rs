@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use reticulum::iface::tcp_client::TcpClient;
+use tokio::time;
+
+use reticulum::destination::DestinationName;
+use reticulum::destination::link::LinkEvent;
+use reticulum::hash::AddressHash;
+use reticulum::identity::PrivateIdentity;
+use reticulum::iface::udp::UdpInterface;
+use reticulum::transport::{Transport, TransportConfig};
+
+use crate::server::parser::select_request;
+use crate::server::server::{DnsEntry, RNSDNSERRORS, VerifierSigning};
+use crate::types::{self, Connection};
+use crate::utilites::codec::{Reader, Writer};
+use crate::utilites::error::RequestError;
+
+// The endorsement protocol
+//
+// owner    -> verifier : ENDORSE <entry>
+// verifier -> owner    : SIGNING <name> <signing>
+// owner    -> server   : ATTACH <name> <signing>
+// server   -> verifier : CHANGED <entry> | REMOVED <name>
+// verifier -> server   : SIGNING <name> <signing> | WITHDRAW <name>
+//
+// Entries and signings are sent base64 encoded.
+
+pub fn encode_entry(entry: &DnsEntry) -> String {
+    URL_SAFE_NO_PAD.encode(entry.encode())
+}
+
+pub fn decode_entry(encoded: &str) -> Option<DnsEntry> {
+    let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+    DnsEntry::decode(&mut Reader::new(&bytes)).ok()
+}
+
+pub fn encode_signing(signing: &VerifierSigning) -> String {
+    let mut writer = Writer::new();
+    signing.encode_into(&mut writer);
+    URL_SAFE_NO_PAD.encode(writer.finish())
+}
+
+pub fn decode_signing(encoded: &str) -> Option<VerifierSigning> {
+    let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+    VerifierSigning::decode(&mut Reader::new(&bytes)).ok()
+}
+
+/// Builds the request a record owner sends to a verifier.
+pub fn endorse_request(entry: &DnsEntry) -> String {
+    format!("ENDORSE {}", encode_entry(entry))
+}
+
+/// The checks a verifier runs before it vouches for an entry.
+///
+/// # Reasoning
+///
+/// What a verifier checks is entirely up to the verifier, one may check a list of
+/// known organisations while another may require a manual review. The protocol
+/// only requires a yes or no. The owner signature of the entry is always checked
+/// before the hook is asked.
+pub trait ApprovalHook: Send + Sync {
+    /// Decides whether the verifier vouches for the entry.
+    fn approve(&self, entry: &DnsEntry) -> bool;
+
+    /// Decides whether the verifier keeps vouching for an entry that changed.
+    /// Returning `false` withdraws the signing.
+    fn on_changed(&self, entry: &DnsEntry) -> bool {
+        self.approve(entry)
+    }
+
+    /// Called when an entry the verifier vouched for was removed.
+    fn on_removed(&self, _name: &str) {}
+}
+
+/// A hook that vouches for every correctly signed entry. Only useful for testing.
+pub struct ApproveAll;
+
+impl ApprovalHook for ApproveAll {
+    fn approve(&self, _entry: &DnsEntry) -> bool {
+        true
+    }
+}
+
+/// Handles a single request made to the verifier and returns the response, should
+/// there be one.
+pub fn handle_request(
+    request: &str,
+    destination: AddressHash,
+    private_id: &PrivateIdentity,
+    hook: &dyn ApprovalHook,
+) -> Option<String> {
+    let parsed = match select_request(request) {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(format!("ERR {}", e.code())),
+    };
+
+    match (parsed.command, parsed.args.as_slice()) {
+        (command @ ("ENDORSE" | "CHANGED"), [entry]) => {
+            let Some(entry) = decode_entry(entry) else {
+                return Some(format!("ERR {}", RequestError::FailedToParse.code()));
+            };
+            if !entry.verify_signature() {
+                return Some(format!("ERR {}", RNSDNSERRORS::InvalidSignature.code()));
+            }
+
+            let approved = match command {
+                "ENDORSE" => hook.approve(&entry),
+                _ => hook.on_changed(&entry),
+            };
+            if approved {
+                let signing = VerifierSigning::sign(&entry, destination, private_id);
+                log::info!("vouching for {}", entry.name());
+                Some(format!("SIGNING {} {}", entry.name(), encode_signing(&signing)))
+            } else if command == "CHANGED" {
+                log::info!("withdrawing from {}", entry.name());
+                Some(format!("WITHDRAW {}", entry.name()))
+            } else {
+                log::info!("refused to vouch for {}", entry.name());
+                Some(format!("ERR {}", RNSDNSERRORS::NotAuthorized.code()))
+            }
+        }
+        ("REMOVED", [name]) => {
+            hook.on_removed(name);
+            None
+        }
+        _ => Some(format!("ERR {}", RequestError::UnknownCommand.code())),
+    }
+}
+
+/// Starts a verifier which vouches for dns entries on request.
+pub async fn start_verifier(
+    node_settings: types::NodeSettings,
+    destination_settings: types::DestinationConfig,
+    hook: Arc<dyn ApprovalHook>,
+) {
+    log::info!("Starting RNS-DNS Verifier");
+
+    let private_id = node_settings.private_identity.extract();
+
+    let mut transport = Transport::new(TransportConfig::new("verifier", &private_id, false));
+
+    let mut address_hash: AddressHash = AddressHash::new_empty();
+
+    // add each interface to the node
+    for i in &node_settings.interfaces {
+        match i {
+            Connection::Tcp {
+                local_host,
+                local_port,
+            } => {
+                address_hash = transport.iface_manager().lock().await.spawn(
+                    TcpClient::new(&format!("{local_host}:{local_port}")),
+                    TcpClient::spawn,
+                );
+            }
+            Connection::Udp {
+                local_host,
+                local_port,
+                remote_host,
+                remote_port,
+            } => {
+                address_hash = transport.iface_manager().lock().await.spawn(
+                    UdpInterface::new(
+                        format!("{local_host}:{local_port}"),
+                        Some(format!("{remote_host}:{remote_port}")),
+                    ),
+                    UdpInterface::spawn,
+                );
+            }
+            unsupported => {
+                // the verifier has to keep running on its other interfaces
+                log::error!("unsupported interface {}", unsupported.to_string());
+                continue;
+            }
+        };
+        log::info!("New Node address registered: {}", address_hash);
+    }
+
+    let destination = transport
+        .add_destination(
+            private_id.clone(),
+            DestinationName::new(
+                &destination_settings.app_name,
+                &destination_settings.application_space,
+            ),
+        )
+        .await;
+
+    // this is the destination the dns server has to register for this verifier
+    let destination_hash = destination.lock().await.desc.address_hash;
+    log::info!(
+        "Verifier destination {destination_hash} with key {}",
+        URL_SAFE_NO_PAD.encode(private_id.as_identity().verifying_key.as_bytes())
+    );
+
+    let announce_loop = async || loop {
+        log::trace!("SEND ANNOUNCE {}", destination_hash);
+        transport.send_announce(&destination, None).await;
+        time::sleep(time::Duration::from_secs(15)).await;
+    };
+
+    let in_event_loop = async || {
+        let mut in_link_events = transport.in_link_events();
+        while let Ok(link_event) = in_link_events.recv().await {
+            match link_event.event {
+                LinkEvent::Data(payload) => {
+                    let Ok(payload) = str::from_utf8(payload.as_slice()) else {
+                        continue;
+                    };
+                    let Some(response) =
+                        handle_request(payload, destination_hash, &private_id, hook.as_ref())
+                    else {
+                        continue;
+                    };
+
+                    let Some(link) = transport.find_in_link(&link_event.id).await else {
+                        continue;
+                    };
+                    let link = link.lock().await;
+                    let packet = link.data_packet(response.as_bytes());
+                    drop(link);
+                    if let Ok(packet) = packet {
+                        transport.send_packet(packet).await;
+                    }
+                }
+                LinkEvent::Activated => {
+                    log::trace!(
+                        "IN LINK ACTIVATED {} ({})",
+                        link_event.address_hash,
+                        link_event.id
+                    )
+                }
+                LinkEvent::Closed => {
+                    log::trace!(
+                        "IN LINK CLOSED {} ({})",
+                        link_event.address_hash,
+                        link_event.id
+                    )
+                }
+            }
+        }
+        log::info!("IN LINK LOOP EXIT")
+    };
+
+    tokio::select! {
+      _ = announce_loop() => log::info!("announce loop exited"),
+      _ = in_event_loop() => log::info!("in event loop exited"),
+    }
+}